@@ -0,0 +1,84 @@
+//! Times the tree-walking vm on recursion and loops of growing size; the
+//! time per call or iteration should stay flat as the size grows.
+//!
+//! Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use ti_lang::driver::driver::{TiDriver, TiSource};
+
+const FIB: &str = "
+fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+fn main() { fib({n}) }
+";
+
+const DEPTH: &str = "
+fn depth(n) { if n < 1 { 0 } else { depth(n - 1) + 1 } }
+fn main() { depth({n}) }
+";
+
+const LOOP: &str = "
+fn main() {
+    let i = 0
+    let s = 0
+    while i < {n} {
+        let t = i * 2
+        s = s + t
+        i = i + 1
+    }
+    s
+}
+";
+
+/// Runs `script` with `n` in place of `{n}`, the best of a few runs.
+fn time(script: &str, n: u64) -> Duration {
+    let src = script.replace("{n}", &n.to_string());
+    let source = TiSource::new("<bench>".to_string(), src);
+    let driver = TiDriver::new(false);
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            if let Err(errs) = driver.run(&source, "main", Vec::new()) {
+                driver.report(&source, &errs);
+                panic!("the benchmark script failed");
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn fib_calls(n: u64) -> u64 {
+    let (mut a, mut b) = (1, 1);
+    for _ in 0..n {
+        (a, b) = (b, a + b + 1);
+    }
+    a
+}
+
+fn report(name: &str, unit: &str, sizes: &[u64], script: &str, count: fn(u64) -> u64) {
+    println!("{}", name);
+    for n in sizes {
+        let elapsed = time(script, *n);
+        let units = count(*n);
+        println!(
+            "  n = {:>6}  {:>10.2?}  {:>8.0} ns/{}",
+            n,
+            elapsed,
+            elapsed.as_nanos() as f64 / units as f64,
+            unit
+        );
+    }
+}
+
+fn main() {
+    report("fib(n)", "call", &[14, 16, 18, 20], FIB, fib_calls);
+    report("depth(n)", "call", &[100, 200, 400, 800], DEPTH, |n| n + 1);
+    report(
+        "while",
+        "iteration",
+        &[10_000, 20_000, 40_000, 80_000],
+        LOOP,
+        |n| n,
+    );
+}
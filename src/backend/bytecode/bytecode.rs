@@ -1,7 +1,20 @@
+use std::rc::Rc;
+
+use crate::{
+  build_ti_error,
+  error::error::TiError,
+  frontend::{
+    lexer::token::TokenAt,
+    parser::ast::{EnumDef, FnDef, StructDef, TraitDef},
+  },
+};
+
+/// The opcodes of a `TiByteCode`, each followed by the number of `u16`
+/// operands given by `ti_bytecode::operands`.
 pub mod ti_bytecode {
-  pub const LOAD_CONST: u16   = 0;
-  pub const LOAD_SESSION: u16 = 1;
-  pub const BUILD_FUNC: u16   = 2;
+  pub const LOAD_CONST: u16   = 0;  // const
+  pub const LOAD_SESSION: u16 = 1;  // name
+  pub const BUILD_FUNC: u16   = 2;  // const
   pub const BIN_PRINT: u16    = 3;
   pub const ADD: u16          = 4;
   pub const SUB: u16          = 5;
@@ -17,20 +30,201 @@ pub mod ti_bytecode {
   pub const GEQ: u16          = 15;
   pub const EQ: u16           = 16;
   pub const NEQ: u16          = 17;
-  pub const JMP: u16          = 18;
-  pub const JNZ: u16          = 19;
-  pub const JZ: u16           = 20;
+  pub const JMP: u16          = 18; // target
+  pub const JNZ: u16          = 19; // target
+  pub const JZ: u16           = 20; // target
+  pub const POP: u16          = 21;
+  pub const LOAD_LOCAL: u16   = 22; // slot
+  pub const STORE_LOCAL: u16  = 23; // slot
+  pub const STORE_SESSION: u16 = 24; // name
+  pub const DEFINE_SESSION: u16 = 25; // name
+  pub const CALL: u16         = 26; // argc
+  pub const RETURN: u16       = 27;
+  pub const NEG: u16          = 28;
+  pub const BUILD_LIST: u16   = 29; // len
+  pub const BUILD_STRUCT: u16 = 30; // name, len
+  pub const GET_FIELD: u16    = 31; // name
+  pub const SET_FIELD: u16    = 32; // name
+  pub const LOAD_PATH: u16    = 33; // name, item
+  pub const CALL_PATH: u16    = 34; // name, item, argc
+  pub const CALL_METHOD: u16  = 35; // name, argc
+  pub const IS_VARIANT: u16   = 36; // name, variant
+  pub const GET_PAYLOAD: u16  = 37; // index
+  pub const IS_EQ: u16        = 38;
+  pub const MATCH_FAIL: u16   = 39;
+  pub const DEFINE_TYPE: u16  = 40; // const
+  pub const DEFINE_TRAIT: u16 = 41; // const, len
+  pub const IMPL: u16         = 42; // name, trait, len
+  pub const LOAD_UPVALUE: u16 = 43; // upvalue
+  pub const STORE_UPVALUE: u16 = 44; // upvalue
+  pub const CLOSE_UPVALUES: u16 = 45; // slot
+  pub const BUILD_RANGE: u16  = 46; // inclusive
+  pub const ITER: u16         = 47;
+  pub const ITER_NEXT: u16    = 48; // slot
+  pub const POW: u16          = 49;
+  pub const BIT_AND: u16      = 50;
+  pub const BIT_OR: u16       = 51;
+  pub const BIT_XOR: u16      = 52;
+  pub const SHL: u16          = 53;
+  pub const SHR: u16          = 54;
+  pub const GET_INDEX: u16    = 55;
+  pub const TRY: u16          = 56;
+  pub const SET_INDEX: u16    = 57;
+  pub const BUILD_MAP: u16    = 58; // len
   pub const EXIT: u16           = 65535;
+
+  /// the trait operand of an inherent `IMPL`
+  pub const NO_NAME: u16 = u16::MAX;
+
+  /// The number of operands following `op`, `None` for unknown opcodes.
+  pub fn operands(op: u16) -> Option<usize> {
+    match op {
+      BIN_PRINT | ADD..=NEQ | POP | RETURN | NEG | IS_EQ | MATCH_FAIL | ITER | POW..=SET_INDEX | EXIT => Some(0),
+      LOAD_CONST | LOAD_SESSION | BUILD_FUNC | JMP | JNZ | JZ | LOAD_LOCAL | STORE_LOCAL
+      | STORE_SESSION | DEFINE_SESSION | CALL | BUILD_LIST | GET_FIELD | SET_FIELD
+      | GET_PAYLOAD | DEFINE_TYPE | LOAD_UPVALUE | STORE_UPVALUE | CLOSE_UPVALUES | BUILD_RANGE
+      | ITER_NEXT | BUILD_MAP => Some(1),
+      BUILD_STRUCT | LOAD_PATH | CALL_METHOD | IS_VARIANT | DEFINE_TRAIT => Some(2),
+      CALL_PATH | IMPL => Some(3),
+      _ => None,
+    }
+  }
+
+  /// The number of values an instruction pops and pushes.
+  pub fn effect(op: u16, args: &[u16]) -> (usize, usize) {
+    let n = args.last().copied().unwrap_or(0) as usize;
+    match op {
+      LOAD_CONST | LOAD_SESSION | BUILD_FUNC | LOAD_LOCAL | LOAD_UPVALUE | LOAD_PATH => (0, 1),
+      NOT | NEG | GET_FIELD | IS_VARIANT | GET_PAYLOAD | STORE_LOCAL | STORE_UPVALUE | STORE_SESSION | ITER => (1, 1),
+      ADD..=NEQ | SET_FIELD | IS_EQ | POW..=GET_INDEX => (2, 1),
+      BIN_PRINT | JNZ | JZ | POP | DEFINE_SESSION | RETURN | MATCH_FAIL | EXIT => (1, 0),
+      CALL => (n + 1, 1),
+      BUILD_LIST | CALL_PATH => (n, 1),
+      BUILD_STRUCT | BUILD_MAP => (n * 2, 1),
+      CALL_METHOD => (n + 1, 2),
+      DEFINE_TRAIT | IMPL => (n, 0),
+      BUILD_RANGE => (3, 1),
+      // the next value, and whether there was one
+      ITER_NEXT => (0, 2),
+      // the value to go on with or to return, and whether to go on
+      TRY => (1, 2),
+      // the list or map with its item at the index set to the value
+      SET_INDEX => (3, 1),
+      _ => (0, 0),
+    }
+  }
+
+  pub fn mnemonic(op: u16) -> &'static str {
+    match op {
+      LOAD_CONST => "LOAD_CONST",
+      LOAD_SESSION => "LOAD_SESSION",
+      BUILD_FUNC => "BUILD_FUNC",
+      BIN_PRINT => "BIN_PRINT",
+      ADD => "ADD",
+      SUB => "SUB",
+      MUL => "MUL",
+      DIV => "DIV",
+      MOD => "MOD",
+      AND => "AND",
+      OR => "OR",
+      NOT => "NOT",
+      LES => "LES",
+      GRT => "GRT",
+      LEQ => "LEQ",
+      GEQ => "GEQ",
+      EQ => "EQ",
+      NEQ => "NEQ",
+      JMP => "JMP",
+      JNZ => "JNZ",
+      JZ => "JZ",
+      POP => "POP",
+      LOAD_LOCAL => "LOAD_LOCAL",
+      STORE_LOCAL => "STORE_LOCAL",
+      STORE_SESSION => "STORE_SESSION",
+      DEFINE_SESSION => "DEFINE_SESSION",
+      CALL => "CALL",
+      RETURN => "RETURN",
+      NEG => "NEG",
+      BUILD_LIST => "BUILD_LIST",
+      BUILD_STRUCT => "BUILD_STRUCT",
+      GET_FIELD => "GET_FIELD",
+      SET_FIELD => "SET_FIELD",
+      LOAD_PATH => "LOAD_PATH",
+      CALL_PATH => "CALL_PATH",
+      CALL_METHOD => "CALL_METHOD",
+      IS_VARIANT => "IS_VARIANT",
+      GET_PAYLOAD => "GET_PAYLOAD",
+      IS_EQ => "IS_EQ",
+      MATCH_FAIL => "MATCH_FAIL",
+      DEFINE_TYPE => "DEFINE_TYPE",
+      DEFINE_TRAIT => "DEFINE_TRAIT",
+      IMPL => "IMPL",
+      LOAD_UPVALUE => "LOAD_UPVALUE",
+      STORE_UPVALUE => "STORE_UPVALUE",
+      CLOSE_UPVALUES => "CLOSE_UPVALUES",
+      BUILD_RANGE => "BUILD_RANGE",
+      ITER => "ITER",
+      ITER_NEXT => "ITER_NEXT",
+      POW => "POW",
+      BIT_AND => "BIT_AND",
+      BIT_OR => "BIT_OR",
+      BIT_XOR => "BIT_XOR",
+      SHL => "SHL",
+      SHR => "SHR",
+      GET_INDEX => "GET_INDEX",
+      TRY => "TRY",
+      SET_INDEX => "SET_INDEX",
+      BUILD_MAP => "BUILD_MAP",
+      EXIT => "EXIT",
+      _ => "UNKNOWN",
+    }
+  }
+}
+
+/// An entry of the constant pool of a `TiByteCode`.
+#[derive(Debug, Clone)]
+pub enum TiConst {
+  Num(f64),
+  Str(String),
+  Bool(bool),
+  Fn(Rc<TiFnProto>),
+  Struct(StructDef),
+  Enum(EnumDef),
+  /// the signatures of a trait, its default bodies are compiled to `Fn`s
+  Trait(TraitDef),
+}
+
+/// Where `BUILD_FUNC` finds a variable the new function captures, in the
+/// function running it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiCapture {
+  Local(u16),
+  Upvalue(u16),
 }
 
-pub struct TiByteCode<'a> {
-  bytecode: &'a [u16],
+/// A compiled function: `BUILD_FUNC` turns it into a callable `TiObj`.
+#[derive(Debug)]
+pub struct TiFnProto {
+  pub def: FnDef,
+  /// the number of local slots, the parameters included
+  pub locals: usize,
+  /// the variables of enclosing functions it uses, by upvalue index
+  pub upvalues: Vec<TiCapture>,
+  pub bytecode: TiByteCode,
+}
+
+pub struct TiByteCode {
+  bytecode: Vec<u16>,
+  consts: Vec<TiConst>,
+  /// the span of the statement each run of instructions was compiled from,
+  /// by offset of its first instruction
+  spans: Vec<(usize, TokenAt)>,
   curr: usize,
 }
 
-impl<'a> TiByteCode<'a> {
-  pub fn new(bytecode: &'a [u16]) -> Self {
-    Self { bytecode, curr: 0,  }
+impl TiByteCode {
+  pub fn new(bytecode: Vec<u16>, consts: Vec<TiConst>, spans: Vec<(usize, TokenAt)>) -> Self {
+    Self { bytecode, consts, spans, curr: 0,  }
   }
   pub fn has(&self) -> bool {
     self.curr < self.bytecode.len()
@@ -38,17 +232,135 @@ impl<'a> TiByteCode<'a> {
   pub fn next(&mut self, l: usize) {
     self.curr += l;
   }
-  pub fn at(&self) -> u16 {
-    *self.bytecode.get(self.curr).unwrap()
+  pub fn at(&self) -> Option<u16> {
+    self.bytecode.get(self.curr).copied()
   }
-}
-
-impl<'a> std::fmt::Debug for TiByteCode<'a> {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{:?}", self.bytecode)
+  pub fn code(&self) -> &[u16] {
+    &self.bytecode
+  }
+  pub fn consts(&self) -> &[TiConst] {
+    &self.consts
+  }
+  pub fn spans(&self) -> &[(usize, TokenAt)] {
+    &self.spans
+  }
+  /// The span of the statement the instruction at `offset` belongs to.
+  pub fn span_at(&self, offset: usize) -> Option<TokenAt> {
+    let idx = self.spans.partition_point(|(start, _)| *start <= offset);
+    idx.checked_sub(1).map(|idx| self.spans[idx].1)
   }
 }
 
+/// Assembles a `TiByteCode`, deduplicating constants and patching the
+/// targets of forward jumps once they are known.
+#[derive(Default)]
 pub struct TiByteCodeBuilder {
+  bytecode: Vec<u16>,
+  consts: Vec<TiConst>,
+  spans: Vec<(usize, TokenAt)>,
+  /// the number of values on the stack after the last instruction, when
+  /// it runs
+  stack: usize,
+}
+
+impl TiByteCodeBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
 
-}
\ No newline at end of file
+  /// offset of the next instruction
+  pub fn len(&self) -> usize {
+    self.bytecode.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.bytecode.is_empty()
+  }
+
+  pub fn emit(&mut self, op: u16, operands: &[u16]) {
+    let (pops, pushes) = ti_bytecode::effect(op, operands);
+    self.stack = self.stack.saturating_sub(pops) + pushes;
+    self.bytecode.push(op);
+    self.bytecode.extend_from_slice(operands);
+  }
+
+  /// The number of values on the stack at the next instruction.
+  pub fn stack(&self) -> usize {
+    self.stack
+  }
+
+  /// Sets the number of values on the stack at the next instruction, which
+  /// only jumps reach when the last one was a jump.
+  pub fn set_stack(&mut self, stack: usize) {
+    self.stack = stack;
+  }
+
+  pub fn add_const(&mut self, value: TiConst) -> Result<u16, TiError> {
+    let found = self.consts.iter().position(|c| match (c, &value) {
+      (TiConst::Num(x), TiConst::Num(y)) => x.to_bits() == y.to_bits(),
+      (TiConst::Str(x), TiConst::Str(y)) => x == y,
+      (TiConst::Bool(x), TiConst::Bool(y)) => x == y,
+      _ => false,
+    });
+    let idx = match found {
+      Some(idx) => idx,
+      None => {
+        self.consts.push(value);
+        self.consts.len() - 1
+      }
+    };
+    match u16::try_from(idx) {
+      Ok(idx) => Ok(idx),
+      Err(_) => build_ti_error!(@err Compile, "too many constants in one function, the limit is {}.", u16::MAX),
+    }
+  }
+
+  pub fn add_name(&mut self, name: &str) -> Result<u16, TiError> {
+    self.add_const(TiConst::Str(name.to_string()))
+  }
+
+  /// Emits a jump with a placeholder target, returns the operand to patch.
+  pub fn emit_jump(&mut self, op: u16) -> usize {
+    self.emit(op, &[u16::MAX]);
+    self.bytecode.len() - 1
+  }
+
+  /// Points the jump operand at `operand` to the next instruction.
+  pub fn patch_jump(&mut self, operand: usize) -> Result<(), TiError> {
+    let target = self.target(self.bytecode.len())?;
+    self.bytecode[operand] = target;
+    Ok(())
+  }
+
+  /// Emits a jump back to `offset`.
+  pub fn emit_loop(&mut self, op: u16, offset: usize) -> Result<(), TiError> {
+    let target = self.target(offset)?;
+    self.emit(op, &[target]);
+    Ok(())
+  }
+
+  fn target(&self, offset: usize) -> Result<u16, TiError> {
+    match u16::try_from(offset) {
+      Ok(target) if target != u16::MAX => Ok(target),
+      _ => build_ti_error!(@err Compile, "function too large, jumps are limited to {} instructions.", u16::MAX - 1),
+    }
+  }
+
+  /// The span the instructions emitted from now on are marked with.
+  pub fn span(&self) -> Option<TokenAt> {
+    self.spans.last().map(|(_, at)| *at)
+  }
+
+  /// Marks the instructions emitted from now on as compiled from `at`.
+  pub fn mark(&mut self, at: TokenAt) {
+    match self.spans.last_mut() {
+      Some((start, last)) if *start == self.bytecode.len() => *last = at,
+      Some((_, last)) if *last == at => {}
+      _ => self.spans.push((self.bytecode.len(), at)),
+    }
+  }
+
+  pub fn build(self) -> TiByteCode {
+    TiByteCode::new(self.bytecode, self.consts, self.spans)
+  }
+}
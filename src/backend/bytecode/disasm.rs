@@ -0,0 +1,250 @@
+use std::fmt::{self, Write};
+
+use super::bytecode::{ti_bytecode, TiByteCode, TiCapture, TiConst, TiFnProto};
+
+/// Renders `proto` and every function compiled into it, one instruction per
+/// line:
+///
+/// ```text
+/// fn mid(a, b): 2 locals
+///    1  0000       LOAD_LOCAL 0 ; a
+///       0002       LOAD_LOCAL 1 ; b
+///       0004       ADD
+///       0005       JZ -> 0012 ; L0
+///       ...
+///       0012  L0:  LOAD_CONST 3 ; "Ha"
+/// ```
+///
+/// The first column is the source line, printed when it changes, the second
+/// the offset of the instruction; jump targets get a label.
+pub fn disassemble(proto: &TiFnProto) -> String {
+  let mut out = String::new();
+  let _ = write_proto(&mut out, proto);
+  out
+}
+
+fn write_proto(out: &mut String, proto: &TiFnProto) -> fmt::Result {
+  let params: Vec<&str> = proto.def.arguments.iter().map(|farg| farg.name.as_str()).collect();
+  write!(out, "fn {}({}): {} locals", proto.def.name, params.join(", "), proto.locals)?;
+  if !proto.upvalues.is_empty() {
+    let captures: Vec<String> = proto.upvalues.iter().map(|capture| match capture {
+      TiCapture::Local(slot) => format!("local {}", slot),
+      TiCapture::Upvalue(idx) => format!("upvalue {}", idx),
+    }).collect();
+    write!(out, ", captures {}", captures.join(", "))?;
+  }
+  writeln!(out)?;
+  write_chunk(out, &proto.bytecode, &params)?;
+  for value in proto.bytecode.consts() {
+    if let TiConst::Fn(proto) = value {
+      writeln!(out)?;
+      write_proto(out, proto)?;
+    }
+  }
+  Ok(())
+}
+
+/// The offsets of the instructions of `code` and the targets of its jumps,
+/// up to the first instruction that is cut short.
+fn decode(code: &[u16]) -> (Vec<usize>, Vec<usize>) {
+  let mut offsets = vec! [];
+  let mut targets = vec! [];
+  let mut offset = 0;
+  while offset < code.len() {
+    offsets.push(offset);
+    let op = code[offset];
+    let operands = ti_bytecode::operands(op).unwrap_or(0);
+    if offset + operands >= code.len() {
+      break;
+    }
+    if matches!(op, ti_bytecode::JMP | ti_bytecode::JNZ | ti_bytecode::JZ) {
+      targets.push(code[offset + 1] as usize);
+    }
+    offset += 1 + operands;
+  }
+  targets.sort_unstable();
+  targets.dedup();
+  (offsets, targets)
+}
+
+fn write_chunk<W: Write>(out: &mut W, bytecode: &TiByteCode, params: &[&str]) -> fmt::Result {
+  let code = bytecode.code();
+  let (offsets, targets) = decode(code);
+  let label = |target: usize| targets.binary_search(&target).ok();
+  let mut spans = bytecode.spans().iter().peekable();
+  let mut last = None;
+  for offset in offsets {
+    let mut line = String::new();
+    while let Some((_, (l, _))) = spans.next_if(|(start, _)| *start <= offset) {
+      if last != Some(l) {
+        last = Some(l);
+        line = l.to_string();
+      }
+    }
+    let mark = match label(offset) {
+      Some(idx) => format!("L{}:", idx),
+      None => String::new(),
+    };
+    let op = code[offset];
+    let operands = ti_bytecode::operands(op).unwrap_or(0);
+    let args = match code.get(offset + 1..offset + 1 + operands) {
+      Some(args) => args,
+      None => {
+        writeln!(out, "{:>4}  {:04}  {:<4} {} <truncated>", line, offset, mark, ti_bytecode::mnemonic(op))?;
+        break;
+      }
+    };
+    let mut text = match ti_bytecode::operands(op) {
+      Some(_) => ti_bytecode::mnemonic(op).to_string(),
+      None => format!("UNKNOWN {:#06x}", op),
+    };
+    let comment = match op {
+      ti_bytecode::JMP | ti_bytecode::JNZ | ti_bytecode::JZ => {
+        let target = args[0] as usize;
+        text += &format!(" -> {:04}", target);
+        label(target).map(|idx| format!("L{}", idx))
+      }
+      _ => {
+        for arg in args {
+          text += &format!(" {}", arg);
+        }
+        describe(bytecode, params, op, args)
+      }
+    };
+    match comment {
+      Some(comment) => writeln!(out, "{:>4}  {:04}  {:<4} {} ; {}", line, offset, mark, text, comment)?,
+      None => writeln!(out, "{:>4}  {:04}  {:<4} {}", line, offset, mark, text)?,
+    }
+  }
+  Ok(())
+}
+
+/// What the operands of an instruction refer to.
+fn describe(bytecode: &TiByteCode, params: &[&str], op: u16, args: &[u16]) -> Option<String> {
+  let consts = bytecode.consts();
+  let value = |idx: u16| match consts.get(idx as usize) {
+    Some(value) => const_repr(value),
+    None => "<invalid constant>".to_string(),
+  };
+  let name = |idx: u16| match consts.get(idx as usize) {
+    Some(TiConst::Str(name)) => name.clone(),
+    _ => "<invalid name>".to_string(),
+  };
+  let res = match op {
+    ti_bytecode::LOAD_CONST | ti_bytecode::BUILD_FUNC | ti_bytecode::DEFINE_TYPE | ti_bytecode::DEFINE_TRAIT => value(args[0]),
+    ti_bytecode::LOAD_SESSION | ti_bytecode::STORE_SESSION | ti_bytecode::DEFINE_SESSION | ti_bytecode::BUILD_STRUCT => name(args[0]),
+    ti_bytecode::GET_FIELD | ti_bytecode::SET_FIELD | ti_bytecode::CALL_METHOD => format!(".{}", name(args[0])),
+    ti_bytecode::LOAD_PATH | ti_bytecode::CALL_PATH | ti_bytecode::IS_VARIANT => format!("{}::{}", name(args[0]), name(args[1])),
+    ti_bytecode::LOAD_LOCAL | ti_bytecode::STORE_LOCAL => params.get(args[0] as usize)?.to_string(),
+    ti_bytecode::BUILD_RANGE if args[0] != 0 => "..=".to_string(),
+    ti_bytecode::BUILD_RANGE => "..".to_string(),
+    ti_bytecode::IMPL => match args[1] {
+      ti_bytecode::NO_NAME => format!("impl {}", name(args[0])),
+      tname => format!("impl {} for {}", name(tname), name(args[0])),
+    },
+    _ => return None,
+  };
+  Some(res)
+}
+
+fn const_repr(value: &TiConst) -> String {
+  match value {
+    TiConst::Num(x) => x.to_string(),
+    TiConst::Str(x) => format!("{:?}", x),
+    TiConst::Bool(x) => x.to_string(),
+    TiConst::Fn(proto) => format!("<fn {}>", proto.def.name),
+    TiConst::Struct(sdef) => format!("struct {}", sdef.name),
+    TiConst::Enum(edef) => format!("enum {}", edef.name),
+    TiConst::Trait(tdef) => format!("trait {}", tdef.name),
+  }
+}
+
+impl fmt::Debug for TiByteCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write_chunk(f, self, &[])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::rc::Rc;
+
+  use super::*;
+  use crate::{
+    driver::driver::{TiDriver, TiSource},
+    frontend::parser::ast::FnDef,
+  };
+
+  fn disasm(src: &str) -> String {
+    let source = TiSource::new("test.ti".to_string(), src.to_string());
+    disassemble(&TiDriver::new(false).compile(&source).unwrap())
+  }
+
+  #[test]
+  fn instructions_show_their_operands_labels_and_lines() {
+    let src = "fn mid(a, b) {\n    if a + b > 2 {\n        \"Ha\"\n    } else {\n        b\n    }\n}\n";
+    let expected = "\
+fn <main>(): 0 locals
+   1  0000       BUILD_FUNC 0 ; <fn mid>
+      0002       DEFINE_SESSION 1 ; mid
+      0004       BUILD_LIST 0
+      0006       EXIT
+
+fn mid(a, b): 2 locals
+   2  0000       LOAD_LOCAL 0 ; a
+      0002       LOAD_LOCAL 1 ; b
+      0004       ADD
+      0005       LOAD_CONST 0 ; 2
+      0007       GRT
+      0008       JZ -> 0014 ; L0
+   3  0010       LOAD_CONST 1 ; \"Ha\"
+      0012       JMP -> 0016 ; L1
+   5  0014  L0:  LOAD_LOCAL 1 ; b
+      0016  L1:  RETURN
+";
+    assert_eq!(disasm(src), expected);
+  }
+
+  #[test]
+  fn captures_paths_and_ranges_are_described() {
+    let src = "enum E { A(i32), B }\nfn main() {\n    let k = 2\n    let f = fn(x) => x * k\n    let e = E::A(f(1))\n    for i in 0..=k { }\n}\n";
+    let text = disasm(src);
+    assert!(text.contains("DEFINE_TYPE 0 ; enum E"), "{}", text);
+    assert!(text.contains("fn <anonymous>(x): 1 locals, captures local 0"), "{}", text);
+    assert!(text.contains("CALL_PATH 3 4 1 ; E::A"), "{}", text);
+    assert!(text.contains("BUILD_RANGE 1 ; ..="), "{}", text);
+  }
+
+  #[test]
+  fn the_line_is_shown_only_when_it_changes() {
+    let text = disasm("fn main() {\n    let a = 1\n    let b = a\n    let c = a\n}\n");
+    let lines: Vec<&str> = text.lines().filter(|line| line.starts_with(' ')).map(|line| line[..4].trim()).filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, ["1", "2", "3", "4"]);
+  }
+
+  #[test]
+  fn unknown_and_truncated_instructions_do_not_panic() {
+    let code = vec! [ti_bytecode::LOAD_LOCAL, 0, 999, ti_bytecode::LOAD_CONST];
+    let bytecode = TiByteCode::new(code, vec! [], vec! []);
+    let expected = "      0000       LOAD_LOCAL 0\n      0002       UNKNOWN 0x03e7\n      0003       LOAD_CONST <truncated>\n";
+    assert_eq!(format!("{:?}", bytecode), expected);
+  }
+
+  #[test]
+  fn functions_in_the_constants_follow_their_chunk() {
+    let def = |name: &str| FnDef { name: Rc::new(name.to_string()), arguments: vec! [] };
+    let inner = TiFnProto { def: def("g"), locals: 0, upvalues: vec! [], bytecode: TiByteCode::new(vec! [ti_bytecode::BUILD_LIST, 0, ti_bytecode::RETURN], vec! [], vec! []) };
+    let consts = vec! [TiConst::Fn(Rc::new(inner))];
+    let outer = TiFnProto { def: def("f"), locals: 0, upvalues: vec! [], bytecode: TiByteCode::new(vec! [ti_bytecode::BUILD_FUNC, 0, ti_bytecode::EXIT], consts, vec! []) };
+    let expected = "\
+fn f(): 0 locals
+      0000       BUILD_FUNC 0 ; <fn g>
+      0002       EXIT
+
+fn g(): 0 locals
+      0000       BUILD_LIST 0
+      0002       RETURN
+";
+    assert_eq!(disassemble(&outer), expected);
+  }
+}
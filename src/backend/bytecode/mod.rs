@@ -1 +1,5 @@
-pub mod bytecode;
\ No newline at end of file
+pub mod bytecode;
+pub mod disasm;
+pub mod optimize;
+pub mod tic;
+pub mod verify;
\ No newline at end of file
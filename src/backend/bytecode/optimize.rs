@@ -0,0 +1,441 @@
+use std::rc::Rc;
+
+use crate::{
+  frontend::lexer::token::TokenAt,
+  vm::types::object_type::{TiBinaryOp, TiObj},
+};
+
+use super::bytecode::{ti_bytecode, TiByteCode, TiConst, TiFnProto};
+
+/// The highest level accepted by `optimize`.
+pub const MAX_LEVEL: u8 = 2;
+
+/// A decoded instruction; the operand of a jump is the index of its target
+/// instruction rather than an offset while the chunk is being rewritten.
+struct TiInstr {
+  op: u16,
+  args: Vec<u16>,
+  /// the statement starting at this instruction
+  at: Option<TokenAt>,
+  dead: bool,
+}
+
+impl TiInstr {
+  fn new(op: u16, args: Vec<u16>) -> Self {
+    Self { op, args, at: None, dead: false }
+  }
+  fn is_jump(&self) -> bool {
+    matches!(self.op, ti_bytecode::JMP | ti_bytecode::JNZ | ti_bytecode::JZ)
+  }
+  fn ends_block(&self) -> bool {
+    matches!(self.op, ti_bytecode::JMP | ti_bytecode::RETURN | ti_bytecode::EXIT | ti_bytecode::MATCH_FAIL)
+  }
+}
+
+/// Optimizes `proto` and every function compiled into it. Level 1 folds
+/// constant arithmetic and comparisons, turns `NOT; JZ` into `JNZ`, threads
+/// jumps to jumps and drops unreachable code, once; level 2 repeats that
+/// until nothing changes. The chunk is expected to have passed `verify`.
+pub fn optimize(proto: &TiFnProto, level: u8) -> TiFnProto {
+  let mut consts: Vec<TiConst> = proto.bytecode.consts().iter().map(|value| match value {
+    TiConst::Fn(proto) => TiConst::Fn(Rc::new(optimize(proto, level))),
+    value => value.clone(),
+  }).collect();
+  let mut instrs = decode(&proto.bytecode);
+  let rounds = match level {
+    0 => 0,
+    1 => 1,
+    _ => usize::MAX,
+  };
+  for _ in 0..rounds {
+    let mut changed = fold(&mut instrs, &mut consts);
+    changed |= simplify_jumps(&mut instrs);
+    changed |= remove_dead(&mut instrs);
+    if !changed {
+      break;
+    }
+  }
+  TiFnProto { def: proto.def.clone(), locals: proto.locals, upvalues: proto.upvalues.clone(), bytecode: encode(instrs, consts) }
+}
+
+fn decode(bytecode: &TiByteCode) -> Vec<TiInstr> {
+  let code = bytecode.code();
+  let mut instrs = vec! [];
+  let mut offsets = vec! [];
+  let mut offset = 0;
+  while offset < code.len() {
+    let operands = ti_bytecode::operands(code[offset]).unwrap_or(0);
+    offsets.push(offset);
+    instrs.push(TiInstr::new(code[offset], code[offset + 1..offset + 1 + operands].to_vec()));
+    offset += 1 + operands;
+  }
+  let index = |offset: usize| offsets.partition_point(|start| *start < offset);
+  for instr in instrs.iter_mut().filter(|instr| instr.is_jump()) {
+    instr.args[0] = index(instr.args[0] as usize) as u16;
+  }
+  for (offset, at) in bytecode.spans() {
+    if let Some(instr) = instrs.get_mut(index(*offset)) {
+      instr.at = Some(*at);
+    }
+  }
+  instrs
+}
+
+fn encode(instrs: Vec<TiInstr>, consts: Vec<TiConst>) -> TiByteCode {
+  let mut offsets = vec! [];
+  let mut offset = 0;
+  for instr in &instrs {
+    offsets.push(offset);
+    offset += 1 + instr.args.len();
+  }
+  let mut code = vec! [];
+  let mut spans: Vec<(usize, TokenAt)> = vec! [];
+  for (idx, mut instr) in instrs.into_iter().enumerate() {
+    if instr.is_jump() {
+      instr.args[0] = offsets[instr.args[0] as usize] as u16;
+    }
+    match instr.at {
+      Some(at) if spans.last().map(|(_, last)| *last) != Some(at) => spans.push((offsets[idx], at)),
+      _ => {}
+    }
+    code.push(instr.op);
+    code.extend(instr.args);
+  }
+  TiByteCode::new(code, consts, spans)
+}
+
+/// Whether some jump lands on each instruction.
+fn targets(instrs: &[TiInstr]) -> Vec<bool> {
+  let mut res = vec! [false; instrs.len()];
+  for instr in instrs.iter().filter(|instr| instr.is_jump()) {
+    res[instr.args[0] as usize] = true;
+  }
+  res
+}
+
+/// Drops the dead instructions, pointing the jumps to one of them at the
+/// next instruction left and handing its statement span down.
+fn compact(instrs: &mut Vec<TiInstr>) {
+  let mut next = vec! [0; instrs.len() + 1];
+  let mut live = instrs.iter().filter(|instr| !instr.dead).count();
+  next[instrs.len()] = live;
+  for idx in (0..instrs.len()).rev() {
+    if !instrs[idx].dead {
+      live -= 1;
+    }
+    next[idx] = live;
+  }
+  let mut at = None;
+  let mut res = Vec::with_capacity(instrs.len());
+  for mut instr in instrs.drain(..) {
+    if instr.dead {
+      at = at.or(instr.at);
+      continue;
+    }
+    instr.at = instr.at.or(at.take());
+    if instr.is_jump() {
+      instr.args[0] = next[instr.args[0] as usize] as u16;
+    }
+    res.push(instr);
+  }
+  *instrs = res;
+}
+
+fn value(consts: &[TiConst], instr: &TiInstr) -> Option<TiObj> {
+  if instr.op != ti_bytecode::LOAD_CONST {
+    return None;
+  }
+  match consts.get(instr.args[0] as usize)? {
+    TiConst::Num(x) => Some(TiObj::Num(*x)),
+    TiConst::Str(x) => Some(TiObj::Str(x.clone())),
+    TiConst::Bool(x) => Some(TiObj::Bool(*x)),
+    _ => None,
+  }
+}
+
+fn add_const(consts: &mut Vec<TiConst>, obj: TiObj) -> Option<u16> {
+  let value = match obj {
+    TiObj::Num(x) => TiConst::Num(x),
+    TiObj::Str(x) => TiConst::Str(x),
+    TiObj::Bool(x) => TiConst::Bool(x),
+    _ => return None,
+  };
+  let found = consts.iter().position(|c| match (c, &value) {
+    (TiConst::Num(x), TiConst::Num(y)) => x.to_bits() == y.to_bits(),
+    (TiConst::Str(x), TiConst::Str(y)) => x == y,
+    (TiConst::Bool(x), TiConst::Bool(y)) => x == y,
+    _ => false,
+  });
+  let idx = match found {
+    Some(idx) => idx,
+    None => {
+      consts.push(value);
+      consts.len() - 1
+    }
+  };
+  u16::try_from(idx).ok().filter(|idx| *idx != u16::MAX)
+}
+
+fn binary(op: u16) -> Option<TiBinaryOp> {
+  let res: TiBinaryOp = match op {
+    ti_bytecode::ADD => TiObj::add,
+    ti_bytecode::SUB => TiObj::sub,
+    ti_bytecode::MUL => TiObj::mul,
+    ti_bytecode::DIV => TiObj::div,
+    ti_bytecode::MOD => TiObj::r#mod,
+    ti_bytecode::POW => TiObj::pow,
+    ti_bytecode::BIT_AND => TiObj::bit_and,
+    ti_bytecode::BIT_OR => TiObj::bit_or,
+    ti_bytecode::BIT_XOR => TiObj::bit_xor,
+    ti_bytecode::SHL => TiObj::shl,
+    ti_bytecode::SHR => TiObj::shr,
+    ti_bytecode::AND => TiObj::and,
+    ti_bytecode::OR => TiObj::or,
+    ti_bytecode::LES => TiObj::les,
+    ti_bytecode::GRT => TiObj::grt,
+    ti_bytecode::LEQ => TiObj::leq,
+    ti_bytecode::GEQ => TiObj::geq,
+    ti_bytecode::EQ => TiObj::eq,
+    ti_bytecode::NEQ => TiObj::neq,
+    _ => return None,
+  };
+  Some(res)
+}
+
+/// The next instruction after `idx` that is still alive.
+fn next_live(instrs: &[TiInstr], idx: usize) -> Option<usize> {
+  (idx + 1..instrs.len()).find(|idx| !instrs[*idx].dead)
+}
+
+/// Evaluates `LOAD_CONST a; LOAD_CONST b; <op>` at compile time, and the
+/// conditional jumps on a constant `bool`. Operations that fail are left to
+/// fail at run time. Nothing is folded across a jump target.
+fn fold(instrs: &mut Vec<TiInstr>, consts: &mut Vec<TiConst>) -> bool {
+  let targets = targets(instrs);
+  let mut changed = false;
+  let mut idx = 0;
+  while idx < instrs.len() {
+    let lhs = match value(consts, &instrs[idx]) {
+      Some(lhs) => lhs,
+      None => {
+        idx += 1;
+        continue;
+      }
+    };
+    let next = next_live(instrs, idx);
+    let after = next.and_then(|next| next_live(instrs, next));
+    let plain = |last: usize| !targets[idx + 1..=last].iter().any(|target| *target);
+    if let (Some(next), Some(after)) = (next, after) {
+      let folded = match binary(instrs[after].op) {
+        Some(op) if plain(after) => value(consts, &instrs[next])
+          .and_then(|rhs| op(lhs.clone(), &rhs).ok())
+          .and_then(|res| add_const(consts, res)),
+        _ => None,
+      };
+      if let Some(res) = folded {
+        instrs[idx].args[0] = res;
+        instrs[next].dead = true;
+        instrs[after].dead = true;
+        changed = true;
+        // the result may be the right operand of an earlier constant
+        idx = (0..idx).rev().find(|prev| !instrs[*prev].dead).unwrap_or(idx);
+        continue;
+      }
+    }
+    if let (TiObj::Bool(cond), Some(next)) = (lhs, next) {
+      let taken = match instrs[next].op {
+        ti_bytecode::JZ if plain(next) => Some(!cond),
+        ti_bytecode::JNZ if plain(next) => Some(cond),
+        _ => None,
+      };
+      if let Some(taken) = taken {
+        instrs[idx].dead = true;
+        if taken {
+          instrs[next].op = ti_bytecode::JMP;
+        } else {
+          instrs[next].dead = true;
+        }
+        changed = true;
+      }
+    }
+    idx += 1;
+  }
+  compact(instrs);
+  changed
+}
+
+/// Whether `op` leaves a bool on the stack whenever it doesn't fail.
+fn gives_bool(op: u16) -> bool {
+  matches!(
+    op,
+    ti_bytecode::EQ | ti_bytecode::NEQ | ti_bytecode::LES | ti_bytecode::GRT | ti_bytecode::LEQ | ti_bytecode::GEQ | ti_bytecode::NOT
+  )
+}
+
+/// Turns `NOT; JZ` into `JNZ` and `NOT; JNZ` into `JZ` when the `NOT` is of
+/// a bool, as it's the `NOT` that fails on anything else, points jumps to an
+/// unconditional jump at its target, replaces a jump to `RETURN` or `EXIT`
+/// with that instruction and drops jumps to the next instruction.
+fn simplify_jumps(instrs: &mut Vec<TiInstr>) -> bool {
+  let targets = targets(instrs);
+  let mut changed = false;
+  for idx in 0..instrs.len().saturating_sub(1) {
+    let of_bool = idx > 0 && !targets[idx] && gives_bool(instrs[idx - 1].op);
+    if instrs[idx].op == ti_bytecode::NOT && of_bool && !targets[idx + 1] && !instrs[idx].dead {
+      let inverse = match instrs[idx + 1].op {
+        ti_bytecode::JZ => ti_bytecode::JNZ,
+        ti_bytecode::JNZ => ti_bytecode::JZ,
+        _ => continue,
+      };
+      instrs[idx].dead = true;
+      instrs[idx + 1].op = inverse;
+      changed = true;
+    }
+  }
+  for idx in 0..instrs.len() {
+    if !instrs[idx].is_jump() {
+      continue;
+    }
+    // bounded, so a loop of jumps cannot hang the optimizer
+    for _ in 0..instrs.len() {
+      let target = instrs[idx].args[0] as usize;
+      if target == idx || instrs[target].op != ti_bytecode::JMP || instrs[target].args[0] as usize == target {
+        break;
+      }
+      instrs[idx].args[0] = instrs[target].args[0];
+      changed = true;
+    }
+    let target = instrs[idx].args[0] as usize;
+    if instrs[idx].op == ti_bytecode::JMP {
+      if matches!(instrs[target].op, ti_bytecode::RETURN | ti_bytecode::EXIT) {
+        instrs[idx].op = instrs[target].op;
+        instrs[idx].args.clear();
+        changed = true;
+      } else if target == idx + 1 {
+        instrs[idx].dead = true;
+        changed = true;
+      }
+    }
+  }
+  compact(instrs);
+  changed
+}
+
+/// Drops the instructions no path from the entry reaches, such as the code
+/// after a `JMP` or `EXIT`.
+fn remove_dead(instrs: &mut Vec<TiInstr>) -> bool {
+  let mut reached = vec! [false; instrs.len()];
+  let mut paths = vec! [0];
+  while let Some(idx) = paths.pop() {
+    if idx >= instrs.len() || reached[idx] {
+      continue;
+    }
+    reached[idx] = true;
+    let instr = &instrs[idx];
+    if instr.is_jump() {
+      paths.push(instr.args[0] as usize);
+    }
+    if !instr.ends_block() {
+      paths.push(idx + 1);
+    }
+  }
+  let mut changed = false;
+  for (instr, reached) in instrs.iter_mut().zip(reached) {
+    if !reached {
+      instr.dead = true;
+      changed = true;
+    }
+  }
+  compact(instrs);
+  changed
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::frontend::parser::ast::FnDef;
+  use ti_bytecode::*;
+
+  fn proto(code: Vec<u16>, consts: Vec<TiConst>) -> TiFnProto {
+    let def = FnDef { name: Rc::new("f".to_string()), arguments: vec! [] };
+    TiFnProto { def, locals: 2, upvalues: vec! [], bytecode: TiByteCode::new(code, consts, vec! []) }
+  }
+
+  fn num(consts: &[TiConst], idx: u16) -> f64 {
+    match consts[idx as usize] {
+      TiConst::Num(x) => x,
+      ref other => panic!("expected a number, found {:?}", other),
+    }
+  }
+
+  #[test]
+  fn folds_constant_arithmetic() {
+    let consts = vec! [TiConst::Num(2.0), TiConst::Num(3.0), TiConst::Num(4.0)];
+    let code = vec! [LOAD_CONST, 0, LOAD_CONST, 1, ADD, LOAD_CONST, 2, MUL, EXIT];
+    let res = optimize(&proto(code, consts), 1);
+    let code = res.bytecode.code();
+    assert_eq!(code[0], LOAD_CONST);
+    assert_eq!(code[2..], [EXIT]);
+    assert_eq!(num(res.bytecode.consts(), code[1]), 20.0);
+  }
+
+  #[test]
+  fn leaves_failing_operations_to_run_time() {
+    let consts = vec! [TiConst::Num(2.0), TiConst::Bool(true)];
+    let code = vec! [LOAD_CONST, 0, LOAD_CONST, 1, ADD, EXIT];
+    let res = optimize(&proto(code.clone(), consts), 2);
+    assert_eq!(res.bytecode.code(), code);
+  }
+
+  #[test]
+  fn level_0_changes_nothing() {
+    let consts = vec! [TiConst::Num(2.0), TiConst::Num(3.0)];
+    let code = vec! [LOAD_CONST, 0, LOAD_CONST, 1, ADD, EXIT, POP];
+    let res = optimize(&proto(code.clone(), consts), 0);
+    assert_eq!(res.bytecode.code(), code);
+  }
+
+  #[test]
+  fn not_of_a_bool_before_a_jump_inverts_it() {
+    let code = vec! [LOAD_LOCAL, 0, LOAD_LOCAL, 1, LES, NOT, JZ, 11, LOAD_LOCAL, 0, POP, BUILD_LIST, 0, EXIT];
+    let res = optimize(&proto(code, vec! []), 1);
+    assert_eq!(res.bytecode.code(), [LOAD_LOCAL, 0, LOAD_LOCAL, 1, LES, JNZ, 10, LOAD_LOCAL, 0, POP, BUILD_LIST, 0, EXIT]);
+  }
+
+  #[test]
+  fn not_of_anything_else_before_a_jump_is_kept() {
+    let code = vec! [LOAD_LOCAL, 0, NOT, JZ, 8, LOAD_LOCAL, 0, POP, BUILD_LIST, 0, EXIT];
+    let res = optimize(&proto(code.clone(), vec! []), 2);
+    assert_eq!(res.bytecode.code(), code);
+  }
+
+  #[test]
+  fn jumps_to_jumps_are_threaded_and_dead_code_dropped() {
+    let code = vec! [LOAD_LOCAL, 0, JZ, 7, LOAD_LOCAL, 1, POP, JMP, 10, POP, BUILD_LIST, 0, EXIT];
+    let once = optimize(&proto(code.clone(), vec! []), 1);
+    assert_eq!(once.bytecode.code(), [LOAD_LOCAL, 0, JZ, 9, LOAD_LOCAL, 1, POP, JMP, 9, BUILD_LIST, 0, EXIT]);
+    let twice = optimize(&proto(code, vec! []), 2);
+    assert_eq!(twice.bytecode.code(), [LOAD_LOCAL, 0, JZ, 7, LOAD_LOCAL, 1, POP, BUILD_LIST, 0, EXIT]);
+  }
+
+  #[test]
+  fn jumps_on_a_constant_are_decided() {
+    let code = vec! [LOAD_CONST, 0, JZ, 7, LOAD_LOCAL, 0, POP, BUILD_LIST, 0, EXIT];
+    let res = optimize(&proto(code, vec! [TiConst::Bool(false)]), 2);
+    assert_eq!(res.bytecode.code(), [BUILD_LIST, 0, EXIT]);
+  }
+
+  #[test]
+  fn functions_in_the_constants_are_optimized() {
+    let inner = proto(vec! [LOAD_CONST, 0, LOAD_CONST, 0, MUL, RETURN], vec! [TiConst::Num(3.0)]);
+    let outer = proto(vec! [BUILD_FUNC, 0, EXIT], vec! [TiConst::Fn(Rc::new(inner))]);
+    let res = optimize(&outer, 1);
+    let inner = match &res.bytecode.consts()[0] {
+      TiConst::Fn(inner) => inner,
+      other => panic!("expected a function, found {:?}", other),
+    };
+    let code = inner.bytecode.code();
+    assert_eq!([code[0], code[2]], [LOAD_CONST, RETURN]);
+    assert_eq!(num(inner.bytecode.consts(), code[1]), 9.0);
+  }
+}
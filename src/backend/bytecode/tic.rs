@@ -0,0 +1,563 @@
+use std::rc::Rc;
+
+use crate::{
+  build_ti_error,
+  error::error::TiError,
+  frontend::{
+    lexer::token::TokenAt,
+    parser::ast::{EnumDef, EnumField, FnArg, FnDef, StructDef, StructField, TraitDef, TraitField, Type},
+  },
+};
+
+use super::bytecode::{ti_bytecode, TiByteCode, TiCapture, TiConst, TiFnProto};
+
+/// Every `.tic` file starts with these bytes, followed by the version.
+pub const TIC_MAGIC: &[u8; 4] = b"TIC\0";
+pub const TIC_VERSION: u16 = 2;
+
+/// Functions and types may not nest deeper than this in a `.tic` file.
+const MAX_DEPTH: usize = 64;
+
+mod tag {
+  pub const NUM: u8    = 0;
+  pub const STR: u8    = 1;
+  pub const BOOL: u8   = 2;
+  pub const FN: u8     = 3;
+  pub const STRUCT: u8 = 4;
+  pub const ENUM: u8   = 5;
+  pub const TRAIT: u8  = 6;
+}
+
+mod capture_tag {
+  pub const LOCAL: u8   = 0;
+  pub const UPVALUE: u8 = 1;
+}
+
+mod type_tag {
+  pub const UNKNOWN: u8 = 0;
+  pub const NEVER: u8   = 1;
+  pub const F32: u8     = 2;
+  pub const F64: u8     = 3;
+  pub const I8: u8      = 4;
+  pub const I16: u8     = 5;
+  pub const I32: u8     = 6;
+  pub const I64: u8     = 7;
+  pub const I128: u8    = 8;
+  pub const U8: u8      = 9;
+  pub const U16: u8     = 10;
+  pub const U32: u8     = 11;
+  pub const U64: u8     = 12;
+  pub const U128: u8    = 13;
+  pub const BOOL: u8    = 14;
+  pub const REF: u8     = 15;
+  pub const ARRAY: u8   = 16;
+  pub const UNIT: u8    = 17;
+  pub const COSTUME: u8 = 18;
+  pub const ANNA: u8    = 19;
+}
+
+/// A compiled program as stored in a `.tic` file: the name of the source it
+/// was compiled from, for the debug line tables, and its main chunk.
+///
+/// All integers are little endian. After the magic and the version come the
+/// source name and the main function; a function is its name, parameters,
+/// local count, captured variables, constant pool, `u16` instruction stream
+/// and line table.
+pub struct TicFile {
+  pub source: String,
+  pub main: TiFnProto,
+}
+
+impl TicFile {
+  pub fn new(source: String, main: TiFnProto) -> Self {
+    Self { source, main }
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut w = TicWriter { bytes: vec! [] };
+    w.bytes.extend_from_slice(TIC_MAGIC);
+    w.u16(TIC_VERSION);
+    w.str(&self.source);
+    w.proto(&self.main);
+    w.bytes
+  }
+
+  /// Loads a `.tic` file, rejecting a bad header, unknown tags, truncated
+  /// data, instructions cut short by the end of their function or bytes
+  /// left after the program.
+  pub fn decode(bytes: &[u8]) -> Result<Self, TiError> {
+    let mut r = TicReader { bytes, pos: 0, depth: 0 };
+    if bytes.len() < TIC_MAGIC.len() || &bytes[..TIC_MAGIC.len()] != TIC_MAGIC {
+      build_ti_error!(@err Load, "not a ti bytecode file, the magic header is missing.")
+    }
+    r.pos = TIC_MAGIC.len();
+    let version = r.u16()?;
+    if version != TIC_VERSION {
+      build_ti_error!(@err Load, "unsupported bytecode version {}, expected {}.", version, TIC_VERSION)
+    }
+    let source = r.str()?;
+    let main = r.proto()?;
+    if r.pos != bytes.len() {
+      build_ti_error!(@err Load, "malformed bytecode file, {} unexpected bytes after the program.", bytes.len() - r.pos)
+    }
+    Ok(Self { source, main })
+  }
+}
+
+struct TicWriter {
+  bytes: Vec<u8>,
+}
+
+impl TicWriter {
+  fn u8(&mut self, x: u8) {
+    self.bytes.push(x);
+  }
+  fn u16(&mut self, x: u16) {
+    self.bytes.extend_from_slice(&x.to_le_bytes());
+  }
+  fn u32(&mut self, x: usize) {
+    self.bytes.extend_from_slice(&(x as u32).to_le_bytes());
+  }
+  fn str(&mut self, x: &str) {
+    self.u32(x.len());
+    self.bytes.extend_from_slice(x.as_bytes());
+  }
+
+  fn fn_def(&mut self, fdef: &FnDef) {
+    self.str(&fdef.name);
+    self.u32(fdef.arguments.len());
+    for farg in &fdef.arguments {
+      self.str(&farg.name);
+    }
+  }
+
+  fn proto(&mut self, proto: &TiFnProto) {
+    self.fn_def(&proto.def);
+    self.u32(proto.locals);
+    self.u32(proto.upvalues.len());
+    for capture in &proto.upvalues {
+      match capture {
+        TiCapture::Local(slot) => {
+          self.u8(capture_tag::LOCAL);
+          self.u16(*slot);
+        }
+        TiCapture::Upvalue(idx) => {
+          self.u8(capture_tag::UPVALUE);
+          self.u16(*idx);
+        }
+      }
+    }
+    let bytecode = &proto.bytecode;
+    self.u32(bytecode.consts().len());
+    for value in bytecode.consts() {
+      self.constant(value);
+    }
+    self.u32(bytecode.code().len());
+    for x in bytecode.code() {
+      self.u16(*x);
+    }
+    self.u32(bytecode.spans().len());
+    for (offset, (line, (start, end))) in bytecode.spans() {
+      self.u32(*offset);
+      self.u32(*line);
+      self.u32(*start);
+      self.u32(*end);
+    }
+  }
+
+  fn constant(&mut self, value: &TiConst) {
+    match value {
+      TiConst::Num(x) => {
+        self.u8(tag::NUM);
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+      }
+      TiConst::Str(x) => {
+        self.u8(tag::STR);
+        self.str(x);
+      }
+      TiConst::Bool(x) => {
+        self.u8(tag::BOOL);
+        self.u8(*x as u8);
+      }
+      TiConst::Fn(proto) => {
+        self.u8(tag::FN);
+        self.proto(proto);
+      }
+      TiConst::Struct(sdef) => {
+        self.u8(tag::STRUCT);
+        self.str(&sdef.name);
+        self.u32(sdef.fields.len());
+        for field in &sdef.fields {
+          self.str(&field.name);
+          self.ty(&field.ty);
+        }
+      }
+      TiConst::Enum(edef) => {
+        self.u8(tag::ENUM);
+        self.str(&edef.name);
+        self.u32(edef.fields.len());
+        for field in &edef.fields {
+          self.str(&field.name);
+          self.types(&field.values);
+        }
+      }
+      TiConst::Trait(tdef) => {
+        self.u8(tag::TRAIT);
+        self.str(&tdef.name);
+        self.u32(tdef.fields.len());
+        for field in &tdef.fields {
+          self.fn_def(&field.body);
+        }
+      }
+    }
+  }
+
+  fn types(&mut self, tys: &[Type]) {
+    self.u32(tys.len());
+    for ty in tys {
+      self.ty(ty);
+    }
+  }
+
+  fn ty(&mut self, ty: &Type) {
+    match ty {
+      Type::Unknown => self.u8(type_tag::UNKNOWN),
+      Type::Never => self.u8(type_tag::NEVER),
+      Type::F32 => self.u8(type_tag::F32),
+      Type::F64 => self.u8(type_tag::F64),
+      Type::I8 => self.u8(type_tag::I8),
+      Type::I16 => self.u8(type_tag::I16),
+      Type::I32 => self.u8(type_tag::I32),
+      Type::I64 => self.u8(type_tag::I64),
+      Type::I128 => self.u8(type_tag::I128),
+      Type::U8 => self.u8(type_tag::U8),
+      Type::U16 => self.u8(type_tag::U16),
+      Type::U32 => self.u8(type_tag::U32),
+      Type::U64 => self.u8(type_tag::U64),
+      Type::U128 => self.u8(type_tag::U128),
+      Type::Bool => self.u8(type_tag::BOOL),
+      Type::Ref(t) => {
+        self.u8(type_tag::REF);
+        self.ty(t);
+      }
+      Type::Array(t, l) => {
+        self.u8(type_tag::ARRAY);
+        self.ty(t);
+        self.u32(*l);
+      }
+      Type::Unit(ts) => {
+        self.u8(type_tag::UNIT);
+        self.types(ts);
+      }
+      Type::Costume(name) => {
+        self.u8(type_tag::COSTUME);
+        self.str(name);
+      }
+      Type::Anna(t, anna) => {
+        self.u8(type_tag::ANNA);
+        self.ty(t);
+        self.types(anna);
+      }
+    }
+  }
+}
+
+struct TicReader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  /// how deep the function or type being read is nested
+  depth: usize,
+}
+
+impl TicReader<'_> {
+  fn take(&mut self, len: usize) -> Result<&[u8], TiError> {
+    match self.bytes.get(self.pos..).filter(|rest| rest.len() >= len) {
+      Some(rest) => {
+        self.pos += len;
+        Ok(&rest[..len])
+      }
+      None => build_ti_error!(@err Load, "truncated bytecode file, expected {} more bytes at offset {}.", len, self.pos),
+    }
+  }
+  fn u8(&mut self) -> Result<u8, TiError> {
+    Ok(self.take(1)?[0])
+  }
+  fn u16(&mut self) -> Result<u16, TiError> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+  }
+  fn u32(&mut self) -> Result<usize, TiError> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+  }
+  /// Reads a count of items of at least `size` bytes each, which must fit
+  /// in the rest of the file.
+  fn len(&mut self, size: usize) -> Result<usize, TiError> {
+    let at = self.pos;
+    let len = self.u32()?;
+    if len.saturating_mul(size) > self.bytes.len() - self.pos {
+      build_ti_error!(@err Load, "truncated bytecode file, {} items announced at offset {} do not fit.", len, at)
+    }
+    Ok(len)
+  }
+  fn str(&mut self) -> Result<String, TiError> {
+    let at = self.pos;
+    let len = self.len(1)?;
+    match String::from_utf8(self.take(len)?.to_vec()) {
+      Ok(x) => Ok(x),
+      Err(_) => build_ti_error!(@err Load, "malformed bytecode file, invalid utf-8 string at offset {}.", at),
+    }
+  }
+  fn name(&mut self) -> Result<Rc<String>, TiError> {
+    Ok(Rc::new(self.str()?))
+  }
+
+  fn enter(&mut self) -> Result<(), TiError> {
+    self.depth += 1;
+    if self.depth > MAX_DEPTH {
+      build_ti_error!(@err Load, "malformed bytecode file, items nested deeper than {} at offset {}.", MAX_DEPTH, self.pos)
+    }
+    Ok(())
+  }
+
+  fn fn_def(&mut self) -> Result<FnDef, TiError> {
+    let name = self.name()?;
+    let len = self.len(4)?;
+    let mut arguments = Vec::with_capacity(len);
+    for _ in 0..len {
+      arguments.push(FnArg::new(self.name()?));
+    }
+    Ok(FnDef { name, arguments })
+  }
+
+  fn proto(&mut self) -> Result<TiFnProto, TiError> {
+    self.enter()?;
+    let def = self.fn_def()?;
+    let locals = self.u32()?;
+    if locals < def.arguments.len() || locals > u16::MAX as usize {
+      build_ti_error!(@err Load, "malformed bytecode file, function `{}` has {} locals for {} parameters.", def.name, locals, def.arguments.len())
+    }
+    let len = self.len(3)?;
+    let mut upvalues = Vec::with_capacity(len);
+    for _ in 0..len {
+      let at = self.pos;
+      let capture = match self.u8()? {
+        capture_tag::LOCAL => TiCapture::Local(self.u16()?),
+        capture_tag::UPVALUE => TiCapture::Upvalue(self.u16()?),
+        tag => build_ti_error!(@err Load, "malformed bytecode file, unknown capture tag {} at offset {}.", tag, at),
+      };
+      upvalues.push(capture);
+    }
+    let len = self.len(1)?;
+    let mut consts = Vec::with_capacity(len);
+    for _ in 0..len {
+      consts.push(self.constant()?);
+    }
+    let len = self.len(2)?;
+    let mut code = Vec::with_capacity(len);
+    for _ in 0..len {
+      code.push(self.u16()?);
+    }
+    let mut offset = 0;
+    while offset < code.len() {
+      match ti_bytecode::operands(code[offset]) {
+        Some(operands) if offset + operands < code.len() => offset += 1 + operands,
+        Some(_) => build_ti_error!(@err Load, "malformed bytecode file, `{}` at {:04} of `{}` is cut short.", ti_bytecode::mnemonic(code[offset]), offset, def.name),
+        None => build_ti_error!(@err Load, "malformed bytecode file, unknown opcode {} at {:04} of `{}`.", code[offset], offset, def.name),
+      }
+    }
+    let len = self.len(16)?;
+    let mut spans: Vec<(usize, TokenAt)> = Vec::with_capacity(len);
+    for _ in 0..len {
+      let offset = self.u32()?;
+      let at = (self.u32()?, (self.u32()?, self.u32()?));
+      if offset > code.len() || spans.last().is_some_and(|(last, _)| *last >= offset) {
+        build_ti_error!(@err Load, "malformed bytecode file, line table of `{}` is out of order.", def.name)
+      }
+      spans.push((offset, at));
+    }
+    self.depth -= 1;
+    Ok(TiFnProto { def, locals, upvalues, bytecode: TiByteCode::new(code, consts, spans) })
+  }
+
+  fn constant(&mut self) -> Result<TiConst, TiError> {
+    let at = self.pos;
+    let res = match self.u8()? {
+      tag::NUM => {
+        let bytes = self.take(8)?;
+        TiConst::Num(f64::from_le_bytes(bytes.try_into().unwrap()))
+      }
+      tag::STR => TiConst::Str(self.str()?),
+      tag::BOOL => match self.u8()? {
+        0 => TiConst::Bool(false),
+        1 => TiConst::Bool(true),
+        x => build_ti_error!(@err Load, "malformed bytecode file, invalid bool {} at offset {}.", x, at + 1),
+      },
+      tag::FN => TiConst::Fn(Rc::new(self.proto()?)),
+      tag::STRUCT => {
+        let name = self.name()?;
+        let len = self.len(5)?;
+        let mut fields = Vec::with_capacity(len);
+        for _ in 0..len {
+          fields.push(StructField { name: self.name()?, ty: self.ty()? });
+        }
+        TiConst::Struct(StructDef { name, fields })
+      }
+      tag::ENUM => {
+        let name = self.name()?;
+        let len = self.len(8)?;
+        let mut fields = Vec::with_capacity(len);
+        for _ in 0..len {
+          fields.push(EnumField { name: self.name()?, values: self.types()? });
+        }
+        TiConst::Enum(EnumDef { name, fields })
+      }
+      tag::TRAIT => {
+        let name = self.name()?;
+        let len = self.len(8)?;
+        let mut fields = Vec::with_capacity(len);
+        for _ in 0..len {
+          fields.push(TraitField { body: self.fn_def()?, default: None });
+        }
+        TiConst::Trait(TraitDef { name, fields })
+      }
+      x => build_ti_error!(@err Load, "malformed bytecode file, unknown constant tag {} at offset {}.", x, at),
+    };
+    Ok(res)
+  }
+
+  fn types(&mut self) -> Result<Vec<Type>, TiError> {
+    let len = self.len(1)?;
+    let mut tys = Vec::with_capacity(len);
+    for _ in 0..len {
+      tys.push(self.ty()?);
+    }
+    Ok(tys)
+  }
+
+  fn ty(&mut self) -> Result<Type, TiError> {
+    let at = self.pos;
+    self.enter()?;
+    let res = match self.u8()? {
+      type_tag::UNKNOWN => Type::Unknown,
+      type_tag::NEVER => Type::Never,
+      type_tag::F32 => Type::F32,
+      type_tag::F64 => Type::F64,
+      type_tag::I8 => Type::I8,
+      type_tag::I16 => Type::I16,
+      type_tag::I32 => Type::I32,
+      type_tag::I64 => Type::I64,
+      type_tag::I128 => Type::I128,
+      type_tag::U8 => Type::U8,
+      type_tag::U16 => Type::U16,
+      type_tag::U32 => Type::U32,
+      type_tag::U64 => Type::U64,
+      type_tag::U128 => Type::U128,
+      type_tag::BOOL => Type::Bool,
+      type_tag::REF => Type::Ref(Box::new(self.ty()?)),
+      type_tag::ARRAY => Type::Array(Box::new(self.ty()?), self.u32()?),
+      type_tag::UNIT => Type::Unit(self.types()?),
+      type_tag::COSTUME => Type::Costume(self.name()?),
+      type_tag::ANNA => Type::Anna(Box::new(self.ty()?), self.types()?),
+      x => build_ti_error!(@err Load, "malformed bytecode file, unknown type tag {} at offset {}.", x, at),
+    };
+    self.depth -= 1;
+    Ok(res)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    backend::bytecode::disasm::disassemble,
+    driver::driver::{TiDriver, TiSource},
+    error::error::TiErrorKind,
+  };
+
+  const SRC: &str = "struct P { x: i32 }\nenum E { A(i32), B }\nfn main() {\n    let k = 2\n    let f = fn(x) => x * k\n    println(\"{} {}\", f(3), true)\n}\n";
+
+  fn compiled() -> TicFile {
+    let main = TiDriver::new(false).compile(&TiSource::new("test.ti".to_string(), SRC.to_string())).unwrap();
+    TicFile::new("test.ti".to_string(), main)
+  }
+
+  /// The file holding `main` with a single constant, `value`, and the
+  /// offset of the tag of that constant.
+  fn one_const(value: TiConst) -> (Vec<u8>, usize) {
+    let def = FnDef { name: Rc::new("f".to_string()), arguments: vec! [] };
+    let main = TiFnProto { def, locals: 0, upvalues: vec! [], bytecode: TiByteCode::new(vec! [ti_bytecode::LOAD_CONST, 0, ti_bytecode::EXIT], vec! [value], vec! []) };
+    let bytes = TicFile::new("s".to_string(), main).encode();
+    // magic, version, source, name, parameters, locals, captures, constants
+    (bytes, 4 + 2 + 5 + 5 + 4 + 4 + 4 + 4)
+  }
+
+  fn rejected(bytes: &[u8]) -> String {
+    let err = TicFile::decode(bytes).err().expect("the file was accepted");
+    assert_eq!(err.kind, TiErrorKind::Load);
+    err.message
+  }
+
+  #[test]
+  fn decoding_gives_back_what_was_encoded() {
+    let tic = compiled();
+    let decoded = TicFile::decode(&tic.encode()).unwrap();
+    assert_eq!(decoded.source, "test.ti");
+    assert_eq!(disassemble(&decoded.main), disassemble(&tic.main));
+    assert_eq!(decoded.main.bytecode.spans(), tic.main.bytecode.spans());
+  }
+
+  #[test]
+  fn files_without_the_magic_are_rejected() {
+    let mut bytes = compiled().encode();
+    bytes[0] = b'X';
+    assert_eq!(rejected(&bytes), "not a ti bytecode file, the magic header is missing.");
+    assert_eq!(rejected(b"TI"), "not a ti bytecode file, the magic header is missing.");
+  }
+
+  #[test]
+  fn other_versions_are_rejected() {
+    let mut bytes = compiled().encode();
+    bytes[4..6].copy_from_slice(&(TIC_VERSION + 1).to_le_bytes());
+    assert_eq!(rejected(&bytes), format!("unsupported bytecode version {}, expected {}.", TIC_VERSION + 1, TIC_VERSION));
+  }
+
+  #[test]
+  fn every_truncated_file_is_rejected() {
+    let bytes = compiled().encode();
+    for len in 0..bytes.len() {
+      assert!(TicFile::decode(&bytes[..len]).is_err(), "a file cut at {} bytes was accepted", len);
+    }
+  }
+
+  #[test]
+  fn bytes_after_the_program_are_rejected() {
+    let mut bytes = compiled().encode();
+    bytes.extend_from_slice(&[0, 0, 0]);
+    assert_eq!(rejected(&bytes), "malformed bytecode file, 3 unexpected bytes after the program.");
+  }
+
+  #[test]
+  fn unknown_tags_and_invalid_values_are_rejected() {
+    let (mut bytes, at) = one_const(TiConst::Bool(true));
+    assert_eq!(bytes[at], tag::BOOL);
+    bytes[at + 1] = 7;
+    assert_eq!(rejected(&bytes), format!("malformed bytecode file, invalid bool 7 at offset {}.", at + 1));
+    bytes[at] = 99;
+    assert_eq!(rejected(&bytes), format!("malformed bytecode file, unknown constant tag 99 at offset {}.", at));
+  }
+
+  #[test]
+  fn invalid_strings_are_rejected() {
+    let (mut bytes, at) = one_const(TiConst::Str("ab".to_string()));
+    bytes[at + 1 + 4] = 0xff;
+    assert_eq!(rejected(&bytes), format!("malformed bytecode file, invalid utf-8 string at offset {}.", at + 1));
+  }
+
+  #[test]
+  fn lengths_past_the_end_are_rejected() {
+    let (mut bytes, at) = one_const(TiConst::Str("ab".to_string()));
+    bytes[at + 1..at + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(rejected(&bytes).starts_with("truncated bytecode file"));
+  }
+}
@@ -0,0 +1,242 @@
+use crate::{build_ti_error, error::error::TiError};
+
+use super::bytecode::{ti_bytecode, TiCapture, TiConst, TiFnProto};
+
+/// What an operand of an instruction refers to.
+#[derive(Clone, Copy)]
+enum Operand {
+  Value,
+  Fn,
+  Name,
+  Type,
+  Trait,
+  Local,
+  Upvalue,
+  Target,
+  Count,
+}
+
+fn layout(op: u16) -> &'static [Operand] {
+  use Operand::*;
+  match op {
+    ti_bytecode::LOAD_CONST => &[Value],
+    ti_bytecode::BUILD_FUNC => &[Fn],
+    ti_bytecode::LOAD_SESSION | ti_bytecode::STORE_SESSION | ti_bytecode::DEFINE_SESSION
+    | ti_bytecode::GET_FIELD | ti_bytecode::SET_FIELD => &[Name],
+    ti_bytecode::JMP | ti_bytecode::JNZ | ti_bytecode::JZ => &[Target],
+    ti_bytecode::LOAD_LOCAL | ti_bytecode::STORE_LOCAL | ti_bytecode::CLOSE_UPVALUES | ti_bytecode::ITER_NEXT => &[Local],
+    ti_bytecode::LOAD_UPVALUE | ti_bytecode::STORE_UPVALUE => &[Upvalue],
+    ti_bytecode::CALL | ti_bytecode::BUILD_LIST | ti_bytecode::BUILD_MAP | ti_bytecode::GET_PAYLOAD | ti_bytecode::BUILD_RANGE => &[Count],
+    ti_bytecode::BUILD_STRUCT | ti_bytecode::CALL_METHOD => &[Name, Count],
+    ti_bytecode::LOAD_PATH | ti_bytecode::IS_VARIANT => &[Name, Name],
+    ti_bytecode::CALL_PATH | ti_bytecode::IMPL => &[Name, Name, Count],
+    ti_bytecode::DEFINE_TYPE => &[Type],
+    ti_bytecode::DEFINE_TRAIT => &[Trait, Count],
+    _ => &[],
+  }
+}
+
+/// Checks the main chunk `proto` and every function compiled into it before
+/// they run: opcodes are known and have all their operands, constants,
+/// local slots and upvalues exist and have the right kind, the variables a
+/// function captures exist in the one building it, jumps land on
+/// instructions, and every path reaches `RETURN` (`EXIT` for a main chunk)
+/// with just the result on the stack, the same stack depth wherever paths
+/// meet.
+pub fn verify(proto: &TiFnProto) -> Result<(), TiError> {
+  if !proto.upvalues.is_empty() {
+    build_ti_error!(@err Verify, "invalid bytecode in `{}`: a main chunk cannot capture variables.", proto.def.name)
+  }
+  verify_fn(proto)
+}
+
+fn verify_fn(proto: &TiFnProto) -> Result<(), TiError> {
+  let name = &proto.def.name;
+  let code = proto.bytecode.code();
+  let consts = proto.bytecode.consts();
+  if proto.locals < proto.def.arguments.len() {
+    build_ti_error!(@err Verify, "invalid bytecode in `{}`: {} locals for {} parameters.", name, proto.locals, proto.def.arguments.len())
+  }
+  let mut starts = vec! [false; code.len()];
+  let mut offset = 0;
+  while offset < code.len() {
+    let op = code[offset];
+    let operands = match ti_bytecode::operands(op) {
+      Some(operands) => operands,
+      None => build_ti_error!(@err Verify, "invalid bytecode in `{}`: unknown opcode {} at {:04}.", name, op, offset),
+    };
+    let args = match code.get(offset + 1..offset + 1 + operands) {
+      Some(args) => args,
+      None => build_ti_error!(@err Verify, "invalid bytecode in `{}`: `{}` at {:04} is cut short.", name, ti_bytecode::mnemonic(op), offset),
+    };
+    for (kind, arg) in layout(op).iter().zip(args) {
+      let value = consts.get(*arg as usize);
+      let ok = match kind {
+        Operand::Value => matches!(value, Some(TiConst::Num(_) | TiConst::Str(_) | TiConst::Bool(_))),
+        Operand::Fn => matches!(value, Some(TiConst::Fn(_))),
+        Operand::Name => matches!(value, Some(TiConst::Str(_))) || (op == ti_bytecode::IMPL && *arg == ti_bytecode::NO_NAME),
+        Operand::Type => matches!(value, Some(TiConst::Struct(_) | TiConst::Enum(_))),
+        Operand::Trait => matches!(value, Some(TiConst::Trait(_))),
+        Operand::Local => (*arg as usize) < proto.locals,
+        Operand::Upvalue => (*arg as usize) < proto.upvalues.len(),
+        Operand::Target | Operand::Count => true,
+      };
+      if !ok {
+        build_ti_error!(@err Verify, "invalid bytecode in `{}`: operand {} of `{}` at {:04} is not a valid {}.", name, arg, ti_bytecode::mnemonic(op), offset, describe(*kind))
+      }
+    }
+    starts[offset] = true;
+    offset += 1 + operands;
+  }
+
+  let mut depths: Vec<Option<usize>> = vec! [None; code.len()];
+  let mut paths = vec! [(0, 0)];
+  while let Some((offset, depth)) = paths.pop() {
+    if offset >= code.len() {
+      build_ti_error!(@err Verify, "invalid bytecode in `{}`: control reaches the end of the code.", name)
+    }
+    if !starts[offset] {
+      build_ti_error!(@err Verify, "invalid bytecode in `{}`: jump into the middle of an instruction at {:04}.", name, offset)
+    }
+    match depths[offset] {
+      Some(known) if known == depth => continue,
+      Some(known) => {
+        build_ti_error!(@err Verify, "invalid bytecode in `{}`: paths meet at {:04} with {} and {} values on the stack.", name, offset, known, depth)
+      }
+      None => depths[offset] = Some(depth),
+    }
+    let op = code[offset];
+    let operands = ti_bytecode::operands(op).unwrap_or(0);
+    let args = &code[offset + 1..offset + 1 + operands];
+    let (pops, pushes) = ti_bytecode::effect(op, args);
+    if depth < pops {
+      build_ti_error!(@err Verify, "invalid bytecode in `{}`: `{}` at {:04} takes {} values from a stack of {}.", name, ti_bytecode::mnemonic(op), offset, pops, depth)
+    }
+    let next = depth - pops + pushes;
+    match op {
+      ti_bytecode::RETURN | ti_bytecode::EXIT if depth != 1 => {
+        build_ti_error!(@err Verify, "invalid bytecode in `{}`: `{}` at {:04} leaves {} values on the stack.", name, ti_bytecode::mnemonic(op), offset, depth - 1)
+      }
+      ti_bytecode::RETURN | ti_bytecode::EXIT | ti_bytecode::MATCH_FAIL => {}
+      ti_bytecode::JMP => paths.push((args[0] as usize, next)),
+      ti_bytecode::JZ | ti_bytecode::JNZ => {
+        paths.push((args[0] as usize, next));
+        paths.push((offset + 1 + operands, next));
+      }
+      _ => paths.push((offset + 1 + operands, next)),
+    }
+  }
+
+  for value in consts {
+    if let TiConst::Fn(inner) = value {
+      for capture in &inner.upvalues {
+        let ok = match capture {
+          TiCapture::Local(slot) => (*slot as usize) < proto.locals,
+          TiCapture::Upvalue(idx) => (*idx as usize) < proto.upvalues.len(),
+        };
+        if !ok {
+          build_ti_error!(@err Verify, "invalid bytecode in `{}`: `{}` captures {:?}, which `{}` does not have.", name, inner.def.name, capture, name)
+        }
+      }
+      verify_fn(inner)?;
+    }
+  }
+  Ok(())
+}
+
+fn describe(kind: Operand) -> &'static str {
+  match kind {
+    Operand::Value => "constant",
+    Operand::Fn => "function",
+    Operand::Name => "name",
+    Operand::Type => "type",
+    Operand::Trait => "trait",
+    Operand::Local => "local slot",
+    Operand::Upvalue => "upvalue",
+    Operand::Target => "jump target",
+    Operand::Count => "count",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::rc::Rc;
+
+  use super::*;
+  use crate::{
+    backend::bytecode::bytecode::TiByteCode,
+    driver::driver::{TiDriver, TiSource},
+    error::error::TiErrorKind,
+    frontend::parser::ast::FnDef,
+  };
+  use ti_bytecode::*;
+
+  fn proto(code: Vec<u16>, consts: Vec<TiConst>) -> TiFnProto {
+    let def = FnDef { name: Rc::new("f".to_string()), arguments: vec! [] };
+    TiFnProto { def, locals: 1, upvalues: vec! [], bytecode: TiByteCode::new(code, consts, vec! []) }
+  }
+
+  /// The message `verify` rejects the chunk with, without its prefix.
+  fn rejected(proto: &TiFnProto) -> String {
+    let err = verify(proto).expect_err("the chunk was accepted");
+    assert_eq!(err.kind, TiErrorKind::Verify);
+    err.message.trim_start_matches("invalid bytecode in `f`: ").to_string()
+  }
+
+  #[test]
+  fn compiled_programs_are_accepted() {
+    let src = "fn main() {\n    let xs = [1, 2, 3]\n    let f = fn(x) => x * 2\n    for x in xs {\n        if x > 1 { println(\"{}\", f(x)) }\n    }\n}\n";
+    let main = TiDriver::new(false).compile(&TiSource::new("test.ti".to_string(), src.to_string())).unwrap();
+    assert!(verify(&main).is_ok());
+  }
+
+  #[test]
+  fn unknown_opcodes_are_rejected() {
+    assert_eq!(rejected(&proto(vec! [BUILD_LIST, 0, 999, EXIT], vec! [])), "unknown opcode 999 at 0002.");
+  }
+
+  #[test]
+  fn instructions_cut_short_are_rejected() {
+    assert_eq!(rejected(&proto(vec! [BUILD_LIST, 0, EXIT, LOAD_CONST], vec! [])), "`LOAD_CONST` at 0003 is cut short.");
+  }
+
+  #[test]
+  fn operands_of_the_wrong_kind_are_rejected() {
+    assert_eq!(rejected(&proto(vec! [LOAD_CONST, 1, EXIT], vec! [TiConst::Num(1.0)])), "operand 1 of `LOAD_CONST` at 0000 is not a valid constant.");
+    assert_eq!(rejected(&proto(vec! [BUILD_FUNC, 0, EXIT], vec! [TiConst::Num(1.0)])), "operand 0 of `BUILD_FUNC` at 0000 is not a valid function.");
+    assert_eq!(rejected(&proto(vec! [LOAD_LOCAL, 1, EXIT], vec! [])), "operand 1 of `LOAD_LOCAL` at 0000 is not a valid local slot.");
+  }
+
+  #[test]
+  fn jumps_into_an_instruction_are_rejected() {
+    assert_eq!(rejected(&proto(vec! [JMP, 3, BUILD_LIST, 0, EXIT], vec! [])), "jump into the middle of an instruction at 0003.");
+  }
+
+  #[test]
+  fn unbalanced_stacks_are_rejected() {
+    assert_eq!(rejected(&proto(vec! [POP, BUILD_LIST, 0, EXIT], vec! [])), "`POP` at 0000 takes 1 values from a stack of 0.");
+    assert_eq!(rejected(&proto(vec! [BUILD_LIST, 0, BUILD_LIST, 0, EXIT], vec! [])), "`EXIT` at 0004 leaves 1 values on the stack.");
+    let code = vec! [LOAD_LOCAL, 0, JZ, 6, BUILD_LIST, 0, EXIT];
+    assert_eq!(rejected(&proto(code, vec! [])), "paths meet at 0006 with 1 and 0 values on the stack.");
+  }
+
+  #[test]
+  fn falling_off_the_end_is_rejected() {
+    assert_eq!(rejected(&proto(vec! [BUILD_LIST, 0], vec! [])), "control reaches the end of the code.");
+  }
+
+  #[test]
+  fn captures_the_builder_lacks_are_rejected() {
+    let mut inner = proto(vec! [LOAD_UPVALUE, 0, RETURN], vec! []);
+    inner.upvalues = vec! [TiCapture::Local(3)];
+    let outer = proto(vec! [BUILD_FUNC, 0, EXIT], vec! [TiConst::Fn(Rc::new(inner))]);
+    assert_eq!(rejected(&outer), "`f` captures Local(3), which `f` does not have.");
+  }
+
+  #[test]
+  fn functions_in_the_constants_are_verified() {
+    let inner = proto(vec! [POP, RETURN], vec! []);
+    let outer = proto(vec! [BUILD_FUNC, 0, EXIT], vec! [TiConst::Fn(Rc::new(inner))]);
+    assert_eq!(rejected(&outer), "`POP` at 0000 takes 1 values from a stack of 0.");
+  }
+}
@@ -1,18 +1,951 @@
-use crate::frontend::parser::parser::Parser;
+use std::rc::Rc;
+
+use crate::{
+  backend::bytecode::bytecode::{ti_bytecode, TiByteCodeBuilder, TiCapture, TiConst, TiFnProto},
+  build_ti_error,
+  error::error::TiError,
+  frontend::{
+    lexer::token::TokenAt,
+    parser::{
+      ast::{AstBlock, AstExpr, AstNode, AstPattern, FnDef},
+      parser::Parser,
+    },
+  },
+};
+
+/// A local slot of the function being compiled.
+struct TiLocal {
+  /// temporaries are named `""`
+  name: Rc<String>,
+  /// whether a nested function uses it, which means its upvalue has to be
+  /// closed when the scope ends
+  captured: bool,
+  /// unset while only the functions defined before its declaration, which
+  /// run after it, may see it
+  declared: bool,
+}
+
+/// A loop being compiled, which `break` and `continue` leave.
+struct TiLoop {
+  label: Option<Rc<String>>,
+  /// the offset of its condition, where `continue` jumps back to
+  start: usize,
+  /// the number of values on the stack and of locals as it starts
+  stack: usize,
+  locals: usize,
+  /// the jumps of its `break`s, patched to its end
+  breaks: Vec<usize>,
+}
+
+/// A place an assignment stores into, with the index of each item on the
+/// way to it already worked out into a local slot.
+enum TiPlace {
+  /// a variable, with the span of its name
+  Var(Rc<String>, TokenAt),
+  Field(Box<TiPlace>, Rc<String>),
+  /// the item at the index in the slot, with the span of its `[i]`
+  Index(Box<TiPlace>, u16, TokenAt),
+}
+
+/// The function being compiled: its code, its local slots and the variables
+/// it captures.
+struct TiFnState {
+  def: FnDef,
+  builder: TiByteCodeBuilder,
+  /// the slot of each visible local
+  locals: Vec<TiLocal>,
+  max_locals: usize,
+  upvalues: Vec<TiCapture>,
+  /// the loops around the code being compiled, innermost last
+  loops: Vec<TiLoop>,
+  /// the number of enclosing blocks, `let`s outside of any block of the
+  /// main chunk define session variables
+  depth: usize,
+}
+
+impl TiFnState {
+  fn new(def: FnDef, depth: usize) -> Self {
+    Self { def, builder: TiByteCodeBuilder::new(), locals: vec! [], max_locals: 0, upvalues: vec! [], loops: vec! [], depth }
+  }
+}
 
 pub struct Codegen {
   parser: Parser,
+  fns: Vec<TiFnState>,
 }
 
 impl Codegen {
   pub fn new(parser: Parser) -> Self {
-    Self { parser }
+    Self { parser, fns: vec! [] }
   }
 }
 
 impl Codegen {
-  // this while be replaced with the struct `TiBytecode`
-  pub fn bytecode(&self) -> String {
-    format!("{:#?}\n{:#?}", self.parser.ast, self.parser.fn_def)
+  /// Compiles the parsed program into the main chunk `<main>`, which leaves
+  /// the value of the program on the stack and ends with `EXIT`.
+  pub fn bytecode(&mut self) -> Result<TiFnProto, TiError> {
+    let ast = self.parser.ast.clone();
+    let def = FnDef { name: Rc::new("<main>".to_string()), arguments: vec! [] };
+    self.fns.push(TiFnState::new(def, 0));
+    let res = self.compile_stmt(ast, true);
+    self.emit(ti_bytecode::EXIT, &[]);
+    let main = self.finish();
+    res.map(|_| main)
+  }
+
+  fn state(&mut self) -> &mut TiFnState {
+    self.fns.last_mut().unwrap()
+  }
+
+  fn emit(&mut self, op: u16, operands: &[u16]) {
+    self.state().builder.emit(op, operands)
+  }
+
+  /// Emits `op` marked with the span `at` of the expr it runs, for the
+  /// errors it may raise, rather than with the span of the statement.
+  fn emit_at(&mut self, op: u16, operands: &[u16], at: TokenAt) {
+    self.marked(at, |this| this.emit(op, operands))
+  }
+
+  /// Emits the jump `op` marked with the span `at`, as `emit_at` does.
+  fn emit_jump_at(&mut self, op: u16, at: TokenAt) -> usize {
+    self.marked(at, |this| this.state().builder.emit_jump(op))
+  }
+
+  /// Runs `emit` with what it emits marked with the span `at`, then goes
+  /// back to the span it was marked with before.
+  fn marked<T>(&mut self, at: TokenAt, emit: impl FnOnce(&mut Self) -> T) -> T {
+    let outer = self.state().builder.span();
+    self.state().builder.mark(at);
+    let res = emit(self);
+    if let Some(outer) = outer {
+      self.state().builder.mark(outer);
+    }
+    res
+  }
+
+  fn name(&mut self, name: &str) -> Result<u16, TiError> {
+    self.state().builder.add_name(name)
+  }
+
+  fn finish(&mut self) -> TiFnProto {
+    let state = self.fns.pop().unwrap();
+    TiFnProto { def: state.def, locals: state.max_locals, upvalues: state.upvalues, bytecode: state.builder.build() }
+  }
+
+  fn is_session(&self) -> bool {
+    self.fns.len() == 1 && self.fns[0].depth == 0
+  }
+
+  fn declare(&mut self, name: Rc<String>) -> Result<u16, TiError> {
+    let state = self.state();
+    let slot = count(state.locals.len(), "local variables")?;
+    state.locals.push(TiLocal { name, captured: false, declared: true });
+    state.max_locals = state.max_locals.max(state.locals.len());
+    Ok(slot)
+  }
+
+  /// Takes the slot of a variable declared further on in the scope, which
+  /// the functions nested before its declaration can already capture.
+  fn declare_later(&mut self, name: Rc<String>) -> Result<u16, TiError> {
+    let slot = self.declare(name)?;
+    self.state().locals[slot as usize].declared = false;
+    Ok(slot)
+  }
+
+  fn temp(&mut self) -> Result<u16, TiError> {
+    self.declare(Rc::new(String::new()))
+  }
+
+  fn resolve(&mut self, name: &Rc<String>) -> Option<u16> {
+    self.state().locals.iter().rposition(|local| local.declared && local.name == *name).map(|slot| slot as u16)
+  }
+
+  /// The upvalue of the function at `idx` in `fns` through which it sees
+  /// `name`, a local of one of the functions around it.
+  fn resolve_upvalue(&mut self, idx: usize, name: &Rc<String>) -> Result<Option<u16>, TiError> {
+    if idx == 0 {
+      return Ok(None);
+    }
+    let parent = &mut self.fns[idx - 1];
+    let found = parent.locals.iter().rposition(|local| local.declared && local.name == *name)
+      .or_else(|| parent.locals.iter().position(|local| !local.declared && local.name == *name));
+    let capture = match found {
+      Some(slot) => {
+        parent.locals[slot].captured = true;
+        TiCapture::Local(slot as u16)
+      }
+      None => match self.resolve_upvalue(idx - 1, name)? {
+        Some(upvalue) => TiCapture::Upvalue(upvalue),
+        None => return Ok(None),
+      },
+    };
+    let upvalues = &mut self.fns[idx].upvalues;
+    let upvalue = match upvalues.iter().position(|upvalue| *upvalue == capture) {
+      Some(upvalue) => upvalue,
+      None => {
+        upvalues.push(capture);
+        upvalues.len() - 1
+      }
+    };
+    count(upvalue, "captured variables").map(Some)
+  }
+
+  /// Drops the locals declared since the scope had `len` of them, closing
+  /// the upvalues of the ones a function captured.
+  fn end_scope(&mut self, len: usize) {
+    let state = self.state();
+    if state.locals[len..].iter().any(|local| local.captured) {
+      state.builder.emit(ti_bytecode::CLOSE_UPVALUES, &[len as u16]);
+    }
+    state.locals.truncate(len)
+  }
+
+  /// Drops the values on the stack above the first `stack` ones but the one
+  /// on top.
+  fn keep_top(&mut self, stack: usize) -> Result<(), TiError> {
+    let extra = self.state().builder.stack() - stack - 1;
+    if extra > 0 {
+      let len = self.state().locals.len();
+      let value = self.temp()?;
+      self.emit(ti_bytecode::STORE_LOCAL, &[value]);
+      for _ in 0..=extra {
+        self.emit(ti_bytecode::POP, &[]);
+      }
+      self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+      self.end_scope(len);
+    }
+    Ok(())
+  }
+
+  /// The loop a `break` or `continue` naming `label` leaves.
+  fn find_loop(&mut self, label: &Option<Rc<String>>, keyword: &str) -> Result<usize, TiError> {
+    let found = self.state().loops.iter().rposition(|lp| label.is_none() || lp.label == *label);
+    match found {
+      Some(idx) => Ok(idx),
+      None => build_ti_error!(@err Compile, "`{}` outside of a loop.", keyword),
+    }
+  }
+
+  /// Closes the upvalues of the locals declared since the loop at `idx`
+  /// started, as a jump is about to leave their scope.
+  fn close_loop(&mut self, idx: usize) {
+    let locals = self.state().loops[idx].locals;
+    if self.state().locals.len() > locals {
+      self.emit(ti_bytecode::CLOSE_UPVALUES, &[locals as u16]);
+    }
   }
-}
\ No newline at end of file
+
+  fn unit(&mut self) {
+    self.emit(ti_bytecode::BUILD_LIST, &[0])
+  }
+
+  fn compile_block(&mut self, ast_block: AstBlock, want: bool) -> Result<(), TiError> {
+    let len = self.state().locals.len();
+    self.state().depth += 1;
+    let stmts = ast_block.block.len();
+    if stmts == 0 && want {
+      self.unit();
+    }
+    // the functions of a block get their slots as it starts, so the ones
+    // before can call them
+    for stmt in &ast_block.block {
+      if let AstNode::Fn(fdef, _) = stmt {
+        self.unit();
+        let slot = self.declare_later(fdef.name.clone())?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[slot]);
+        self.emit(ti_bytecode::POP, &[]);
+      }
+    }
+    for (idx, (stmt, at)) in ast_block.block.into_iter().zip(ast_block.at).enumerate() {
+      self.state().builder.mark(at);
+      self.compile_stmt(stmt, want && idx + 1 == stmts)?;
+    }
+    self.state().depth -= 1;
+    self.end_scope(len);
+    Ok(())
+  }
+
+  /// Compiles `fbody` into a function taking the parameters of `fdef`,
+  /// which live in the first slots.
+  fn compile_fn(&mut self, fdef: FnDef, fbody: AstBlock) -> Result<Rc<TiFnProto>, TiError> {
+    self.fns.push(TiFnState::new(fdef.clone(), 1));
+    let mut res = Ok(());
+    for farg in fdef.arguments {
+      if let Err(err) = self.declare(farg.name) {
+        res = Err(err);
+      }
+    }
+    let res = res.and_then(|_| self.compile_block(fbody, true));
+    self.emit(ti_bytecode::RETURN, &[]);
+    let proto = self.finish();
+    res.map(|_| Rc::new(proto))
+  }
+
+  fn build_fn(&mut self, fdef: FnDef, fbody: AstBlock) -> Result<(), TiError> {
+    let proto = self.compile_fn(fdef, fbody)?;
+    let idx = self.state().builder.add_const(TiConst::Fn(proto))?;
+    self.emit(ti_bytecode::BUILD_FUNC, &[idx]);
+    Ok(())
+  }
+
+  /// Binds the value on top of the stack to a new variable `name`, leaving
+  /// a copy of it when `want` is set.
+  fn bind(&mut self, name: Rc<String>, want: bool) -> Result<(), TiError> {
+    if self.is_session() {
+      let name = self.name(&name)?;
+      self.emit(ti_bytecode::DEFINE_SESSION, &[name]);
+      if want {
+        self.emit(ti_bytecode::LOAD_SESSION, &[name]);
+      }
+    } else {
+      let slot = self.declare(name)?;
+      self.emit(ti_bytecode::STORE_LOCAL, &[slot]);
+      if !want {
+        self.emit(ti_bytecode::POP, &[]);
+      }
+    }
+    Ok(())
+  }
+
+  fn compile_stmt(&mut self, ast_node: AstNode, want: bool) -> Result<(), TiError> {
+    match ast_node {
+      AstNode::Program(program) => {
+        let mut stmts: Vec<_> = program.program.into_iter().zip(program.at).collect();
+        stmts.sort_by_key(|(stmt, _)| stmt.hoisting());
+        for (stmt, at) in stmts {
+          self.state().builder.mark(at);
+          self.compile_stmt(stmt, false)?;
+        }
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Fn(fdef, fbody) => {
+        let name = fdef.name.clone();
+        let later = self.state().locals.iter().rposition(|local| !local.declared && local.name == name);
+        self.build_fn(fdef, fbody)?;
+        // given a slot by the block around it
+        match later {
+          Some(slot) => {
+            self.state().locals[slot].declared = true;
+            self.emit(ti_bytecode::STORE_LOCAL, &[slot as u16]);
+            if !want {
+              self.emit(ti_bytecode::POP, &[]);
+            }
+          }
+          None => self.bind(name, want)?,
+        }
+      }
+      AstNode::Impl(sname, tname, items) => {
+        let mut len = 0;
+        for item in items {
+          if let AstNode::Fn(fdef, fbody) = item {
+            self.build_fn(fdef, fbody)?;
+            len += 1;
+          }
+        }
+        let sname = self.name(&sname)?;
+        let tname = match tname {
+          Some(tname) => self.name(&tname)?,
+          None => ti_bytecode::NO_NAME,
+        };
+        let len = count(len, "methods")?;
+        self.emit(ti_bytecode::IMPL, &[sname, tname, len]);
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Trait(mut tdef) => {
+        let mut len = 0;
+        for field in tdef.fields.iter_mut() {
+          if let Some(fbody) = field.default.take() {
+            self.build_fn(field.body.clone(), fbody)?;
+            len += 1;
+          }
+        }
+        let idx = self.state().builder.add_const(TiConst::Trait(tdef))?;
+        let len = count(len, "methods")?;
+        self.emit(ti_bytecode::DEFINE_TRAIT, &[idx, len]);
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Struct(sdef) => {
+        let idx = self.state().builder.add_const(TiConst::Struct(sdef))?;
+        self.emit(ti_bytecode::DEFINE_TYPE, &[idx]);
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Enum(edef) => {
+        let idx = self.state().builder.add_const(TiConst::Enum(edef))?;
+        self.emit(ti_bytecode::DEFINE_TYPE, &[idx]);
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Let(name, value) if !self.is_session() => {
+        // the functions in the value may use the variable
+        let slot = self.declare_later(name)?;
+        match value {
+          Some(v) => self.compile_expr(v)?,
+          None => self.unit(),
+        }
+        self.state().locals[slot as usize].declared = true;
+        self.emit(ti_bytecode::STORE_LOCAL, &[slot]);
+        self.emit(ti_bytecode::POP, &[]);
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Let(name, value) => {
+        match value {
+          Some(v) => self.compile_expr(v)?,
+          None => self.unit(),
+        }
+        self.bind(name, false)?;
+        if want {
+          self.unit();
+        }
+      }
+      AstNode::Expr(expr) => {
+        self.compile_expr(expr)?;
+        if !want {
+          self.emit(ti_bytecode::POP, &[]);
+        }
+      }
+      AstNode::Empty => {
+        if want {
+          self.unit();
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn compile_binary(&mut self, x: AstExpr, y: AstExpr, op: u16, at: TokenAt) -> Result<(), TiError> {
+    self.compile_expr(x)?;
+    self.compile_expr(y)?;
+    self.emit_at(op, &[], at);
+    Ok(())
+  }
+
+  /// Compiles `x && y` when `jump` is `JZ` and `x || y` when it's `JNZ`,
+  /// leaving `y` unrun once `x` decides. `AND` and `OR` run both operands,
+  /// they're only kept for the bytecode compiled before.
+  fn compile_logic(&mut self, x: AstExpr, y: AstExpr, jump: u16, at: TokenAt) -> Result<(), TiError> {
+    let decided_value = jump == ti_bytecode::JNZ;
+    self.compile_expr(x)?;
+    let x_jump = self.emit_jump_at(jump, at);
+    let stack = self.state().builder.stack();
+    self.compile_expr(y)?;
+    let y_jump = self.emit_jump_at(jump, at);
+    let idx = self.state().builder.add_const(TiConst::Bool(!decided_value))?;
+    self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+    let end_jump = self.state().builder.emit_jump(ti_bytecode::JMP);
+    self.state().builder.patch_jump(x_jump)?;
+    self.state().builder.patch_jump(y_jump)?;
+    self.state().builder.set_stack(stack);
+    let idx = self.state().builder.add_const(TiConst::Bool(decided_value))?;
+    self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+    self.state().builder.patch_jump(end_jump)?;
+    Ok(())
+  }
+
+  /// Compiles `target op= value`, which stores the result into `target`, a
+  /// variable or a field or an item of one, and leaves it on the stack.
+  fn compile_compound(&mut self, target: AstExpr, value: AstExpr, op: u16, at: TokenAt) -> Result<(), TiError> {
+    match target {
+      AstExpr::Var(name, slot, var_at) => {
+        self.compile_binary(AstExpr::Var(name.clone(), slot, var_at), value, op, at)?;
+        self.store_var(name)?;
+      }
+      place => {
+        let len = self.state().locals.len();
+        let place = self.pin_place(place)?;
+        self.load_place(&place)?;
+        self.compile_expr(value)?;
+        self.emit_at(op, &[], at);
+        let res = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[res]);
+        self.store_place(&place)?;
+        self.emit(ti_bytecode::LOAD_LOCAL, &[res]);
+        self.end_scope(len);
+      }
+    }
+    Ok(())
+  }
+
+  fn compile_args(&mut self, args: Vec<AstExpr>) -> Result<u16, TiError> {
+    let argc = count(args.len(), "arguments")?;
+    for arg in args {
+      self.compile_expr(arg)?;
+    }
+    Ok(argc)
+  }
+
+  /// Pushes the value of an expression.
+  fn compile_expr(&mut self, ast_expr: AstExpr) -> Result<(), TiError> {
+    match ast_expr {
+      AstExpr::Add(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::ADD, at)?,
+      AstExpr::Sub(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::SUB, at)?,
+      AstExpr::Mul(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::MUL, at)?,
+      AstExpr::Div(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::DIV, at)?,
+      AstExpr::Mod(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::MOD, at)?,
+      AstExpr::Pow(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::POW, at)?,
+      AstExpr::BitAnd(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::BIT_AND, at)?,
+      AstExpr::BitOr(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::BIT_OR, at)?,
+      AstExpr::BitXor(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::BIT_XOR, at)?,
+      AstExpr::Shl(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::SHL, at)?,
+      AstExpr::Shr(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::SHR, at)?,
+      AstExpr::AddEq(x, y, at) => self.compile_compound(*x, *y, ti_bytecode::ADD, at)?,
+      AstExpr::SubEq(x, y, at) => self.compile_compound(*x, *y, ti_bytecode::SUB, at)?,
+      AstExpr::MulEq(x, y, at) => self.compile_compound(*x, *y, ti_bytecode::MUL, at)?,
+      AstExpr::DivEq(x, y, at) => self.compile_compound(*x, *y, ti_bytecode::DIV, at)?,
+      AstExpr::ModEq(x, y, at) => self.compile_compound(*x, *y, ti_bytecode::MOD, at)?,
+      AstExpr::Eq(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::EQ, at)?,
+      AstExpr::Neq(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::NEQ, at)?,
+      AstExpr::Grt(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::GRT, at)?,
+      AstExpr::Les(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::LES, at)?,
+      AstExpr::Geq(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::GEQ, at)?,
+      AstExpr::Leq(x, y, at) => self.compile_binary(*x, *y, ti_bytecode::LEQ, at)?,
+      AstExpr::And(x, y, at) => self.compile_logic(*x, *y, ti_bytecode::JZ, at)?,
+      AstExpr::Or(x, y, at) => self.compile_logic(*x, *y, ti_bytecode::JNZ, at)?,
+      AstExpr::Not(x, at) => {
+        self.compile_expr(*x)?;
+        self.emit_at(ti_bytecode::NOT, &[], at);
+      }
+      AstExpr::Neg(x, at) => {
+        self.compile_expr(*x)?;
+        self.emit_at(ti_bytecode::NEG, &[], at);
+      }
+      AstExpr::Assign(n, _, v, _) => {
+        self.compile_expr(*v)?;
+        self.store_var(n)?;
+      }
+      AstExpr::AssignField(object, field, v) => self.compile_assign(AstExpr::Field(object, field), *v)?,
+      AstExpr::AssignIndex(object, index, v, at) => self.compile_assign(AstExpr::Index(object, index, at), *v)?,
+      AstExpr::Field(object, field) => {
+        self.compile_expr(*object)?;
+        let field = self.name(&field)?;
+        self.emit(ti_bytecode::GET_FIELD, &[field]);
+      }
+      AstExpr::Index(object, index, at) => {
+        self.compile_expr(*object)?;
+        self.compile_expr(*index)?;
+        self.emit_at(ti_bytecode::GET_INDEX, &[], at);
+      }
+      AstExpr::Try(x) => {
+        // x; TRY; JNZ go_on; RETURN the value; go_on:
+        self.compile_expr(*x)?;
+        self.emit(ti_bytecode::TRY, &[]);
+        let go_on = self.state().builder.emit_jump(ti_bytecode::JNZ);
+        let stack = self.state().builder.stack();
+        self.keep_top(0)?;
+        self.emit(ti_bytecode::RETURN, &[]);
+        self.state().builder.patch_jump(go_on)?;
+        self.state().builder.set_stack(stack);
+      }
+      AstExpr::FnCall(mem, args) => match *mem {
+        AstExpr::Path(name, item) => {
+          let argc = self.compile_args(args)?;
+          let name = self.name(&name)?;
+          let item = self.name(&item)?;
+          self.emit(ti_bytecode::CALL_PATH, &[name, item, argc]);
+        }
+        AstExpr::Field(object, name) => {
+          let len = self.state().locals.len();
+          let place = if object.is_place() {
+            let place = self.pin_place(*object)?;
+            self.load_place(&place)?;
+            Some(place)
+          } else {
+            self.compile_expr(*object)?;
+            None
+          };
+          let argc = self.compile_args(args)?;
+          let name = self.name(&name)?;
+          self.emit(ti_bytecode::CALL_METHOD, &[name, argc]);
+          // hand a `self` modified by the method back to the caller
+          let res = self.temp()?;
+          self.emit(ti_bytecode::STORE_LOCAL, &[res]);
+          self.emit(ti_bytecode::POP, &[]);
+          match place {
+            Some(place) => self.store_place(&place)?,
+            None => self.emit(ti_bytecode::POP, &[]),
+          }
+          self.emit(ti_bytecode::LOAD_LOCAL, &[res]);
+          self.end_scope(len);
+        }
+        mem => {
+          self.compile_expr(mem)?;
+          let argc = self.compile_args(args)?;
+          self.emit(ti_bytecode::CALL, &[argc]);
+        }
+      },
+      AstExpr::Var(x, _, at) => match self.resolve(&x) {
+        Some(slot) => self.emit(ti_bytecode::LOAD_LOCAL, &[slot]),
+        None => match self.resolve_upvalue(self.fns.len() - 1, &x)? {
+          Some(upvalue) => self.emit(ti_bytecode::LOAD_UPVALUE, &[upvalue]),
+          None => {
+            let x = self.name(&x)?;
+            self.emit_at(ti_bytecode::LOAD_SESSION, &[x], at);
+          }
+        },
+      },
+      AstExpr::Path(name, item) => {
+        let name = self.name(&name)?;
+        let item = self.name(&item)?;
+        self.emit(ti_bytecode::LOAD_PATH, &[name, item]);
+      }
+      AstExpr::LiteralNum(x) => {
+        let idx = self.state().builder.add_const(TiConst::Num(x))?;
+        self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+      }
+      AstExpr::LiteralStr(x) => {
+        let idx = self.state().builder.add_const(TiConst::Str(x))?;
+        self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+      }
+      AstExpr::LiteralBool(x) => {
+        let idx = self.state().builder.add_const(TiConst::Bool(x))?;
+        self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+      }
+      AstExpr::LiteralUnit(x) | AstExpr::LiteralArray(x) => {
+        let len = self.compile_args(x)?;
+        self.emit(ti_bytecode::BUILD_LIST, &[len]);
+      }
+      AstExpr::LiteralMap(entries) => {
+        // each entry is set on its own, so a key that can't be one fails
+        // at its own span
+        self.emit(ti_bytecode::BUILD_MAP, &[0]);
+        for (key, value, at) in entries {
+          self.compile_expr(key)?;
+          self.compile_expr(value)?;
+          self.emit_at(ti_bytecode::SET_INDEX, &[], at);
+        }
+      }
+      AstExpr::LiteralStruct(name, fields) => {
+        let len = count(fields.len(), "fields")?;
+        for (fname, expr) in fields {
+          let fname = self.name(&fname)?;
+          self.emit(ti_bytecode::LOAD_CONST, &[fname]);
+          self.compile_expr(expr)?;
+        }
+        let name = self.name(&name)?;
+        self.emit(ti_bytecode::BUILD_STRUCT, &[name, len]);
+      }
+      AstExpr::IfElse(cond, true_case, false_case) => {
+        self.compile_expr(*cond)?;
+        let else_jump = self.state().builder.emit_jump(ti_bytecode::JZ);
+        let stack = self.state().builder.stack();
+        self.compile_block(true_case, true)?;
+        let end_jump = self.state().builder.emit_jump(ti_bytecode::JMP);
+        self.state().builder.patch_jump(else_jump)?;
+        self.state().builder.set_stack(stack);
+        self.compile_block(false_case, true)?;
+        self.state().builder.patch_jump(end_jump)?;
+      }
+      AstExpr::Block(block) => self.compile_block(block, true)?,
+      AstExpr::Lambda(fdef, fbody) => self.build_fn(fdef, Rc::unwrap_or_clone(fbody))?,
+      AstExpr::Match(expr, arms, _) => {
+        let len = self.state().locals.len();
+        self.compile_expr(*expr)?;
+        let value = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[value]);
+        self.emit(ti_bytecode::POP, &[]);
+        let stack = self.state().builder.stack();
+        let mut end_jumps = vec! [];
+        for (pattern, body) in arms {
+          self.state().builder.set_stack(stack);
+          let mut fail_jumps = vec! [];
+          self.compile_test(&pattern, value, &mut vec! [], &mut fail_jumps)?;
+          let arm = self.state().locals.len();
+          self.state().depth += 1;
+          self.compile_bindings(&pattern, value, &mut vec! [])?;
+          self.compile_expr(body)?;
+          self.state().depth -= 1;
+          self.end_scope(arm);
+          end_jumps.push(self.state().builder.emit_jump(ti_bytecode::JMP));
+          for fail_jump in fail_jumps {
+            self.state().builder.patch_jump(fail_jump)?;
+          }
+        }
+        self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+        self.emit(ti_bytecode::MATCH_FAIL, &[]);
+        for end_jump in end_jumps {
+          self.state().builder.patch_jump(end_jump)?;
+        }
+        self.state().builder.set_stack(stack + 1);
+        self.end_scope(len);
+      }
+      AstExpr::While(label, cond, body) => {
+        let state = self.state();
+        let lp = TiLoop { label, start: state.builder.len(), stack: state.builder.stack(), locals: state.locals.len(), breaks: vec! [] };
+        state.loops.push(lp);
+        let res = self.compile_while(*cond, body);
+        let lp = self.state().loops.pop().unwrap();
+        res?;
+        for jump in lp.breaks {
+          self.state().builder.patch_jump(jump)?;
+        }
+      }
+      AstExpr::For(label, name, expr, body) => {
+        let len = self.state().locals.len();
+        self.compile_expr(*expr)?;
+        self.emit(ti_bytecode::ITER, &[]);
+        let iter = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[iter]);
+        self.emit(ti_bytecode::POP, &[]);
+        let state = self.state();
+        let lp = TiLoop { label, start: state.builder.len(), stack: state.builder.stack(), locals: state.locals.len(), breaks: vec! [] };
+        state.loops.push(lp);
+        let res = self.compile_for(name, iter, body);
+        let lp = self.state().loops.pop().unwrap();
+        res?;
+        for jump in lp.breaks {
+          self.state().builder.patch_jump(jump)?;
+        }
+        self.end_scope(len);
+      }
+      AstExpr::Range(start, end, step, inclusive) => {
+        self.compile_expr(*start)?;
+        self.compile_expr(*end)?;
+        self.compile_expr(step.map_or(AstExpr::LiteralNum(1.0), |step| *step))?;
+        self.emit(ti_bytecode::BUILD_RANGE, &[inclusive as u16]);
+      }
+      AstExpr::Return(value) => {
+        let stack = self.state().builder.stack();
+        self.compile_expr(*value)?;
+        self.keep_top(0)?;
+        self.emit(ti_bytecode::RETURN, &[]);
+        // the code after it is not reached, `return` stands for a value there
+        self.state().builder.set_stack(stack + 1);
+      }
+      AstExpr::Break(label, value) => {
+        let idx = self.find_loop(&label, "break")?;
+        let stack = self.state().builder.stack();
+        self.compile_expr(*value)?;
+        let loop_stack = self.state().loops[idx].stack;
+        self.keep_top(loop_stack)?;
+        self.close_loop(idx);
+        let jump = self.state().builder.emit_jump(ti_bytecode::JMP);
+        self.state().loops[idx].breaks.push(jump);
+        self.state().builder.set_stack(stack + 1);
+      }
+      AstExpr::Continue(label) => {
+        let idx = self.find_loop(&label, "continue")?;
+        let stack = self.state().builder.stack();
+        for _ in self.state().loops[idx].stack..stack {
+          self.emit(ti_bytecode::POP, &[]);
+        }
+        self.close_loop(idx);
+        let start = self.state().loops[idx].start;
+        self.state().builder.emit_loop(ti_bytecode::JMP, start)?;
+        self.state().builder.set_stack(stack + 1);
+      }
+    }
+    Ok(())
+  }
+
+  /// Compiles the loop on top of `loops`, which leaves the unit on the stack
+  /// unless a `break` gives it another value.
+  fn compile_while(&mut self, cond: AstExpr, body: AstBlock) -> Result<(), TiError> {
+    let start = self.state().loops.last().unwrap().start;
+    self.compile_expr(cond)?;
+    let end_jump = self.state().builder.emit_jump(ti_bytecode::JZ);
+    self.compile_block(body, false)?;
+    self.state().builder.emit_loop(ti_bytecode::JMP, start)?;
+    self.state().builder.patch_jump(end_jump)?;
+    self.unit();
+    Ok(())
+  }
+
+  /// Compiles the `for` loop on top of `loops`, walking the iterator in slot
+  /// `iter`, which leaves the unit on the stack unless a `break` gives it
+  /// another value.
+  fn compile_for(&mut self, name: Rc<String>, iter: u16, body: AstBlock) -> Result<(), TiError> {
+    let start = self.state().loops.last().unwrap().start;
+    self.emit(ti_bytecode::ITER_NEXT, &[iter]);
+    let end_jump = self.state().builder.emit_jump(ti_bytecode::JZ);
+    let stack = self.state().builder.stack();
+    let len = self.state().locals.len();
+    let slot = self.declare(name)?;
+    self.emit(ti_bytecode::STORE_LOCAL, &[slot]);
+    self.emit(ti_bytecode::POP, &[]);
+    self.compile_block(body, false)?;
+    self.end_scope(len);
+    self.state().builder.emit_loop(ti_bytecode::JMP, start)?;
+    // the unit `ITER_NEXT` gives once the iterator is done is the value
+    self.state().builder.patch_jump(end_jump)?;
+    self.state().builder.set_stack(stack);
+    Ok(())
+  }
+
+  /// Stores the value on top of the stack into variable `name`, keeping it
+  /// on the stack.
+  fn store_var(&mut self, name: Rc<String>) -> Result<(), TiError> {
+    match self.resolve(&name) {
+      Some(slot) => self.emit(ti_bytecode::STORE_LOCAL, &[slot]),
+      None => match self.resolve_upvalue(self.fns.len() - 1, &name)? {
+        Some(upvalue) => self.emit(ti_bytecode::STORE_UPVALUE, &[upvalue]),
+        None => {
+          let name = self.name(&name)?;
+          self.emit(ti_bytecode::STORE_SESSION, &[name]);
+        }
+      },
+    }
+    Ok(())
+  }
+
+  /// Compiles `place = value`, which leaves the value on the stack.
+  fn compile_assign(&mut self, place: AstExpr, value: AstExpr) -> Result<(), TiError> {
+    let len = self.state().locals.len();
+    self.compile_expr(value)?;
+    let value = self.temp()?;
+    self.emit(ti_bytecode::STORE_LOCAL, &[value]);
+    let place = self.pin_place(place)?;
+    self.store_place(&place)?;
+    self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+    self.end_scope(len);
+    Ok(())
+  }
+
+  /// Works out the place `expr`, a variable or a chain of fields and items
+  /// on one, running each index in turn into a new local slot.
+  fn pin_place(&mut self, expr: AstExpr) -> Result<TiPlace, TiError> {
+    match expr {
+      AstExpr::Var(name, _, at) => Ok(TiPlace::Var(name, at)),
+      AstExpr::Field(object, field) => Ok(TiPlace::Field(Box::new(self.pin_place(*object)?), field)),
+      AstExpr::Index(object, index, at) => {
+        let object = self.pin_place(*object)?;
+        self.compile_expr(*index)?;
+        let slot = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[slot]);
+        self.emit(ti_bytecode::POP, &[]);
+        Ok(TiPlace::Index(Box::new(object), slot, at))
+      }
+      _ => build_ti_error!(@err Compile, "invalid left-hand side of assignment."),
+    }
+  }
+
+  /// Pushes the value at `place`.
+  fn load_place(&mut self, place: &TiPlace) -> Result<(), TiError> {
+    match place {
+      TiPlace::Var(name, at) => self.compile_expr(AstExpr::Var(name.clone(), None, *at))?,
+      TiPlace::Field(object, field) => {
+        self.load_place(object)?;
+        let field = self.name(field)?;
+        self.emit(ti_bytecode::GET_FIELD, &[field]);
+      }
+      TiPlace::Index(object, slot, at) => {
+        self.load_place(object)?;
+        self.emit(ti_bytecode::LOAD_LOCAL, &[*slot]);
+        self.emit_at(ti_bytecode::GET_INDEX, &[], *at);
+      }
+    }
+    Ok(())
+  }
+
+  /// Pops the value on top of the stack into `place`; each struct, list or
+  /// map on the way is rebuilt with the new value and stored back.
+  fn store_place(&mut self, place: &TiPlace) -> Result<(), TiError> {
+    match place {
+      TiPlace::Var(name, _) => {
+        self.store_var(name.clone())?;
+        self.emit(ti_bytecode::POP, &[]);
+      }
+      TiPlace::Field(object, field) => {
+        let len = self.state().locals.len();
+        let value = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[value]);
+        self.emit(ti_bytecode::POP, &[]);
+        self.load_place(object)?;
+        self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+        let field = self.name(field)?;
+        self.emit(ti_bytecode::SET_FIELD, &[field]);
+        self.end_scope(len);
+        self.store_place(object)?;
+      }
+      TiPlace::Index(object, slot, at) => {
+        let len = self.state().locals.len();
+        let value = self.temp()?;
+        self.emit(ti_bytecode::STORE_LOCAL, &[value]);
+        self.emit(ti_bytecode::POP, &[]);
+        self.load_place(object)?;
+        self.emit(ti_bytecode::LOAD_LOCAL, &[*slot]);
+        self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+        self.emit_at(ti_bytecode::SET_INDEX, &[], *at);
+        self.end_scope(len);
+        self.store_place(object)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Loads the value matched by a sub-pattern: the payload at `path` of the
+  /// scrutinee in slot `value`.
+  fn load_matched(&mut self, value: u16, path: &[u16]) {
+    self.emit(ti_bytecode::LOAD_LOCAL, &[value]);
+    for idx in path {
+      self.emit(ti_bytecode::GET_PAYLOAD, &[*idx]);
+    }
+  }
+
+  /// Emits the checks of `pattern`, each jumping away when it fails; a
+  /// variant is checked before any of its payload is loaded.
+  fn compile_test(&mut self, pattern: &AstPattern, value: u16, path: &mut Vec<u16>, fail_jumps: &mut Vec<usize>) -> Result<(), TiError> {
+    let literal = match pattern {
+      AstPattern::Wildcard | AstPattern::Binding(_) => return Ok(()),
+      AstPattern::LiteralNum(x) => TiConst::Num(*x),
+      AstPattern::LiteralStr(x) => TiConst::Str(x.clone()),
+      AstPattern::LiteralBool(x) => TiConst::Bool(*x),
+      AstPattern::Variant(name, variant, patterns) => {
+        self.load_matched(value, path);
+        let name = self.name(name)?;
+        let variant = self.name(variant)?;
+        self.emit(ti_bytecode::IS_VARIANT, &[name, variant]);
+        fail_jumps.push(self.state().builder.emit_jump(ti_bytecode::JZ));
+        for (idx, pattern) in patterns.iter().enumerate() {
+          path.push(count(idx, "values")?);
+          self.compile_test(pattern, value, path, fail_jumps)?;
+          path.pop();
+        }
+        return Ok(());
+      }
+    };
+    self.load_matched(value, path);
+    let idx = self.state().builder.add_const(literal)?;
+    self.emit(ti_bytecode::LOAD_CONST, &[idx]);
+    self.emit(ti_bytecode::IS_EQ, &[]);
+    fail_jumps.push(self.state().builder.emit_jump(ti_bytecode::JZ));
+    Ok(())
+  }
+
+  /// Declares the names bound by a matched `pattern` as locals.
+  fn compile_bindings(&mut self, pattern: &AstPattern, value: u16, path: &mut Vec<u16>) -> Result<(), TiError> {
+    match pattern {
+      AstPattern::Binding(name) => {
+        self.load_matched(value, path);
+        self.bind(name.clone(), false)?;
+      }
+      AstPattern::Variant(_, _, patterns) => {
+        for (idx, pattern) in patterns.iter().enumerate() {
+          path.push(count(idx, "values")?);
+          self.compile_bindings(pattern, value, path)?;
+          path.pop();
+        }
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+}
+
+/// Checks that `len` fits in an operand.
+fn count(len: usize, what: &str) -> Result<u16, TiError> {
+  match u16::try_from(len) {
+    Ok(len) if len != u16::MAX => Ok(len),
+    _ => build_ti_error!(@err Compile, "too many {}, the limit is {}.", what, u16::MAX - 1),
+  }
+}
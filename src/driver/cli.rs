@@ -0,0 +1,326 @@
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Write},
+    panic,
+    path::Path,
+    thread,
+};
+
+use colored::Colorize;
+
+use crate::{
+    backend::bytecode::{disasm::disassemble, optimize::MAX_LEVEL, tic::TicFile},
+    error::{
+        error::{TiError, TiErrorKind},
+        render::TiErrorRenderer,
+    },
+    frontend::resolve::resolve::TiPrelude,
+    vm::{types::object_type::TiObj, vm::MAX_CALL_DEPTH},
+};
+
+use super::{
+    driver::{TiDriver, TiSource},
+    repl::TiRepl,
+};
+
+pub const USAGE: &str = "\
+usage: ti <command> [options] <file.ti> [args...]
+       ti run|disasm [options] <file.tic> [args...]
+       ti repl [options]
+
+commands:
+    run       run the entry function of <file.ti>, passing [args...]
+    compile   compile <file.ti> to bytecode, written next to it as <file.tic>
+    disasm    print the bytecode of <file.ti> or <file.tic>
+    check     check <file.ti> without running it
+    tokens    dump the token stream of <file.ti>
+    ast       pretty-print the ast of <file.ti>
+    repl      start an interactive session
+
+options:
+    --entry <name>    entry function of `run` (default: main)
+    --bytecode        compile to bytecode and run it on the bytecode vm
+    -o <path>         output file of `compile`
+    -O[level]         optimize the bytecode, level 0 to 2 (`-O` is `-O1`);
+                      `run` then uses the bytecode vm
+    --no-color        print diagnostics without colors
+    -h, --help        print this message";
+
+/// The stack of the thread running a command, with room for
+/// `MAX_CALL_DEPTH` nested calls in the tree-walking vm, which recurses on
+/// the native stack, even in a debug build.
+pub const STACK_SIZE: usize = 512 << 20;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiCommand {
+    Run,
+    Compile,
+    Disasm,
+    Check,
+    Tokens,
+    Ast,
+    Repl,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct TiCli {
+    pub command: TiCommand,
+    pub file: String,
+    pub entry: String,
+    pub bytecode: bool,
+    pub output: Option<String>,
+    pub opt_level: u8,
+    pub args: Vec<String>,
+    pub color: bool,
+}
+
+impl TiCli {
+    pub fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = match args.next().as_deref() {
+            Some("run") => TiCommand::Run,
+            Some("compile") => TiCommand::Compile,
+            Some("disasm") => TiCommand::Disasm,
+            Some("check") => TiCommand::Check,
+            Some("tokens") => TiCommand::Tokens,
+            Some("ast") => TiCommand::Ast,
+            Some("repl") => TiCommand::Repl,
+            Some("-h" | "--help" | "help") => TiCommand::Help,
+            Some(command) => return Err(format!("unknown command `{}`", command)),
+            None => return Err("missing command".to_string()),
+        };
+        let mut cli = Self {
+            command,
+            file: String::new(),
+            entry: "main".to_string(),
+            bytecode: false,
+            output: None,
+            opt_level: 0,
+            args: Vec::new(),
+            color: io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        };
+        if command == TiCommand::Help {
+            return Ok(cli);
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--entry" => match args.next() {
+                    Some(entry) => cli.entry = entry,
+                    None => return Err("missing value of `--entry`".to_string()),
+                },
+                "--no-color" => cli.color = false,
+                "--bytecode" => cli.bytecode = true,
+                "-o" | "--output" => match args.next() {
+                    Some(output) => cli.output = Some(output),
+                    None => return Err(format!("missing value of `{}`", arg)),
+                },
+                "-h" | "--help" => {
+                    cli.command = TiCommand::Help;
+                    return Ok(cli);
+                }
+                _ if arg.starts_with("-O") => {
+                    cli.opt_level = match &arg["-O".len()..] {
+                        "" => 1,
+                        level => match level.parse() {
+                            Ok(level) if level <= MAX_LEVEL => level,
+                            _ => return Err(format!("unknown optimization level `{}`", level)),
+                        },
+                    };
+                    cli.bytecode |= cli.opt_level > 0;
+                }
+                _ if arg.starts_with("--entry=") => {
+                    cli.entry = arg["--entry=".len()..].to_string();
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ if command == TiCommand::Repl => {
+                    return Err(format!("unexpected argument `{}`", arg))
+                }
+                _ => {
+                    cli.file = arg;
+                    break;
+                }
+            }
+        }
+        if command == TiCommand::Repl {
+            return Ok(cli);
+        }
+        if cli.file.is_empty() {
+            return Err("missing input file".to_string());
+        }
+        cli.args = args.collect();
+        if !cli.args.is_empty() && command != TiCommand::Run {
+            if command != TiCommand::Compile || cli.args[0] != "-o" || cli.args.len() != 2 {
+                return Err(format!("unexpected argument `{}`", cli.args[0]));
+            }
+            // `-o` may also follow the input file
+            cli.output = cli.args.pop();
+            cli.args.clear();
+        }
+        if cli.output.is_some() && command != TiCommand::Compile {
+            return Err("`-o` is only accepted by `compile`".to_string());
+        }
+        Ok(cli)
+    }
+
+    /// Parses `args` (without the program name), runs the command on a
+    /// thread with a `STACK_SIZE` stack and returns the process exit code.
+    pub fn main(args: Vec<String>) -> i32 {
+        match Self::parse(args) {
+            Ok(cli) => thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(|| cli.execute())
+                .expect("failed to spawn the main thread")
+                .join()
+                .unwrap_or_else(|panic| panic::resume_unwind(panic)),
+            Err(msg) => {
+                eprintln!("{}: {}\n\n{}", "error".red().bold(), msg, USAGE);
+                EXIT_USAGE
+            }
+        }
+    }
+
+    pub fn execute(self) -> i32 {
+        match self.command {
+            TiCommand::Help => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            TiCommand::Repl => {
+                let driver = TiDriver::new(self.color).with_max_depth(MAX_CALL_DEPTH);
+                return TiRepl::new(driver).run();
+            }
+            TiCommand::Run | TiCommand::Disasm if self.file.ends_with(".tic") => {
+                return self.execute_tic()
+            }
+            _ => {}
+        }
+        let source = match TiSource::load(&self.file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!(
+                    "{}: cannot read `{}`: {}",
+                    "error".red().bold(),
+                    self.file,
+                    err
+                );
+                return EXIT_FAILURE;
+            }
+        };
+        let driver = TiDriver::new(self.color)
+            .with_opt_level(self.opt_level)
+            .with_max_depth(MAX_CALL_DEPTH);
+        let res = match self.command {
+            TiCommand::Run if self.bytecode => driver
+                .run_bytecode(&source, &self.entry, self.args)
+                .map(|res| exit_code(&res)),
+            TiCommand::Run => driver
+                .run(&source, &self.entry, self.args)
+                .map(|res| exit_code(&res)),
+            TiCommand::Compile => driver.compile(&source).and_then(|main| {
+                let output = match &self.output {
+                    Some(output) => output.clone(),
+                    None => Path::new(&self.file)
+                        .with_extension("tic")
+                        .to_string_lossy()
+                        .into_owned(),
+                };
+                let tic = TicFile::new(self.file.clone(), main);
+                match fs::write(&output, tic.encode()) {
+                    Ok(()) => Ok(0),
+                    Err(err) => Err(vec![TiError::new(
+                        TiErrorKind::Compile,
+                        format!("cannot write `{}`: {}", output, err),
+                    )]),
+                }
+            }),
+            TiCommand::Disasm => driver.compile(&source).map(|main| {
+                let _ = write!(io::stdout().lock(), "{}", disassemble(&main));
+                0
+            }),
+            TiCommand::Check => driver
+                .parse(&source)
+                .and_then(|ast| driver.prepare(&source, ast, &TiPrelude::default()))
+                .map(|_| {
+                    println!("{}: {}", "check".white().bold(), "ok".green().bold());
+                    0
+                }),
+            TiCommand::Tokens => driver.tokenize(&source).map(|mut tokens| {
+                let renderer = TiErrorRenderer::new(&source.src);
+                let mut out = io::stdout().lock();
+                while !tokens.is_eof() {
+                    let token = tokens.next();
+                    let (line, col) = renderer.locate(token.t_at.1 .0);
+                    // stop quietly once the reader of a pipe is gone
+                    if writeln!(out, "{}:{}\t{:?}", line, col, token.t_type).is_err() {
+                        break;
+                    }
+                }
+                0
+            }),
+            TiCommand::Ast => driver.parse(&source).map(|ast| {
+                let _ = writeln!(io::stdout().lock(), "{:#?}", ast);
+                0
+            }),
+            TiCommand::Repl | TiCommand::Help => unreachable!(),
+        };
+        match res {
+            Ok(code) => code,
+            Err(errs) => {
+                driver.report(&source, &errs);
+                EXIT_FAILURE
+            }
+        }
+    }
+
+    /// Runs or disassembles a program compiled by `ti compile`.
+    fn execute_tic(self) -> i32 {
+        let driver = TiDriver::new(self.color);
+        let bytes = match fs::read(&self.file) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!(
+                    "{}: cannot read `{}`: {}",
+                    "error".red().bold(),
+                    self.file,
+                    err
+                );
+                return EXIT_FAILURE;
+            }
+        };
+        let tic = match TicFile::decode(&bytes) {
+            Ok(tic) => tic,
+            Err(err) => {
+                driver.report_loaded(&self.file, vec![err]);
+                return EXIT_FAILURE;
+            }
+        };
+        if self.command == TiCommand::Disasm {
+            let _ = write!(io::stdout().lock(), "{}", disassemble(&tic.main));
+            return 0;
+        }
+        match driver.run_main(tic.main, &self.entry, self.args) {
+            Ok(res) => exit_code(&res),
+            Err(errs) => {
+                driver.report_loaded(&tic.source, errs);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+/// Maps the value returned by the entry function to a process exit code:
+/// numbers are used as is, `false` fails, anything else succeeds.
+pub fn exit_code(res: &TiObj) -> i32 {
+    match res {
+        TiObj::Num(x) if x.fract() == 0.0 && *x >= i32::MIN as f64 && *x <= i32::MAX as f64 => {
+            *x as i32
+        }
+        TiObj::Num(_) => EXIT_FAILURE,
+        TiObj::Bool(false) => EXIT_FAILURE,
+        _ => 0,
+    }
+}
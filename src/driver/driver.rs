@@ -0,0 +1,892 @@
+use std::{fs, io, rc::Rc};
+
+use crate::{
+    backend::{
+        bytecode::{bytecode::TiFnProto, optimize::optimize, verify::verify},
+        codegen::codegen::Codegen,
+    },
+    error::{
+        error::{TiError, TiErrorKind, TiErrorNote},
+        render::TiErrorRenderer,
+    },
+    frontend::{
+        fold::fold::fold,
+        lexer::{lexer::Lexer, token::TokenStream},
+        parser::{ast::AstNode, parser::Parser},
+        resolve::resolve::{resolve, TiPrelude},
+    },
+    vm::{types::object_type::TiObj, vm, vm_ast},
+};
+
+pub struct TiSource {
+    pub name: String,
+    pub src: String,
+}
+
+impl TiSource {
+    pub fn new(name: String, src: String) -> Self {
+        Self { name, src }
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Ok(Self::new(path.to_string(), fs::read_to_string(path)?))
+    }
+}
+
+/// Runs the pipeline stages over a `TiSource` and reports their errors.
+pub struct TiDriver {
+    pub color: bool,
+    /// how hard `compile` optimizes the bytecode, see `optimize`
+    pub opt_level: u8,
+    /// how deep calls may nest in `vm_ast`, see `TiVM::with_max_depth`
+    pub max_depth: usize,
+}
+
+impl TiDriver {
+    pub fn new(color: bool) -> Self {
+        Self {
+            color,
+            opt_level: 0,
+            max_depth: vm_ast::DEFAULT_CALL_DEPTH,
+        }
+    }
+
+    pub fn with_opt_level(mut self, opt_level: u8) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// A new `vm_ast` vm, letting calls nest `max_depth` deep.
+    pub fn vm(&self) -> vm_ast::TiVM {
+        vm_ast::TiVM::new().with_max_depth(self.max_depth)
+    }
+
+    pub fn report(&self, source: &TiSource, errs: &[TiError]) {
+        let renderer = TiErrorRenderer::new(&source.src)
+            .with_name(&source.name)
+            .with_color(self.color);
+        for err in errs {
+            eprint!("{}", renderer.render(err));
+        }
+    }
+
+    pub fn tokenize(&self, source: &TiSource) -> Result<TokenStream, Vec<TiError>> {
+        Lexer::new(&source.src).tokenize().map_err(|err| vec![err])
+    }
+
+    /// Returns the parsed program, or every syntax error followed by the summary.
+    pub fn parse(&self, source: &TiSource) -> Result<AstNode, Vec<TiError>> {
+        let mut parser = Parser::new(self.tokenize(source)?);
+        let res = parser.parse();
+        Self::finish(parser, res).map(|parser| parser.ast)
+    }
+
+    /// Like `parse`, but accepts top level statements as the REPL does.
+    pub fn parse_stmts(&self, source: &TiSource) -> Result<AstNode, Vec<TiError>> {
+        let mut parser = Parser::new(self.tokenize(source)?);
+        let res = parser.parse_stmts();
+        Self::finish(parser, res).map(|parser| parser.ast)
+    }
+
+    fn finish(parser: Parser, res: Result<(), TiError>) -> Result<Parser, Vec<TiError>> {
+        match res {
+            Ok(()) => Ok(parser),
+            Err(err) => {
+                let mut errs = parser.diagnostics;
+                errs.push(err);
+                Err(errs)
+            }
+        }
+    }
+
+    /// Folds the parsed program and resolves its variables, reporting the
+    /// warnings of the resolver and returning its errors, if any.
+    pub fn prepare(
+        &self,
+        source: &TiSource,
+        ast: AstNode,
+        prelude: &TiPrelude,
+    ) -> Result<AstNode, Vec<TiError>> {
+        let mut ast = fold(ast);
+        let (warnings, errs): (Vec<_>, Vec<_>) = resolve(&mut ast, prelude)
+            .into_iter()
+            .partition(|err| err.kind == TiErrorKind::Warning);
+        self.report(source, &warnings);
+        match errs.is_empty() {
+            true => Ok(ast),
+            false => Err(errs),
+        }
+    }
+
+    /// Parses the program and compiles it to its main chunk.
+    pub fn compile(&self, source: &TiSource) -> Result<TiFnProto, Vec<TiError>> {
+        let mut parser = Parser::new(self.tokenize(source)?);
+        let res = parser.parse();
+        let mut parser = Self::finish(parser, res)?;
+        parser.ast = self.prepare(source, parser.ast, &TiPrelude::default())?;
+        let main = Codegen::new(parser).bytecode().map_err(|err| vec![err])?;
+        if self.opt_level == 0 {
+            return Ok(main);
+        }
+        verify(&main).map_err(|err| vec![err])?;
+        Ok(optimize(&main, self.opt_level))
+    }
+
+    /// Runs `entry` of the program; an entry taking one parameter receives
+    /// the script arguments as a list of strings.
+    pub fn run(
+        &self,
+        source: &TiSource,
+        entry: &str,
+        args: Vec<String>,
+    ) -> Result<TiObj, Vec<TiError>> {
+        let ast = self.prepare(source, self.parse(source)?, &TiPrelude::default())?;
+        let mut vm = self.vm();
+        vm.execute(&ast).map_err(|err| vec![err])?;
+        let args = match vm.environment.get(&Rc::new(entry.to_string())) {
+            Some(TiObj::Fn(fdef, _, _)) if fdef.arguments.len() == 1 => {
+                vec![TiObj::List(args.into_iter().map(TiObj::Str).collect())]
+            }
+            _ => Vec::new(),
+        };
+        vm.run_fn(entry, args).map_err(|err| vec![err])
+    }
+
+    /// Like `run`, but compiles the program and runs it on the bytecode vm.
+    pub fn run_bytecode(
+        &self,
+        source: &TiSource,
+        entry: &str,
+        args: Vec<String>,
+    ) -> Result<TiObj, Vec<TiError>> {
+        let main = self.compile(source)?;
+        self.run_main(main, entry, args)
+    }
+
+    /// Runs the main chunk of a compiled program, then its `entry`.
+    pub fn run_main(
+        &self,
+        main: TiFnProto,
+        entry: &str,
+        args: Vec<String>,
+    ) -> Result<TiObj, Vec<TiError>> {
+        let mut vm = vm::TiVM::new();
+        vm.execute(Rc::new(main)).map_err(|err| vec![err])?;
+        let args = match vm.environment.get(&Rc::new(entry.to_string())) {
+            Some(TiObj::Proto(proto, _)) if proto.def.arguments.len() == 1 => {
+                vec![TiObj::List(args.into_iter().map(TiObj::Str).collect())]
+            }
+            _ => Vec::new(),
+        };
+        vm.run_fn(entry, args).map_err(|err| vec![err])
+    }
+
+    /// Reports errors of a program loaded from bytecode, whose spans point
+    /// into the source `name` it was compiled from. When that source cannot
+    /// be read anymore, or no longer holds a span, the span is reduced to
+    /// its line number.
+    pub fn report_loaded(&self, name: &str, mut errs: Vec<TiError>) {
+        let source =
+            TiSource::load(name).unwrap_or_else(|_| TiSource::new(name.to_string(), String::new()));
+        let len = source.src.chars().count();
+        for err in errs.iter_mut() {
+            if let Some((line, _)) = err.at.filter(|(_, (_, end))| *end > len) {
+                err.at = None;
+                err.notes
+                    .insert(0, TiErrorNote::Note(format!("raised at {}:{}", name, line)));
+            }
+            err.labels.retain(|label| label.at.1 .1 <= len);
+        }
+        self.report(&source, &errs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{driver::cli::STACK_SIZE, frontend::parser::ast::AstExpr, vm::vm::MAX_CALL_DEPTH};
+
+    /// Runs `main` of `src` on both vms, checking they agree.
+    fn run(src: &str) -> TiObj {
+        let source = TiSource::new("test.ti".to_string(), src.to_string());
+        let driver = TiDriver::new(false);
+        let ast = driver.run(&source, "main", Vec::new()).unwrap();
+        let bytecode = driver.run_bytecode(&source, "main", Vec::new()).unwrap();
+        assert_eq!(ast, bytecode);
+        ast
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        let res = run("fn main() {
+            let hits = [0]
+            let touch = fn(x) {
+                hits[0] += 1
+                x
+            }
+            let skipped = [false && touch(true), true || touch(false), hits[0]]
+            let taken = [true && touch(false), false || touch(true), hits[0]]
+            [skipped, taken]
+        }");
+        assert_eq!(res.to_string(), "[[false, true, 0], [false, true, 2]]");
+    }
+
+    #[test]
+    fn user_iterators_run_until_none() {
+        let res = run("struct Items { xs: list, i: f64 }
+        impl Items {
+            fn next(self) {
+                if self.i == 3 {
+                    Option::None
+                } else {
+                    let x = self.xs[self.i]
+                    self.i += 1
+                    Option::Some(x)
+                }
+            }
+        }
+        fn main() {
+            let seen = []
+            for x in (Items { xs: [[1], [], [2]], i: 0 }) {
+                seen = seen + [x]
+            }
+            seen
+        }");
+        assert_eq!(res.to_string(), "[[1], [], [2]]");
+    }
+
+    /// The first error running `main` of `src` gives on each vm.
+    fn fail(src: &str) -> [TiError; 2] {
+        let source = TiSource::new("test.ti".to_string(), src.to_string());
+        let driver = TiDriver::new(false);
+        let ast = driver.run(&source, "main", Vec::new()).unwrap_err();
+        let bytecode = driver.run_bytecode(&source, "main", Vec::new());
+        [ast, bytecode.unwrap_err()].map(|errs| errs[0].clone())
+    }
+
+    #[test]
+    fn iterators_end_only_at_the_builtin_none() {
+        let errs = fail(
+            "enum Step { Go(f64), None }
+        struct Steps { n: f64 }
+        impl Steps {
+            fn next(self) { Step::None }
+        }
+        fn main() {
+            for x in (Steps { n: 0 }) {}
+        }",
+        );
+        for err in errs {
+            assert_eq!(err.kind, TiErrorKind::Type);
+            assert_eq!(
+                err.message,
+                "the `next` method of an iterator must return an `Option`, found `Step::None`."
+            );
+        }
+    }
+
+    #[test]
+    fn try_takes_only_the_builtin_option_and_result() {
+        let res = run("fn half(n) {
+            if n % 2 == 0 { Option::Some(n / 2) } else { Option::None }
+        }
+        fn quarter(n) { half(half(n)?) }
+        fn main() { [quarter(8), quarter(6)] }");
+        assert_eq!(res.to_string(), "[Option::Some(2), Option::None]");
+        let errs = fail(
+            "enum Maybe { Some(f64), None }
+        fn main() { Maybe::Some(1)? }",
+        );
+        for err in errs {
+            assert_eq!(err.kind, TiErrorKind::Type);
+            assert_eq!(
+                err.message,
+                "the operator `?` takes an `Option` or a `Result`, found `Maybe::Some(1)`."
+            );
+        }
+    }
+
+    #[test]
+    fn builtin_enums_cannot_be_defined_again() {
+        for errs in fail("enum Result { Ok, Err }\nfn main() {}") {
+            assert_eq!(errs.kind, TiErrorKind::Type);
+            assert_eq!(errs.at, Some((1, (0, 4))));
+        }
+    }
+
+    /// Runs `f` recursing without end on a thread with a stack of
+    /// `stack_size` and `driver`, on both vms.
+    fn run_away(stack_size: usize, driver: TiDriver) -> [Vec<TiError>; 2] {
+        thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let source = TiSource::new(
+                    "test.ti".to_string(),
+                    "fn f(n) {\nlet xs = [n, [n + 1, n * 2]]\nif xs[1][0] > n { [f(xs[0] + 1)][0] } else { 0 }\n}\nfn main() { f(0) }".to_string(),
+                );
+                let ast = driver.run(&source, "main", Vec::new()).unwrap_err();
+                let bytecode = driver.run_bytecode(&source, "main", Vec::new());
+                [ast, bytecode.unwrap_err()]
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn runaway_recursion_is_a_runtime_error() {
+        // the depth `ti` lets calls nest on its stack
+        let errs = run_away(
+            STACK_SIZE,
+            TiDriver::new(false).with_max_depth(MAX_CALL_DEPTH),
+        );
+        for errs in errs {
+            assert_eq!(errs[0].kind, TiErrorKind::Runtime);
+            assert!(
+                errs[0].message.contains("stack overflow"),
+                "{}",
+                errs[0].message
+            );
+        }
+    }
+
+    #[test]
+    fn runaway_recursion_fits_a_main_thread_stack_by_default() {
+        for errs in run_away(8 << 20, TiDriver::new(false)) {
+            assert_eq!(errs[0].kind, TiErrorKind::Runtime);
+            assert!(
+                errs[0].message.contains("stack overflow"),
+                "{}",
+                errs[0].message
+            );
+        }
+    }
+
+    #[test]
+    fn non_exhaustive_match_fails_before_running() {
+        let source = TiSource::new(
+            "test.ti".to_string(),
+            "fn never(x) { match x { true => 1 } }\nfn main() { 0 }".to_string(),
+        );
+        let driver = TiDriver::new(false);
+        let ast = driver.parse(&source).unwrap();
+        let errs = [
+            driver
+                .prepare(&source, ast, &TiPrelude::default())
+                .unwrap_err(),
+            driver.run(&source, "main", Vec::new()).unwrap_err(),
+            driver
+                .run_bytecode(&source, "main", Vec::new())
+                .unwrap_err(),
+        ];
+        for errs in errs {
+            assert_eq!(errs[0].kind, TiErrorKind::Type);
+            assert_eq!(
+                errs[0].message,
+                "non-exhaustive patterns: `false` not covered."
+            );
+            // the `match`
+            assert_eq!(errs[0].at, Some((1, (14, 19))));
+        }
+    }
+
+    #[test]
+    fn undeclared_names_fail_before_running() {
+        let source = TiSource::new(
+            "test.ti".to_string(),
+            "fn main() { println(missing) }".to_string(),
+        );
+        let driver = TiDriver::new(false);
+        let ast = driver.parse(&source).unwrap();
+        let errs = driver
+            .prepare(&source, ast, &TiPrelude::default())
+            .unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].kind, TiErrorKind::Reference);
+        assert_eq!(errs[0].message, "name `missing` is not declared.");
+        // the `missing`, not the `println` starting the statement
+        assert_eq!(errs[0].at, Some((1, (20, 27))));
+    }
+
+    #[test]
+    fn operator_errors_point_at_the_operator_in_both_vms() {
+        let cases = [
+            ("fn main() {\nlet n = 100\n1 << n\n}", (3, (26, 28))),
+            ("fn main() {\nlet n = 64\n1 >> n\n}", (3, (25, 27))),
+            ("fn main() {\nlet s = \"a\"\ntrue && s\n}", (3, (29, 31))),
+            ("fn main() {\nlet s = \"a\"\ns -= 1\n}", (3, (26, 28))),
+            ("fn main() {\nlet s = \"a\"\nprintln(-s)\n}", (3, (32, 33))),
+        ];
+        for (src, at) in cases {
+            let source = TiSource::new("test.ti".to_string(), src.to_string());
+            let driver = TiDriver::new(false);
+            let errs = [
+                driver.run(&source, "main", Vec::new()).unwrap_err(),
+                driver
+                    .run_bytecode(&source, "main", Vec::new())
+                    .unwrap_err(),
+            ];
+            for errs in errs {
+                assert_eq!(errs[0].at, Some(at), "{}", src);
+            }
+        }
+    }
+
+    #[test]
+    fn closures_do_not_keep_the_frame_storing_them_alive() {
+        let source = TiSource::new(
+            "test.ti".to_string(),
+            "fn f(i) {\nlet g = fn() => i\ng()\n}\nfn main() {\nf(1)\nf(2)\n}".to_string(),
+        );
+        let driver = TiDriver::new(false);
+        let ast = driver
+            .prepare(
+                &source,
+                driver.parse(&source).unwrap(),
+                &TiPrelude::default(),
+            )
+            .unwrap();
+        let AstNode::Program(program) = &ast else {
+            unreachable!()
+        };
+        let AstNode::Fn(_, fbody) = &program.program[0] else {
+            unreachable!()
+        };
+        let AstNode::Let(_, Some(AstExpr::Lambda(_, lambda))) = &fbody.block[0] else {
+            unreachable!()
+        };
+        let mut vm = vm_ast::TiVM::new();
+        vm.execute(&ast).unwrap();
+        // every closure `g` holds the body of the lambda
+        let before = Rc::strong_count(lambda);
+        vm.run_fn("main", Vec::new()).unwrap();
+        assert_eq!(Rc::strong_count(lambda), before);
+    }
+
+    #[test]
+    fn map_key_errors_point_at_the_key_in_both_vms() {
+        let source = TiSource::new(
+            "test.ti".to_string(),
+            "fn main() {\nlet k = 0 / 0\nlet m = {\"a\": 1, [k]: 1}\n}".to_string(),
+        );
+        let driver = TiDriver::new(false);
+        let errs = [
+            driver.run(&source, "main", Vec::new()).unwrap_err(),
+            driver
+                .run_bytecode(&source, "main", Vec::new())
+                .unwrap_err(),
+        ];
+        for errs in errs {
+            assert_eq!(errs[0].at, Some((3, (43, 46))));
+        }
+    }
+
+    #[test]
+    fn names_used_before_their_declaration_fail_at_the_name() {
+        let source = TiSource::new(
+            "test.ti".to_string(),
+            "fn main() {\nlet y = x + 1\nlet x = 1\n}".to_string(),
+        );
+        let driver = TiDriver::new(false);
+        let errs = [
+            driver.run(&source, "main", Vec::new()).unwrap_err(),
+            driver
+                .run_bytecode(&source, "main", Vec::new())
+                .unwrap_err(),
+        ];
+        for errs in errs {
+            assert_eq!(errs[0].kind, TiErrorKind::Reference);
+            assert_eq!(errs[0].at, Some((2, (20, 21))));
+        }
+    }
+
+    #[test]
+    fn labelled_break_and_continue_leave_the_loop_named() {
+        let res = run("fn main() {
+            let out = []
+            'outer: for i in 0..4 {
+                for j in 0..4 {
+                    if j > i { continue 'outer }
+                    if i == 3 { break 'outer }
+                    out = out + [i * 10 + j]
+                }
+            }
+            let found = 'search: while true {
+                let n = 0
+                while true {
+                    n += 1
+                    if n * n > 30 { break 'search n }
+                }
+            }
+            [out, found]
+        }");
+        assert_eq!(res.to_string(), "[[0, 10, 11, 20, 21, 22], 6]");
+    }
+
+    #[test]
+    fn break_and_continue_outside_their_loop_are_syntax_errors() {
+        let cases = [
+            (
+                "fn main() {\nwhile true { break 'nope }\n}",
+                "Use of Undeclared Label `'nope`",
+                (2, (31, 36)),
+            ),
+            (
+                "fn main() {\n'a: while true { fn() { break 'a } }\n}",
+                "`break` Outside of a Loop",
+                (2, (36, 41)),
+            ),
+            (
+                "fn main() {\ncontinue\n}",
+                "`continue` Outside of a Loop",
+                (2, (12, 20)),
+            ),
+        ];
+        for (src, message, at) in cases {
+            let source = TiSource::new("test.ti".to_string(), src.to_string());
+            let errs = TiDriver::new(false).parse(&source).unwrap_err();
+            assert_eq!(errs.len(), 2, "{:?}", errs);
+            assert_eq!(errs[0].kind, TiErrorKind::Syntax);
+            assert_eq!(errs[0].message, message);
+            assert_eq!(errs[0].at, Some(at));
+        }
+    }
+
+    #[test]
+    fn lists_strings_and_maps_index_and_slice() {
+        let res = run("fn main() {
+            let xs = [1, 2, 3, 4]
+            let m = {\"a\": 1}
+            xs[-1] = 5
+            m[\"b\"] = xs[0]
+            m[\"a\"] += 1
+            [xs[-1], xs[1..3], xs[1..=3], \"hello\"[1..3], m[\"a\"], m[\"b\"]]
+        }");
+        assert_eq!(res.to_string(), "[5, [2, 3], [2, 3, 5], el, 2, 1]");
+    }
+
+    #[test]
+    fn index_errors_point_at_the_index_in_both_vms() {
+        let cases = [
+            (
+                "xs[-4]",
+                "[-4]",
+                TiErrorKind::Runtime,
+                "index `-4` is out of bounds for length 3.",
+            ),
+            (
+                "xs[1.5]",
+                "[1.5]",
+                TiErrorKind::Runtime,
+                "index `1.5` is not a whole number.",
+            ),
+            (
+                "xs[2..1]",
+                "[2..1]",
+                TiErrorKind::Runtime,
+                "slice `2..1` is out of bounds for length 3.",
+            ),
+            (
+                "xs[0..9]",
+                "[0..9]",
+                TiErrorKind::Runtime,
+                "slice `0..9` is out of bounds for length 3.",
+            ),
+            (
+                "m[\"b\"]",
+                "[\"b\"]",
+                TiErrorKind::Runtime,
+                "no key `b` in the map.",
+            ),
+            (
+                "m[\"b\"] += 1",
+                "[\"b\"]",
+                TiErrorKind::Runtime,
+                "no key `b` in the map.",
+            ),
+            (
+                "xs[\"a\"]",
+                "[\"a\"]",
+                TiErrorKind::Type,
+                "type `Str` cannot be used as an index.",
+            ),
+            (
+                "xs[0] = 5[0]",
+                "[0]",
+                TiErrorKind::Type,
+                "type `Num` cannot be indexed.",
+            ),
+        ];
+        let prefix = "fn main() {\nlet xs = [1, 2, 3]\nlet m = {\"a\": 1}\n";
+        for (stmt, index, kind, message) in cases {
+            let src = format!("{}{}\n}}", prefix, stmt);
+            let start = src.rfind(index).unwrap();
+            let end = start + index.len();
+            for err in fail(&src) {
+                assert_eq!(err.kind, kind, "{}", stmt);
+                assert_eq!(err.message, message, "{}", stmt);
+                assert_eq!(err.at, Some((4, (start, end))), "{}", stmt);
+            }
+        }
+    }
+
+    #[test]
+    fn structs_are_built_read_and_assigned_by_field() {
+        let res = run("struct P { x: f64, y: f64 }
+        struct S { from: P, to: P }
+        fn main() {
+            let s = S { from: P { x: 1, y: 2 }, to: P { y: 6, x: 4 } }
+            s.to.x = s.from.x + 10
+            s.from.y += 1
+            let t = s.from
+            t.x = 100
+            [s, s.to.x - s.from.x, t.x, s.from == P { x: 1, y: 3 }]
+        }");
+        assert_eq!(
+            res.to_string(),
+            "[S { from: P { x: 1, y: 3 }, to: P { x: 11, y: 6 } }, 10, 100, true]"
+        );
+    }
+
+    #[test]
+    fn struct_misuse_fails_the_same_in_both_vms() {
+        let cases = [
+            (
+                "P { x: 1 }",
+                TiErrorKind::Type,
+                "missing field `y` in initializer of `P`.",
+            ),
+            (
+                "P { x: 1, y: 2, z: 3 }",
+                TiErrorKind::Reference,
+                "struct `P` has no field named `z`.",
+            ),
+            (
+                "P { x: 1, y: 2 }.z",
+                TiErrorKind::Reference,
+                "no field `z` on type `P`.",
+            ),
+            (
+                "[1].z",
+                TiErrorKind::Type,
+                "type `List` has no fields, cannot access `z`.",
+            ),
+            (
+                "Q { x: 1 }",
+                TiErrorKind::Reference,
+                "struct `Q` is not defined.",
+            ),
+        ];
+        for (expr, kind, message) in cases {
+            let src = format!(
+                "struct P {{ x: f64, y: f64 }}\nfn main() {{\nlet p = {}\n}}",
+                expr
+            );
+            for err in fail(&src) {
+                assert_eq!(err.kind, kind, "{}", expr);
+                assert_eq!(err.message, message, "{}", expr);
+            }
+        }
+    }
+
+    #[test]
+    fn enum_variants_are_built_and_matched() {
+        let res = run("enum Shape { Circle(f64), Rect(f64, f64), Empty }
+        enum Tree { Leaf(f64), Node(Tree, Tree) }
+        fn sum(t) {
+            match t {
+                Tree::Leaf(n) => n,
+                Tree::Node(l, r) => sum(l) + sum(r),
+            }
+        }
+        fn main() {
+            let out = []
+            for s in [Shape::Circle(1), Shape::Rect(2, 3), Shape::Empty] {
+                out = out + [match s { Shape::Rect(w, _) => w, Shape::Empty => -1, other => other }]
+            }
+            let tree = Tree::Node(Tree::Leaf(1), Tree::Node(Tree::Leaf(2), Tree::Leaf(3)))
+            let word = match 2 { 1 => \"one\", 2 => \"two\", _ => \"many\" }
+            [out, sum(tree), word, Shape::Rect(1, 2) == Shape::Rect(1, 2), Shape::Empty == Shape::Circle(0)]
+        }");
+        assert_eq!(
+            res.to_string(),
+            "[[Shape::Circle(1), 2, -1], 6, two, true, false]"
+        );
+    }
+
+    #[test]
+    fn enum_misuse_fails_the_same_in_both_vms() {
+        let cases = [
+            (
+                "E::A(1, 2)",
+                TiErrorKind::Type,
+                "variant `E::A` takes 1 values but 2 were given.",
+            ),
+            (
+                "E::A",
+                TiErrorKind::Type,
+                "variant `E::A` takes 1 values but 0 were given.",
+            ),
+            (
+                "E::C",
+                TiErrorKind::Reference,
+                "no variant `C` in enum `E`.",
+            ),
+            (
+                "match E::B { E::A(x) => x }",
+                TiErrorKind::Type,
+                "non-exhaustive patterns: `E::B` not covered.",
+            ),
+            (
+                "match E::A(2) { E::A(x, y) => x, _ => 0 }",
+                TiErrorKind::Type,
+                "pattern `E::A` has 2 fields but the variant has 1.",
+            ),
+        ];
+        for (expr, kind, message) in cases {
+            let src = format!("enum E {{ A(f64), B }}\nfn main() {{\n{}\n}}", expr);
+            for err in fail(&src) {
+                assert_eq!(err.kind, kind, "{}", expr);
+                assert_eq!(err.message, message, "{}", expr);
+            }
+        }
+    }
+
+    #[test]
+    fn trait_and_inherent_methods_dispatch_on_the_receiver() {
+        let res = run("trait Named {
+            fn name(self)
+            fn greet(self) => \"hi \" + self.name()
+        }
+        struct Dog { n: str }
+        enum Cat { Tom, Other(str) }
+        impl Named for Dog {
+            fn name(self) => self.n
+        }
+        impl Named for Cat {
+            fn name(self) => match self { Cat::Tom => \"tom\", Cat::Other(n) => n }
+            fn greet(self) => \"meow \" + self.name()
+        }
+        impl Dog {
+            fn new(n) => Dog { n: n }
+            fn rename(self, n) { self.n = n }
+        }
+        fn main() {
+            let d = Dog::new(\"rex\")
+            d.rename(\"max\")
+            [d.greet(), Cat::Tom.greet(), Cat::Other(\"felix\").name(), d.n]
+        }");
+        assert_eq!(res.to_string(), "[hi max, meow tom, felix, max]");
+    }
+
+    #[test]
+    fn trait_misuse_fails_the_same_in_both_vms() {
+        let cases = [
+            (
+                "impl T for S { }",
+                "0",
+                TiErrorKind::Type,
+                "not all trait items implemented, missing: `a`.",
+            ),
+            (
+                "impl T for S { fn a(self) => 1\nfn c(self) => 2 }",
+                "0",
+                TiErrorKind::Reference,
+                "method `c` is not a member of trait `T`.",
+            ),
+            (
+                "impl T for S { fn a(self) => 1 }",
+                "S { x: 1 }.z()",
+                TiErrorKind::Reference,
+                "no method named `z` found for type `S`.",
+            ),
+            (
+                "impl T for Q { fn a(self) => 1 }",
+                "0",
+                TiErrorKind::Reference,
+                "cannot implement for `Q`, no struct or enum of this name is defined.",
+            ),
+            (
+                "impl U for S { fn a(self) => 1 }",
+                "0",
+                TiErrorKind::Reference,
+                "trait `U` is not defined.",
+            ),
+        ];
+        for (item, expr, kind, message) in cases {
+            let src = format!(
+                "trait T {{ fn a(self)\nfn b(self) => self.a() + 1 }}\nstruct S {{ x: f64 }}\n{}\nfn main() {{ {} }}",
+                item, expr
+            );
+            for err in fail(&src) {
+                assert_eq!(err.kind, kind, "{}", item);
+                assert_eq!(err.message, message, "{}", item);
+            }
+        }
+    }
+
+    #[test]
+    fn compound_assignments_update_every_kind_of_place() {
+        let res = run("struct P { x: f64 }
+        fn main() {
+            let a = 10
+            a += 2
+            a -= 4
+            a *= 3
+            a /= 4
+            a %= 4
+            let xs = [1, 2, 3]
+            xs[-1] *= 5
+            xs[0] += xs[1] += 1
+            let p = P { x: 1 }
+            p.x -= 3
+            let m = {\"k\": 1}
+            m[\"k\"] += 9
+            let s = \"a\"
+            s += \"b\"
+            let b = 1
+            let c = b += 2 * 3
+            [a, xs, p.x, m[\"k\"], s, b, c]
+        }");
+        assert_eq!(res.to_string(), "[2, [4, 3, 15], -2, 10, ab, 7, 7]");
+    }
+
+    #[test]
+    fn compound_assignment_errors_point_at_the_operator() {
+        let source = TiSource::new("test.ti".to_string(), "fn main() {\n1 += 2\n}".to_string());
+        let errs = TiDriver::new(false).parse(&source).unwrap_err();
+        assert_eq!(errs[0].kind, TiErrorKind::Syntax);
+        assert_eq!(errs[0].at, Some((2, (14, 16))));
+        for err in fail("fn main() {\nlet a = true\na *= 1\n}") {
+            assert_eq!(err.kind, TiErrorKind::Type);
+            assert_eq!(
+                err.message,
+                "the operator `*` is not implemented for type `Bool`."
+            );
+            assert_eq!(err.at, Some((3, (27, 29))));
+        }
+    }
+
+    #[test]
+    fn operators_follow_the_operator_table() {
+        let res = run("fn main() {
+            let t = true
+            let n = 7
+            [
+                n % 3, -n % 3, -n, !t, t || false, false || t && false, !(n > 3) || n == 7,
+                2 ** 3 ** 2, -2 ** 2, 5 & 3 | 8 ^ 1, 1 << 3 >> 1, n != 7, \"a\" < \"b\", [1] == [1],
+            ]
+        }");
+        assert_eq!(
+            res.to_string(),
+            "[1, -1, -7, false, true, false, true, 512, -4, 9, 4, false, true, true]"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use colored::Colorize;
+
+use crate::{
+    error::{error::TiError, render::TiErrorRenderer},
+    frontend::{parser::ast::AstNode, resolve::resolve::TiPrelude},
+    vm::{types::object_type::TiObj, vm_ast},
+};
+
+use super::driver::{TiDriver, TiSource};
+
+pub const REPL_HELP: &str = "\
+:ast <code>       pretty-print the ast of <code>
+:tokens <code>    dump the token stream of <code>
+:env              list the bindings of the environment
+:reset            drop every binding and start over
+:history          list the previous inputs
+:help             print this message
+:quit             leave the repl";
+
+/// Reads statements and definitions from stdin and runs them on a single
+/// long-lived `vm_ast::TiVM`, so bindings persist across inputs.
+pub struct TiRepl {
+    vm: vm_ast::TiVM,
+    driver: TiDriver,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl TiRepl {
+    /// Runs the inputs with `driver` and a vm it makes. The history is kept
+    /// in `$TI_HISTORY`, or `~/.ti_history` when unset, one input per line;
+    /// an empty `$TI_HISTORY` disables it.
+    pub fn new(driver: TiDriver) -> Self {
+        let history_path = match env::var_os("TI_HISTORY") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".ti_history")),
+        };
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|history| history.lines().map(unescape).collect())
+            .unwrap_or_default();
+        Self {
+            vm: driver.vm(),
+            driver,
+            history,
+            history_path,
+        }
+    }
+
+    pub fn run(&mut self) -> i32 {
+        let stdin = io::stdin();
+        let interactive = stdin.is_terminal();
+        let mut lines = stdin.lock().lines();
+        let mut buf = String::new();
+        loop {
+            if interactive {
+                print!("{}", if buf.is_empty() { "ti> " } else { "... " });
+                let _ = io::stdout().flush();
+            }
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            if buf.is_empty() && line.trim_start().starts_with(':') {
+                if !self.command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+            buf.push_str(&line);
+            buf.push('\n');
+            if is_incomplete(&buf) {
+                continue;
+            }
+            let input = std::mem::take(&mut buf);
+            if input.trim().is_empty() {
+                continue;
+            }
+            self.remember(input.trim_end());
+            let source = TiSource::new("<repl>".to_string(), input);
+            match self.eval(&source) {
+                Ok(Some(res)) => println!("{}", res),
+                Ok(None) => {}
+                Err(errs) => self.driver.report(&source, &errs),
+            }
+        }
+        0
+    }
+
+    /// Runs every statement of `source`, returning the value of the last one
+    /// when it is an expression with a value other than `()`.
+    pub fn eval(&mut self, source: &TiSource) -> Result<Option<TiObj>, Vec<TiError>> {
+        let ast = self.driver.parse_stmts(source)?;
+        let program = match self.driver.prepare(source, ast, &self.prelude())? {
+            AstNode::Program(program) => program.program,
+            ast_node => vec![ast_node],
+        };
+        let mut res = None;
+        for ast_node in program {
+            let is_expr = matches!(ast_node, AstNode::Expr(_));
+            let obj = self.vm.execute(&ast_node).map_err(|err| vec![err])?;
+            res = match obj {
+                TiObj::List(ref list) if list.is_empty() => None,
+                _ if is_expr => Some(obj),
+                _ => None,
+            };
+        }
+        Ok(res)
+    }
+
+    /// What the earlier inputs defined, which the next one may use.
+    fn prelude(&self) -> TiPrelude {
+        TiPrelude {
+            enums: self.vm.types.borrow().enums().cloned().collect(),
+            names: self.vm.environment.bindings().into_keys().collect(),
+        }
+    }
+
+    fn remember(&mut self, input: &str) {
+        self.history.push(input.to_string());
+        if let Some(path) = &self.history_path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", escape(input));
+            }
+        }
+    }
+
+    /// Runs a `:` meta-command, returns `false` when the repl should quit.
+    fn command(&mut self, line: &str) -> bool {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let source = TiSource::new("<repl>".to_string(), arg.to_string());
+        match command {
+            ":ast" => match self.driver.parse_stmts(&source) {
+                Ok(ast) => println!("{:#?}", ast),
+                Err(errs) => self.driver.report(&source, &errs),
+            },
+            ":tokens" => match self.driver.tokenize(&source) {
+                Ok(mut tokens) => {
+                    let renderer = TiErrorRenderer::new(&source.src);
+                    while !tokens.is_eof() {
+                        let token = tokens.next();
+                        let (line, col) = renderer.locate(token.t_at.1 .0);
+                        println!("{}:{}\t{:?}", line, col, token.t_type);
+                    }
+                }
+                Err(errs) => self.driver.report(&source, &errs),
+            },
+            ":env" => {
+                let mut bindings: Vec<_> = self
+                    .vm
+                    .environment
+                    .bindings()
+                    .into_iter()
+                    .filter(|(_, value)| !matches!(value, TiObj::Builtin(_, _)))
+                    .collect();
+                bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+                for (name, value) in bindings {
+                    println!("{} = {}", name, value);
+                }
+            }
+            ":reset" => self.vm = self.driver.vm(),
+            ":history" => {
+                for (idx, input) in self.history.iter().enumerate() {
+                    println!("{:>4}  {}", idx + 1, input);
+                }
+            }
+            ":help" => println!("{}", REPL_HELP),
+            ":quit" | ":q" | ":exit" => return false,
+            _ => eprintln!(
+                "{}: unknown command `{}`, try `:help`",
+                "error".red().bold(),
+                command
+            ),
+        }
+        true
+    }
+}
+
+/// Whether `src` still has an open bracket or string, so the next line
+/// continues the same input.
+fn is_incomplete(src: &str) -> bool {
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for c in src.chars() {
+        if in_str {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_str = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_str = true,
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    in_str || depth > 0
+}
+
+/// Puts `input` on one line of the history: each `\` is doubled and each
+/// line break becomes `\n`.
+fn escape(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// The input `escape` put on `line`. A `\` before anything else is kept, as
+/// in a history written before inputs were escaped.
+fn unescape(line: &str) -> String {
+    let mut input = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('\\')) => {
+                chars.next();
+                input.push('\\');
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                input.push('\n');
+            }
+            (c, _) => input.push(c),
+        }
+    }
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_multi_line_inputs_whole() {
+        let input = "fn f(s) {\n    println(\"a\\nb\", s)\n}";
+        let line = escape(input);
+        assert!(!line.contains('\n'));
+        assert_eq!(unescape(&line), input);
+    }
+
+    #[test]
+    fn history_reads_unescaped_lines_as_they_are() {
+        assert_eq!(unescape(r#"println("\"a\"")"#), r#"println("\"a\"")"#);
+    }
+}
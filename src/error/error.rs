@@ -0,0 +1,104 @@
+use std::fmt;
+
+use crate::frontend::lexer::token::TokenAt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiErrorKind {
+    Lex,
+    Syntax,
+    Type,
+    Reference,
+    Compile,
+    Load,
+    Verify,
+    Runtime,
+    Warning,
+}
+
+impl TiErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TiErrorKind::Lex => "lex error",
+            TiErrorKind::Syntax => "syntax error",
+            TiErrorKind::Type => "type error",
+            TiErrorKind::Reference => "reference error",
+            TiErrorKind::Compile => "compile error",
+            TiErrorKind::Load => "load error",
+            TiErrorKind::Verify => "verify error",
+            TiErrorKind::Runtime => "runtime error",
+            TiErrorKind::Warning => "warning",
+        }
+    }
+}
+
+/// A secondary span attached to an error, e.g. "first defined here".
+#[derive(Debug, Clone)]
+pub struct TiErrorLabel {
+    pub at: TokenAt,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum TiErrorNote {
+    Note(String),
+    Help(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TiError {
+    pub kind: TiErrorKind,
+    pub message: String,
+    pub at: Option<TokenAt>,
+    pub labels: Vec<TiErrorLabel>,
+    pub notes: Vec<TiErrorNote>,
+}
+
+impl TiError {
+    pub fn new(kind: TiErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            at: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_at(mut self, at: TokenAt) -> Self {
+        self.at = Some(at);
+        self
+    }
+
+    pub fn with_label(mut self, at: TokenAt, message: String) -> Self {
+        self.labels.push(TiErrorLabel { at, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(TiErrorNote::Note(note));
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.notes.push(TiErrorNote::Help(help));
+        self
+    }
+}
+
+impl fmt::Display for TiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)?;
+        if let Some((line, (start, end))) = self.at {
+            write!(f, " (line {}:{}:{})", line, start, end)?;
+        }
+        for note in &self.notes {
+            match note {
+                TiErrorNote::Note(note) => write!(f, "\n\tnote: {}", note)?,
+                TiErrorNote::Help(help) => write!(f, "\n\thelp: {}", help)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TiError {}
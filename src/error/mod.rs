@@ -1,2 +1,3 @@
 pub mod error;
-pub mod macros;
\ No newline at end of file
+pub mod macros;
+pub mod render;
\ No newline at end of file
@@ -0,0 +1,282 @@
+use colored::{Color, Colorize};
+
+use crate::frontend::lexer::token::TokenAt;
+
+use super::error::{TiError, TiErrorKind, TiErrorNote};
+
+/// Renders a `TiError` against the source it was raised from, printing the
+/// offending lines with `^^^` under the primary span and `---` under every
+/// secondary label.
+pub struct TiErrorRenderer<'a> {
+    name: &'a str,
+    src: Vec<char>,
+    line_starts: Vec<usize>,
+    color: bool,
+}
+
+struct Mark<'a> {
+    col: usize,
+    len: usize,
+    primary: bool,
+    message: Option<&'a str>,
+}
+
+impl<'a> TiErrorRenderer<'a> {
+    pub fn new(src: &str) -> Self {
+        let src: Vec<char> = src.chars().collect();
+        let mut line_starts = vec![0];
+        for (idx, c) in src.iter().enumerate() {
+            if c == &'\n' {
+                line_starts.push(idx + 1);
+            }
+        }
+        Self {
+            name: "<input>",
+            src,
+            line_starts,
+            color: true,
+        }
+    }
+
+    pub fn with_name(mut self, name: &'a str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// `false` renders plain text without any escape codes, e.g. for logs
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+impl TiErrorRenderer<'_> {
+    /// Maps a char offset of a `TokenAt` to a 1-based (line, column).
+    pub fn locate(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        (line, offset - self.line_starts[line - 1] + 1)
+    }
+
+    fn line_text(&self, line: usize) -> String {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next) => next - 1,
+            None => self.src.len(),
+        };
+        self.src[start..end]
+            .iter()
+            .filter(|c| **c != '\r')
+            .map(|c| if *c == '\t' { ' ' } else { *c })
+            .collect()
+    }
+
+    fn paint(&self, text: &str, color: Color, bold: bool) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        if bold {
+            text.color(color).bold().to_string()
+        } else {
+            text.color(color).to_string()
+        }
+    }
+
+    fn mark<'m>(&self, at: &TokenAt, primary: bool, message: Option<&'m str>) -> (usize, Mark<'m>) {
+        let (start, end) = at.1;
+        let (line, col) = self.locate(start);
+        let line_len = self.line_text(line).chars().count();
+        // spans crossing a line break are cut at the end of the first line
+        let len = (end.saturating_sub(start))
+            .min((line_len + 1).saturating_sub(col))
+            .max(1);
+        (
+            line,
+            Mark {
+                col,
+                len,
+                primary,
+                message,
+            },
+        )
+    }
+
+    pub fn render(&self, err: &TiError) -> String {
+        let primary = match err.kind {
+            TiErrorKind::Warning => Color::Yellow,
+            _ => Color::Red,
+        };
+        let mut out = format!(
+            "{}: {}\n",
+            self.paint(err.kind.name(), primary, true),
+            self.paint(&err.message, Color::White, true)
+        );
+
+        let mut marks = Vec::new();
+        if let Some(at) = &err.at {
+            marks.push(self.mark(at, true, None));
+        }
+        for label in &err.labels {
+            marks.push(self.mark(&label.at, false, Some(label.message.as_str())));
+        }
+        let mut lines: Vec<usize> = marks.iter().map(|(line, _)| *line).collect();
+        lines.sort_unstable();
+        lines.dedup();
+
+        let width = lines.last().map_or(1, |line| line.to_string().len());
+        let pad = " ".repeat(width);
+        let gutter = self.paint("|", Color::Blue, true);
+
+        if let Some((line, mark)) = marks.first().filter(|(_, mark)| mark.primary) {
+            out += &format!(
+                "{}{} {}:{}:{}\n",
+                pad,
+                self.paint("-->", Color::Blue, true),
+                self.name,
+                line,
+                mark.col
+            );
+        }
+        if !lines.is_empty() {
+            out += &format!("{} {}\n", pad, gutter);
+        }
+        let mut last = None;
+        for line in lines {
+            if let Some(last) = last {
+                if line > last + 1 {
+                    out += &format!("{}\n", self.paint("...", Color::Blue, true));
+                }
+            }
+            last = Some(line);
+            out += &format!(
+                "{} {} {}\n",
+                self.paint(
+                    &format!("{:>width$}", line, width = width),
+                    Color::Blue,
+                    true
+                ),
+                gutter,
+                self.line_text(line)
+            );
+            for (_, mark) in marks.iter().filter(|(l, _)| *l == line) {
+                let (c, color) = if mark.primary {
+                    ('^', primary)
+                } else {
+                    ('-', Color::Blue)
+                };
+                let mut underline = c.to_string().repeat(mark.len);
+                if let Some(message) = mark.message {
+                    underline = format!("{} {}", underline, message);
+                }
+                out += &format!(
+                    "{} {} {}{}\n",
+                    pad,
+                    gutter,
+                    " ".repeat(mark.col - 1),
+                    self.paint(&underline, color, true)
+                );
+            }
+        }
+
+        for note in &err.notes {
+            let (title, color, note) = match note {
+                TiErrorNote::Note(note) => ("= note:", Color::White, note),
+                TiErrorNote::Help(help) => ("= help:", Color::Green, help),
+            };
+            out += &format!("{} {} {}\n", pad, self.paint(title, color, true), note);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = "fn main() {\n    let a = 1\n    let b = a\n\n\n    a + true\n}\n";
+
+    /// The span of the `n`th `text` in `SRC`, on `line`.
+    fn span(line: usize, text: &str, n: usize) -> TokenAt {
+        let start = SRC.match_indices(text).nth(n).unwrap().0;
+        (line, (start, start + text.len()))
+    }
+
+    fn render(err: &TiError) -> String {
+        TiErrorRenderer::new(SRC)
+            .with_name("test.ti")
+            .with_color(false)
+            .render(err)
+    }
+
+    #[test]
+    fn offsets_map_to_lines_and_columns() {
+        let renderer = TiErrorRenderer::new(SRC);
+        assert_eq!(renderer.locate(0), (1, 1));
+        assert_eq!(renderer.locate(11), (1, 12));
+        assert_eq!(renderer.locate(12), (2, 1));
+        assert_eq!(renderer.locate(SRC.find("true").unwrap()), (6, 9));
+    }
+
+    #[test]
+    fn the_primary_span_is_underlined() {
+        let err =
+            TiError::new(TiErrorKind::Type, "bad operand.".to_string()).with_at(span(6, "true", 0));
+        let expected = "\
+type error: bad operand.
+ --> test.ti:6:9
+  |
+6 |     a + true
+  |         ^^^^
+";
+        assert_eq!(render(&err), expected);
+    }
+
+    #[test]
+    fn labels_notes_and_help_follow_the_primary_span() {
+        let err = TiError::new(TiErrorKind::Type, "bad operand.".to_string())
+            .with_at(span(6, "true", 0))
+            .with_label(span(2, "a", 1), "first defined here".to_string())
+            .with_label(span(3, "a", 2), "used here".to_string())
+            .with_note("the operands must be numbers.".to_string())
+            .with_help("try `a + 1`.".to_string());
+        let expected = "\
+type error: bad operand.
+ --> test.ti:6:9
+  |
+2 |     let a = 1
+  |         - first defined here
+3 |     let b = a
+  |             - used here
+...
+6 |     a + true
+  |         ^^^^
+  = note: the operands must be numbers.
+  = help: try `a + 1`.
+";
+        assert_eq!(render(&err), expected);
+    }
+
+    #[test]
+    fn errors_without_a_span_print_only_their_message() {
+        let err = TiError::new(TiErrorKind::Runtime, "stack overflow.".to_string());
+        assert_eq!(render(&err), "runtime error: stack overflow.\n");
+    }
+
+    #[test]
+    fn spans_crossing_a_line_break_stop_at_its_end() {
+        let start = SRC.find("{").unwrap();
+        let err = TiError::new(TiErrorKind::Syntax, "unclosed.".to_string())
+            .with_at((1, (start, SRC.len())));
+        assert!(render(&err).ends_with("1 | fn main() {\n  |           ^\n"));
+    }
+
+    #[test]
+    fn color_is_left_out_of_plain_output() {
+        let err =
+            TiError::new(TiErrorKind::Type, "bad operand.".to_string()).with_at(span(6, "true", 0));
+        assert!(!render(&err).contains('\x1b'));
+        colored::control::set_override(true);
+        let colored = TiErrorRenderer::new(SRC).render(&err);
+        colored::control::unset_override();
+        assert!(colored.contains("\x1b["), "{:?}", colored);
+    }
+}
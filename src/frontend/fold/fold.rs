@@ -0,0 +1,326 @@
+use std::rc::Rc;
+
+use crate::{
+    frontend::{
+        lexer::token::TokenAt,
+        parser::ast::{AstBlock, AstExpr, AstNode},
+    },
+    vm::types::object_type::{TiBinaryOp, TiObj, TiUnaryOp},
+};
+
+/// Rewrites `ast_node` so the work that does not depend on the program's
+/// state is done once, before it runs: arithmetic, comparisons and string
+/// concatenation of literals, `%`, `**` and the bitwise operators on
+/// literals, `&&` and `||` with a constant left operand, `!` and `-` of
+/// literals, `if` on a constant condition and `while false`.
+/// Operations that would fail are left to fail at run time.
+pub fn fold(ast_node: AstNode) -> AstNode {
+    match ast_node {
+        AstNode::Program(mut program) => {
+            program.program = program.program.into_iter().map(fold).collect();
+            AstNode::Program(program)
+        }
+        AstNode::Fn(fdef, fbody) => AstNode::Fn(fdef, fold_block(fbody)),
+        AstNode::Impl(sname, tname, items) => {
+            AstNode::Impl(sname, tname, items.into_iter().map(fold).collect())
+        }
+        AstNode::Trait(mut tdef) => {
+            for field in tdef.fields.iter_mut() {
+                field.default = field.default.take().map(fold_block);
+            }
+            AstNode::Trait(tdef)
+        }
+        AstNode::Let(name, value) => AstNode::Let(name, value.map(fold_expr)),
+        AstNode::Expr(expr) => AstNode::Expr(fold_expr(expr)),
+        ast_node => ast_node,
+    }
+}
+
+fn fold_block(mut ast_block: AstBlock) -> AstBlock {
+    ast_block.block = ast_block.block.into_iter().map(fold).collect();
+    ast_block
+}
+
+fn fold_exprs(exprs: Vec<AstExpr>) -> Vec<AstExpr> {
+    exprs.into_iter().map(fold_expr).collect()
+}
+
+fn fold_expr(ast_expr: AstExpr) -> AstExpr {
+    match ast_expr {
+        AstExpr::Add(x, y, at) => binary(*x, *y, at, TiObj::add, AstExpr::Add),
+        AstExpr::Sub(x, y, at) => binary(*x, *y, at, TiObj::sub, AstExpr::Sub),
+        AstExpr::Mul(x, y, at) => binary(*x, *y, at, TiObj::mul, AstExpr::Mul),
+        AstExpr::Div(x, y, at) => binary(*x, *y, at, TiObj::div, AstExpr::Div),
+        AstExpr::Mod(x, y, at) => binary(*x, *y, at, TiObj::r#mod, AstExpr::Mod),
+        AstExpr::Pow(x, y, at) => binary(*x, *y, at, TiObj::pow, AstExpr::Pow),
+        AstExpr::BitAnd(x, y, at) => binary(*x, *y, at, TiObj::bit_and, AstExpr::BitAnd),
+        AstExpr::BitOr(x, y, at) => binary(*x, *y, at, TiObj::bit_or, AstExpr::BitOr),
+        AstExpr::BitXor(x, y, at) => binary(*x, *y, at, TiObj::bit_xor, AstExpr::BitXor),
+        AstExpr::Shl(x, y, at) => binary(*x, *y, at, TiObj::shl, AstExpr::Shl),
+        AstExpr::Shr(x, y, at) => binary(*x, *y, at, TiObj::shr, AstExpr::Shr),
+        AstExpr::Eq(x, y, at) => binary(*x, *y, at, TiObj::eq, AstExpr::Eq),
+        AstExpr::Neq(x, y, at) => binary(*x, *y, at, TiObj::neq, AstExpr::Neq),
+        AstExpr::Grt(x, y, at) => binary(*x, *y, at, TiObj::grt, AstExpr::Grt),
+        AstExpr::Les(x, y, at) => binary(*x, *y, at, TiObj::les, AstExpr::Les),
+        AstExpr::Geq(x, y, at) => binary(*x, *y, at, TiObj::geq, AstExpr::Geq),
+        AstExpr::Leq(x, y, at) => binary(*x, *y, at, TiObj::leq, AstExpr::Leq),
+        AstExpr::And(x, y, at) => match (fold_expr(*x), fold_expr(*y)) {
+            (AstExpr::LiteralBool(false), _) => AstExpr::LiteralBool(false),
+            (AstExpr::LiteralBool(true), AstExpr::LiteralBool(y)) => AstExpr::LiteralBool(y),
+            (x, y) => AstExpr::And(Box::new(x), Box::new(y), at),
+        },
+        AstExpr::Or(x, y, at) => match (fold_expr(*x), fold_expr(*y)) {
+            (AstExpr::LiteralBool(true), _) => AstExpr::LiteralBool(true),
+            (AstExpr::LiteralBool(false), AstExpr::LiteralBool(y)) => AstExpr::LiteralBool(y),
+            (x, y) => AstExpr::Or(Box::new(x), Box::new(y), at),
+        },
+        AstExpr::Not(x, at) => unary(*x, at, TiObj::not, AstExpr::Not),
+        AstExpr::Neg(x, at) => unary(*x, at, TiObj::neg, AstExpr::Neg),
+        AstExpr::AddEq(x, y, at) => AstExpr::AddEq(x, Box::new(fold_expr(*y)), at),
+        AstExpr::SubEq(x, y, at) => AstExpr::SubEq(x, Box::new(fold_expr(*y)), at),
+        AstExpr::MulEq(x, y, at) => AstExpr::MulEq(x, Box::new(fold_expr(*y)), at),
+        AstExpr::DivEq(x, y, at) => AstExpr::DivEq(x, Box::new(fold_expr(*y)), at),
+        AstExpr::ModEq(x, y, at) => AstExpr::ModEq(x, Box::new(fold_expr(*y)), at),
+        AstExpr::Assign(name, slot, value, at) => {
+            AstExpr::Assign(name, slot, Box::new(fold_expr(*value)), at)
+        }
+        AstExpr::AssignField(object, field, value) => AstExpr::AssignField(
+            Box::new(fold_expr(*object)),
+            field,
+            Box::new(fold_expr(*value)),
+        ),
+        AstExpr::FnCall(caller, args) => {
+            AstExpr::FnCall(Box::new(fold_expr(*caller)), fold_exprs(args))
+        }
+        AstExpr::Field(object, field) => AstExpr::Field(Box::new(fold_expr(*object)), field),
+        AstExpr::AssignIndex(object, index, value, at) => AstExpr::AssignIndex(
+            Box::new(fold_expr(*object)),
+            Box::new(fold_expr(*index)),
+            Box::new(fold_expr(*value)),
+            at,
+        ),
+        AstExpr::Index(object, index, at) => AstExpr::Index(
+            Box::new(fold_expr(*object)),
+            Box::new(fold_expr(*index)),
+            at,
+        ),
+        AstExpr::Try(x) => AstExpr::Try(Box::new(fold_expr(*x))),
+        AstExpr::Block(block) => AstExpr::Block(fold_block(block)),
+        AstExpr::Lambda(fdef, fbody) => {
+            AstExpr::Lambda(fdef, Rc::new(fold_block(Rc::unwrap_or_clone(fbody))))
+        }
+        AstExpr::IfElse(cond, true_case, false_case) => match fold_expr(*cond) {
+            AstExpr::LiteralBool(true) => AstExpr::Block(fold_block(true_case)),
+            AstExpr::LiteralBool(false) => AstExpr::Block(fold_block(false_case)),
+            cond => AstExpr::IfElse(
+                Box::new(cond),
+                fold_block(true_case),
+                fold_block(false_case),
+            ),
+        },
+        AstExpr::While(label, cond, body) => match fold_expr(*cond) {
+            AstExpr::LiteralBool(false) => AstExpr::LiteralUnit(Vec::new()),
+            cond => AstExpr::While(label, Box::new(cond), fold_block(body)),
+        },
+        AstExpr::For(label, name, expr, body) => {
+            AstExpr::For(label, name, Box::new(fold_expr(*expr)), fold_block(body))
+        }
+        AstExpr::Range(start, end, step, inclusive) => AstExpr::Range(
+            Box::new(fold_expr(*start)),
+            Box::new(fold_expr(*end)),
+            step.map(|step| Box::new(fold_expr(*step))),
+            inclusive,
+        ),
+        AstExpr::Return(value) => AstExpr::Return(Box::new(fold_expr(*value))),
+        AstExpr::Break(label, value) => AstExpr::Break(label, Box::new(fold_expr(*value))),
+        AstExpr::Match(expr, arms, at) => AstExpr::Match(
+            Box::new(fold_expr(*expr)),
+            arms.into_iter()
+                .map(|(pattern, body)| (pattern, fold_expr(body)))
+                .collect(),
+            at,
+        ),
+        AstExpr::LiteralUnit(items) => AstExpr::LiteralUnit(fold_exprs(items)),
+        AstExpr::LiteralArray(items) => AstExpr::LiteralArray(fold_exprs(items)),
+        AstExpr::LiteralMap(entries) => AstExpr::LiteralMap(
+            entries
+                .into_iter()
+                .map(|(key, value, at)| (fold_expr(key), fold_expr(value), at))
+                .collect(),
+        ),
+        AstExpr::LiteralStruct(name, fields) => AstExpr::LiteralStruct(
+            name,
+            fields
+                .into_iter()
+                .map(|(fname, expr)| (fname, fold_expr(expr)))
+                .collect(),
+        ),
+        ast_expr => ast_expr,
+    }
+}
+
+/// Folds the operand, then the operation itself when it's a literal.
+fn unary(
+    x: AstExpr,
+    at: TokenAt,
+    op: TiUnaryOp,
+    build: fn(Box<AstExpr>, TokenAt) -> AstExpr,
+) -> AstExpr {
+    let x = fold_expr(x);
+    let folded = literal(&x).and_then(|x| op(x).ok()).and_then(to_literal);
+    folded.unwrap_or_else(|| build(Box::new(x), at))
+}
+
+/// Folds the operands, then the operation itself when both are literals.
+fn binary(
+    x: AstExpr,
+    y: AstExpr,
+    at: TokenAt,
+    op: TiBinaryOp,
+    build: fn(Box<AstExpr>, Box<AstExpr>, TokenAt) -> AstExpr,
+) -> AstExpr {
+    let x = fold_expr(x);
+    let y = fold_expr(y);
+    let folded = match (literal(&x), literal(&y)) {
+        (Some(lhs), Some(rhs)) => op(lhs, &rhs).ok().and_then(to_literal),
+        _ => None,
+    };
+    folded.unwrap_or_else(|| build(Box::new(x), Box::new(y), at))
+}
+
+fn literal(ast_expr: &AstExpr) -> Option<TiObj> {
+    match ast_expr {
+        AstExpr::LiteralNum(x) => Some(TiObj::Num(*x)),
+        AstExpr::LiteralStr(x) => Some(TiObj::Str(x.clone())),
+        AstExpr::LiteralBool(x) => Some(TiObj::Bool(*x)),
+        _ => None,
+    }
+}
+
+fn to_literal(obj: TiObj) -> Option<AstExpr> {
+    match obj {
+        TiObj::Num(x) => Some(AstExpr::LiteralNum(x)),
+        TiObj::Str(x) => Some(AstExpr::LiteralStr(x)),
+        TiObj::Bool(x) => Some(AstExpr::LiteralBool(x)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::{lexer::lexer::Lexer, parser::parser::Parser};
+
+    /// Folds the single statement of `src` and shows it as an s-expr.
+    fn folded(src: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(src).tokenize().unwrap());
+        parser.parse_stmts().unwrap();
+        match fold(parser.ast) {
+            AstNode::Program(program) => {
+                assert_eq!(program.program.len(), 1, "expected one statement");
+                show_node(&program.program[0])
+            }
+            other => panic!("expected a program, found {:?}", other),
+        }
+    }
+
+    fn show_node(node: &AstNode) -> String {
+        match node {
+            AstNode::Expr(expr) => show(expr),
+            AstNode::Let(name, Some(value)) => format!("let {} = {}", name, show(value)),
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    fn show_block(block: &AstBlock) -> String {
+        let stmts: Vec<_> = block.block.iter().map(show_node).collect();
+        format!("{{{}}}", stmts.join("; "))
+    }
+
+    fn show(expr: &AstExpr) -> String {
+        let (op, a, b) = match expr {
+            AstExpr::LiteralNum(x) => return x.to_string(),
+            AstExpr::LiteralStr(x) => return format!("{:?}", x),
+            AstExpr::LiteralBool(x) => return x.to_string(),
+            AstExpr::LiteralUnit(items) if items.is_empty() => return "()".to_string(),
+            AstExpr::Var(name, ..) => return name.to_string(),
+            AstExpr::Neg(a, ..) => return format!("(-{})", show(a)),
+            AstExpr::Not(a, ..) => return format!("(!{})", show(a)),
+            AstExpr::FnCall(f, args) => {
+                let args: Vec<_> = args.iter().map(show).collect();
+                return format!("{}({})", show(f), args.join(", "));
+            }
+            AstExpr::Block(block) => return show_block(block),
+            AstExpr::IfElse(cond, a, b) => {
+                return format!("if {} {} else {}", show(cond), show_block(a), show_block(b))
+            }
+            AstExpr::While(_, cond, body) => {
+                return format!("while {} {}", show(cond), show_block(body))
+            }
+            AstExpr::Add(a, b, ..) => ("+", a, b),
+            AstExpr::Sub(a, b, ..) => ("-", a, b),
+            AstExpr::Mul(a, b, ..) => ("*", a, b),
+            AstExpr::Div(a, b, ..) => ("/", a, b),
+            AstExpr::Mod(a, b, ..) => ("%", a, b),
+            AstExpr::Pow(a, b, ..) => ("**", a, b),
+            AstExpr::Shl(a, b, ..) => ("<<", a, b),
+            AstExpr::BitXor(a, b, ..) => ("^", a, b),
+            AstExpr::Eq(a, b, ..) => ("==", a, b),
+            AstExpr::Les(a, b, ..) => ("<", a, b),
+            AstExpr::And(a, b, ..) => ("&&", a, b),
+            AstExpr::Or(a, b, ..) => ("||", a, b),
+            other => panic!("unexpected expr {:?}", other),
+        };
+        format!("({} {} {})", show(a), op, show(b))
+    }
+
+    #[test]
+    fn literal_arithmetic_is_folded() {
+        assert_eq!(folded("1 + 2 * 3"), "7");
+        assert_eq!(folded("2 ** 10 % 1000"), "24");
+        assert_eq!(folded("-(4 - 6)"), "2");
+        assert_eq!(folded("1 << 4 ^ 3"), "19");
+        assert_eq!(folded("1 + 2 < 4"), "true");
+    }
+
+    #[test]
+    fn literal_strings_are_concatenated() {
+        assert_eq!(folded("\"a\" + \"b\" + \"c\""), "\"abc\"");
+    }
+
+    #[test]
+    fn only_the_literal_part_of_an_expr_is_folded() {
+        assert_eq!(folded("a + 2 * 3"), "(a + 6)");
+        assert_eq!(folded("f(1 + 1, a * (2 - 1))"), "f(2, (a * 1))");
+        assert_eq!(folded("let x = !(1 == 2)"), "let x = true");
+    }
+
+    #[test]
+    fn operations_that_fail_are_left_to_run_time() {
+        assert_eq!(folded("true + 1"), "(true + 1)");
+        assert_eq!(folded("1 << 64"), "(1 << 64)");
+        assert_eq!(folded("-\"a\""), "(-\"a\")");
+    }
+
+    #[test]
+    fn logic_on_a_constant_left_operand_short_circuits() {
+        assert_eq!(folded("false && f()"), "false");
+        assert_eq!(folded("true || f()"), "true");
+        assert_eq!(folded("true && false"), "false");
+        assert_eq!(folded("true && f()"), "(true && f())");
+        assert_eq!(folded("f() && false"), "(f() && false)");
+    }
+
+    #[test]
+    fn if_on_a_constant_keeps_only_the_branch_taken() {
+        assert_eq!(folded("if 1 < 2 { a } else { b }"), "{a}");
+        assert_eq!(folded("if false { a } else { b }"), "{b}");
+        assert_eq!(folded("if c { 1 + 1 } else { b }"), "if c {2} else {b}");
+    }
+
+    #[test]
+    fn while_false_is_dropped() {
+        assert_eq!(folded("while 1 > 2 { f() }"), "()");
+        assert_eq!(folded("while c { f(2 * 2) }"), "while c {f(4)}");
+    }
+}
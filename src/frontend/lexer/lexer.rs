@@ -1,26 +1,31 @@
 use std::{rc::Rc, string::FromUtf8Error};
 
-use super::token::{Token, TokenAt, TokenStream, TokenType};
+use crate::{build_ti_error, error::error::TiError};
 
-static SPACE_SIZE: u8 = 2;
+use super::token::{Token, TokenAt, TokenStream, TokenType};
 
 pub struct Lexer {
     src: Vec<char>,
     mark: usize,
+    mark_line: usize,
     curr: usize,
     line: usize,
 }
 
 impl Lexer {
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, FromUtf8Error> {
-        let src_str = String::from_utf8(bytes)?;
-        let src: Vec<char> = src_str.chars().collect();
-        Ok(Self {
-            src,
+    pub fn new(src: &str) -> Self {
+        Self {
+            src: src.chars().collect(),
             mark: 0,
+            mark_line: 1,
             curr: 0,
             line: 1,
-        })
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, FromUtf8Error> {
+        let src_str = String::from_utf8(bytes)?;
+        Ok(Self::new(&src_str))
     }
 }
 
@@ -40,28 +45,31 @@ impl Lexer {
         next
     }
     #[inline]
-    fn peek(&self) -> &char {
-        self.src.get(self.curr).unwrap()
+    fn peek(&self) -> Option<&char> {
+        self.src.get(self.curr)
     }
     #[inline]
     fn is_eof(&self) -> bool {
         self.curr >= self.src.len()
     }
 
+    /// span of the char just consumed by `next`
     #[inline]
     fn here(&self) -> TokenAt {
-        (self.line, (self.curr, self.curr + 1))
+        (self.line, (self.curr - 1, self.curr))
     }
+    /// marks the char just consumed by `next` as the start of a token
     #[inline]
     fn mark(&mut self) {
-        self.mark = self.curr;
+        self.mark = self.curr - 1;
+        self.mark_line = self.line;
     }
     #[inline]
-    fn range(&mut self) -> TokenAt {
-        (self.line, (self.mark, self.curr + 1))
+    fn range(&self) -> TokenAt {
+        (self.mark_line, (self.mark, self.curr))
     }
 
-    pub fn tokenize(&mut self) -> TokenStream {
+    pub fn tokenize(&mut self) -> Result<TokenStream, TiError> {
         let mut tokens = Vec::new();
 
         let mut ignore_nl = false;
@@ -89,7 +97,7 @@ impl Lexer {
                     tokens.push(Token::new(TokenType::Semi, self.here()));
                 }
                 '+' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorAddAssign, self.range()));
@@ -98,46 +106,33 @@ impl Lexer {
                     }
                 }
                 '-' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorSubAssign, self.range()));
-                    } else if self.peek() == &'>' {
+                    } else if self.peek() == Some(&'>') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorArrow, self.range()));
-                    } else if let '0'..='9' | '.' = self.peek() {
-                        self.mark();
-                        let mut token = String::new();
-                        while !self.is_eof() {
-                            let curr = self.next();
-                            match curr {
-                                '0'..='9' | '.' => {
-                                    token.push(*curr);
-                                }
-                                _ => {
-                                    self.backward();
-                                    break;
-                                }
-                            }
-                        }
-                        let num = -token.parse::<f64>().unwrap();
-                        tokens.push(Token::new(TokenType::LiteralNum(num), self.range()));
                     } else {
                         tokens.push(Token::new(TokenType::OperatorSub, self.here()));
                     }
                 }
                 '*' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorMulAssign, self.range()));
+                    } else if self.peek() == Some(&'*') {
+                        self.mark();
+                        self.forward();
+                        tokens.push(Token::new(TokenType::OperatorPow, self.range()));
                     } else {
                         tokens.push(Token::new(TokenType::OperatorMul, self.here()));
                     }
                 }
                 '/' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorDivAssign, self.range()));
@@ -145,12 +140,21 @@ impl Lexer {
                         tokens.push(Token::new(TokenType::OperatorDiv, self.here()));
                     }
                 }
+                '%' => {
+                    if self.peek() == Some(&'=') {
+                        self.mark();
+                        self.forward();
+                        tokens.push(Token::new(TokenType::OperatorModAssign, self.range()));
+                    } else {
+                        tokens.push(Token::new(TokenType::OperatorMod, self.here()));
+                    }
+                }
                 '=' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorEq, self.range()));
-                    } else if self.peek() == &'>' {
+                    } else if self.peek() == Some(&'>') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorFatArrow, self.range()));
@@ -159,7 +163,7 @@ impl Lexer {
                     }
                 }
                 '!' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorNeq, self.range()));
@@ -168,16 +172,20 @@ impl Lexer {
                     }
                 }
                 '<' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorLeq, self.range()));
+                    } else if self.peek() == Some(&'<') {
+                        self.mark();
+                        self.forward();
+                        tokens.push(Token::new(TokenType::OperatorShl, self.range()));
                     } else {
                         tokens.push(Token::new(TokenType::OperatorLes, self.here()));
                     }
                 }
                 '>' => {
-                    if self.peek() == &'=' {
+                    if self.peek() == Some(&'=') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorGeq, self.range()));
@@ -186,7 +194,7 @@ impl Lexer {
                     }
                 }
                 '&' => {
-                    if self.peek() == &'&' {
+                    if self.peek() == Some(&'&') {
                         self.mark();
                         self.forward();
                         tokens.push(Token::new(TokenType::OperatorAnd, self.range()));
@@ -195,29 +203,60 @@ impl Lexer {
                     }
                 }
                 ':' => {
-                    tokens.push(Token::new(TokenType::OperatorColon, self.here()));
+                    if self.peek() == Some(&':') {
+                        self.mark();
+                        self.forward();
+                        tokens.push(Token::new(TokenType::OperatorPath, self.range()));
+                    } else {
+                        tokens.push(Token::new(TokenType::OperatorColon, self.here()));
+                    }
                 }
                 ',' => {
                     tokens.push(Token::new(TokenType::OperatorComma, self.here()));
                 }
+                '.' => {
+                    if self.peek() == Some(&'.') {
+                        self.mark();
+                        self.forward();
+                        if self.peek() == Some(&'=') {
+                            self.forward();
+                            tokens
+                                .push(Token::new(TokenType::OperatorRangeInclusive, self.range()));
+                        } else {
+                            tokens.push(Token::new(TokenType::OperatorRange, self.range()));
+                        }
+                    } else {
+                        tokens.push(Token::new(TokenType::OperatorDot, self.here()));
+                    }
+                }
                 '|' => {
-                    if self.peek() == &'|' {
+                    if self.peek() == Some(&'|') {
                         self.mark();
                         self.forward();
-                        tokens.push(Token::new(TokenType::OperatorAnd, self.range()));
+                        tokens.push(Token::new(TokenType::OperatorOr, self.range()));
                     } else {
-                        tokens.push(Token::new(TokenType::OperatorRef, self.here()));
+                        tokens.push(Token::new(TokenType::OperatorBitOr, self.here()));
                     }
                 }
+                '^' => {
+                    tokens.push(Token::new(TokenType::OperatorBitXor, self.here()));
+                }
+                '?' => {
+                    tokens.push(Token::new(TokenType::OperatorTry, self.here()));
+                }
                 '0'..='9' => {
                     self.mark();
                     self.backward();
                     let mut token = String::new();
                     while !self.is_eof() {
-                        let curr = self.next();
+                        let curr = *self.next();
                         match curr {
-                            '0'..='9' | '.' => {
-                                token.push(*curr);
+                            '0'..='9' => {
+                                token.push(curr);
+                            }
+                            // `1..` starts a range
+                            '.' if self.peek() != Some(&'.') => {
+                                token.push('.');
                             }
                             _ => {
                                 self.backward();
@@ -225,15 +264,36 @@ impl Lexer {
                             }
                         }
                     }
-                    let num = token.parse().unwrap();
+                    let num = match token.parse() {
+                        Ok(num) => num,
+                        Err(_) => {
+                            build_ti_error!(@span self.range(), @err Lex, "invalid number literal `{}`", token)
+                        }
+                    };
                     tokens.push(Token::new(TokenType::LiteralNum(num), self.range()));
                 }
+                '\'' => {
+                    self.mark();
+                    let mut token = String::new();
+                    while let Some(curr @ ('a'..='z' | 'A'..='Z' | '_' | '0'..='9')) = self.peek() {
+                        token.push(*curr);
+                        self.forward();
+                    }
+                    if token.is_empty() {
+                        build_ti_error!(@span self.range(), @err Lex, "expect a label name after `'`")
+                    }
+                    tokens.push(Token::new(TokenType::Label(Rc::new(token)), self.range()));
+                }
                 '"' => {
                     self.mark();
                     let mut token = String::new();
                     let mut nf = false;
+                    let mut closed = false;
                     while !self.is_eof() {
-                        let curr = self.next();
+                        let curr = *self.next();
+                        if curr == '\n' {
+                            self.line += 1;
+                        }
                         if nf {
                             nf = false;
                             match curr {
@@ -247,48 +307,35 @@ impl Lexer {
                                     token.push('\t');
                                 }
                                 _ => {
-                                    token.push(*curr);
+                                    token.push(curr);
                                 }
                             }
                         } else {
                             match curr {
-                                '"' => break,
+                                '"' => {
+                                    closed = true;
+                                    break;
+                                }
                                 '\\' => {
                                     nf = true;
                                 }
                                 _ => {
-                                    token.push(*curr);
+                                    token.push(curr);
                                 }
                             }
                         }
                     }
+                    if !closed {
+                        build_ti_error!(@span self.range(), @err Lex, "unterminated string literal")
+                    }
                     tokens.push(Token::new(TokenType::LiteralStr(token), self.range()));
                 }
-                '\n' | '\r' => {
+                '\n' => {
                     self.line += 1;
-                    self.mark();
-                    // self.forward();
-                    let mut tabs = 0;
-                    let mut spaces = 0;
-                    while !self.is_eof() {
-                        let curr = self.next();
-                        match curr {
-                            '\t' => {
-                                tabs += 1;
-                            }
-                            ' ' => {
-                                spaces += 1;
-                            }
-                            _ => {
-                                self.backward();
-                                break;
-                            }
-                        }
-                    }
                     if ignore_nl {
                         ignore_nl = false;
                     } else {
-                        // tokens.push(Token::new(TokenType::IdentTier(tabs + spaces / (SPACE_SIZE as usize)), self.range()));
+                        // tokens.push(Token::new(TokenType::IdentTier(tabs + spaces / SPACE_SIZE), self.range()));
                     }
                 }
                 '\\' => {
@@ -344,6 +391,21 @@ impl Lexer {
                         "for" => {
                             tokens.push(Token::new(TokenType::KeywordFor, self.range()));
                         }
+                        "match" => {
+                            tokens.push(Token::new(TokenType::KeywordMatch, self.range()));
+                        }
+                        "return" => {
+                            tokens.push(Token::new(TokenType::KeywordReturn, self.range()));
+                        }
+                        "break" => {
+                            tokens.push(Token::new(TokenType::KeywordBreak, self.range()));
+                        }
+                        "continue" => {
+                            tokens.push(Token::new(TokenType::KeywordContinue, self.range()));
+                        }
+                        "in" => {
+                            tokens.push(Token::new(TokenType::KeywordIn, self.range()));
+                        }
                         "true" => {
                             tokens.push(Token::new(TokenType::LiteralBool(true), self.range()));
                         }
@@ -361,7 +423,6 @@ impl Lexer {
                 _ => {}
             }
         }
-        TokenStream::new(tokens)
+        Ok(TokenStream::new(tokens))
     }
 }
-
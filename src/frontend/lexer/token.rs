@@ -6,16 +6,22 @@ pub enum TokenType {
     LiteralStr(String),
     LiteralBool(bool),
     Identifier(Rc<String>),
+    /// `'name`, naming a loop
+    Label(Rc<String>),
 
     OperatorAssign,
     OperatorAdd,
     OperatorSub,
     OperatorMul,
     OperatorDiv,
+    OperatorMod,
+    /// `**`
+    OperatorPow,
     OperatorAddAssign,
     OperatorSubAssign,
     OperatorMulAssign,
     OperatorDivAssign,
+    OperatorModAssign,
     OperatorEq,
     OperatorNeq,
     OperatorLes,
@@ -25,11 +31,24 @@ pub enum TokenType {
     OperatorAnd,
     OperatorOr,
     OperatorNot,
+    /// `&`, a reference in a type and bitwise and in an expr
     OperatorRef,
+    OperatorBitOr,
+    OperatorBitXor,
+    /// `<<`, `>>` is two `OperatorGrt` so that nested generics still close
+    OperatorShl,
     OperatorArrow,
     OperatorFatArrow,
     OperatorComma,
     OperatorColon,
+    OperatorDot,
+    /// `..`
+    OperatorRange,
+    /// `..=`
+    OperatorRangeInclusive,
+    OperatorPath,
+    /// `?`
+    OperatorTry,
     /* OperatorDeref,*/ // Equals to OperatorMul
     KeywordLet,
     KeywordFn,
@@ -41,7 +60,12 @@ pub enum TokenType {
     KeywordIf,
     KeywordWhile,
     KeywordFor,
+    KeywordIn,
     KeywordElse,
+    KeywordMatch,
+    KeywordReturn,
+    KeywordBreak,
+    KeywordContinue,
 
     OpenParen,
     CloseParen,
@@ -50,6 +74,7 @@ pub enum TokenType {
     OpenBracket,
     CloseBracket,
     Semi,
+    Eof,
 }
 
 pub type TokenAt = (usize, (usize, usize));
@@ -69,12 +94,22 @@ impl Token {
 #[derive(Debug)]
 pub struct TokenStream {
     tokens: Vec<Token>,
+    eof: Token,
     pub curr: usize,
 }
 
+#[allow(clippy::should_implement_trait)]
 impl TokenStream {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, curr: 0 }
+        let eof_at = match tokens.last() {
+            Some(token) => (token.t_at.0, (token.t_at.1 .1, token.t_at.1 .1)),
+            None => (1, (0, 0)),
+        };
+        Self {
+            tokens,
+            eof: Token::new(TokenType::Eof, eof_at),
+            curr: 0,
+        }
     }
 
     #[inline]
@@ -87,17 +122,20 @@ impl TokenStream {
     }
     #[inline]
     pub fn next(&mut self) -> &Token {
-        let next = self.tokens.get(self.curr).unwrap();
+        let next = self.tokens.get(self.curr).unwrap_or(&self.eof);
         self.curr += 1;
         next
     }
     #[inline]
     pub fn peek(&self) -> &Token {
-        self.tokens.get(self.curr).unwrap()
+        self.tokens.get(self.curr).unwrap_or(&self.eof)
     }
     #[inline]
     pub fn last(&self) -> &Token {
-        self.tokens.get(self.curr - 1).unwrap()
+        self.curr
+            .checked_sub(1)
+            .and_then(|idx| self.tokens.get(idx))
+            .unwrap_or(&self.eof)
     }
     #[inline]
     pub fn is_eof(&self) -> bool {
@@ -111,7 +149,7 @@ impl TokenStream {
 
     #[inline]
     pub fn assert_next_ident(&mut self) -> bool {
-        if let TokenType::Identifier(_) = self.tokens.get(self.curr).unwrap().t_type {
+        if let TokenType::Identifier(_) = self.peek().t_type {
             self.forward();
             true
         } else {
@@ -121,7 +159,7 @@ impl TokenStream {
 
     #[inline]
     pub fn assert_next(&mut self, t_type: TokenType) -> bool {
-        let res = self.tokens.get(self.curr).unwrap().t_type == t_type;
+        let res = self.peek().t_type == t_type;
         if res {
             self.forward();
             true
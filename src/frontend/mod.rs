@@ -1,2 +1,4 @@
+pub mod fold;
 pub mod lexer;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod resolve;
\ No newline at end of file
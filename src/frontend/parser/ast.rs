@@ -1,4 +1,6 @@
-use std::rc::Rc;
+use std::{fmt, rc::Rc};
+
+use crate::frontend::lexer::token::TokenAt;
 
 #[derive(Debug, Clone)]
 pub enum Type {
@@ -28,6 +30,52 @@ pub enum Type {
     Anna(Box<Type>, Vec<Type>),
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unknown => write!(f, "_"),
+            Type::Never => write!(f, "!"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::I128 => write!(f, "i128"),
+            Type::U8 => write!(f, "u8"),
+            Type::U16 => write!(f, "u16"),
+            Type::U32 => write!(f, "u32"),
+            Type::U64 => write!(f, "u64"),
+            Type::U128 => write!(f, "u128"),
+            Type::Bool => write!(f, "bool"),
+            Type::Ref(t) => write!(f, "&{}", t),
+            Type::Array(t, 0) => write!(f, "[{}]", t),
+            Type::Array(t, l) => write!(f, "[{}; {}]", t, l),
+            Type::Unit(ts) => {
+                write!(f, "(")?;
+                for (idx, t) in ts.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", t)?;
+                }
+                write!(f, ")")
+            }
+            Type::Costume(name) => write!(f, "{}", name),
+            Type::Anna(t, anna) => {
+                write!(f, "{}<", t)?;
+                for (idx, t) in anna.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", t)?;
+                }
+                write!(f, ">")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FnArg {
     pub name: Rc<String>,
@@ -45,35 +93,37 @@ pub struct FnDef {
     pub arguments: Vec<FnArg>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructField {
     pub name: Rc<String>,
+    pub ty: Type,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructDef {
     pub name: Rc<String>,
     pub fields: Vec<StructField>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EnumField {
     pub name: Rc<String>,
-    pub values: Vec<Rc<String>>,
+    pub values: Vec<Type>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EnumDef {
     pub name: Rc<String>,
     pub fields: Vec<EnumField>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TraitField {
     pub body: FnDef,
+    pub default: Option<AstBlock>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TraitDef {
     pub name: Rc<String>,
     pub fields: Vec<TraitField>,
@@ -137,76 +187,183 @@ impl<T> WithScope<T> {
 pub enum AstNode {
     Program(AstProgram),
     Fn(FnDef, AstBlock),
-    Impl(Rc<String>, Vec<AstNode>),
+    Impl(Rc<String>, Option<Rc<String>>, Vec<AstNode>),
+    Struct(StructDef),
+    Enum(EnumDef),
+    Trait(TraitDef),
     Let(Rc<String>, Option<AstExpr>),
     Expr(AstExpr),
     // Block(AstBlock),
     Empty,
 }
 
-#[derive(Debug, Clone)]
+impl AstNode {
+    /// When a top level item runs: types first, so `impl` blocks and
+    /// functions may come before the types they use, then functions, so they
+    /// can call each other and be called before their definition, then the
+    /// rest in order.
+    pub fn hoisting(&self) -> u8 {
+        match self {
+            AstNode::Struct(_) | AstNode::Enum(_) | AstNode::Trait(_) => 0,
+            AstNode::Fn(_, _) | AstNode::Impl(_, _, _) => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct AstBlock {
     pub block: Vec<AstNode>,
+    /// where each statement of `block` starts
+    pub at: Vec<TokenAt>,
+    /// the variables of the frames around it that the function whose body
+    /// this is uses, which the resolver fills in
+    pub captures: Vec<VarSlot>,
 }
 
 impl AstBlock {
     pub fn new() -> Self {
-        Self { block: Vec::new() }
+        Self {
+            block: Vec::new(),
+            at: Vec::new(),
+            captures: Vec::new(),
+        }
     }
 
-    pub fn add(&mut self, ast_node: AstNode) {
+    pub fn add(&mut self, ast_node: AstNode, at: TokenAt) {
         self.block.push(ast_node);
+        self.at.push(at);
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct AstProgram {
     pub program: Vec<AstNode>,
+    /// where each item of `program` starts
+    pub at: Vec<TokenAt>,
 }
 
 impl AstProgram {
     pub fn new() -> Self {
         Self {
             program: Vec::new(),
+            at: Vec::new(),
         }
     }
 
-    pub fn add(&mut self, ast_node: AstNode) {
+    pub fn add(&mut self, ast_node: AstNode, at: TokenAt) {
         self.program.push(ast_node);
+        self.at.push(at);
     }
 }
 
+/// Where the resolver found a variable: `depth` frames out from the
+/// innermost one, at `slot` of that frame. A variable it leaves at `None` is
+/// a global, looked up by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VarSlot {
+    pub depth: usize,
+    pub slot: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum AstExpr {
-    Neg(Box<AstExpr>),
-    Add(Box<AstExpr>, Box<AstExpr>),
-    Sub(Box<AstExpr>, Box<AstExpr>),
-    Mul(Box<AstExpr>, Box<AstExpr>),
-    Div(Box<AstExpr>, Box<AstExpr>),
-    AddEq(Box<AstExpr>, Box<AstExpr>),
-    SubEq(Box<AstExpr>, Box<AstExpr>),
-    MulEq(Box<AstExpr>, Box<AstExpr>),
-    DivEq(Box<AstExpr>, Box<AstExpr>),
-    Eq(Box<AstExpr>, Box<AstExpr>),
-    Neq(Box<AstExpr>, Box<AstExpr>),
-    Grt(Box<AstExpr>, Box<AstExpr>),
-    Les(Box<AstExpr>, Box<AstExpr>),
-    Geq(Box<AstExpr>, Box<AstExpr>),
-    Leq(Box<AstExpr>, Box<AstExpr>),
-    Not(Box<AstExpr>),
-    And(Box<AstExpr>, Box<AstExpr>),
-    Or(Box<AstExpr>, Box<AstExpr>),
-    Assign(Rc<String>, Box<AstExpr>),
+    // the operators, where the `TokenAt` spans the operator for the errors
+    // it may raise
+    Neg(Box<AstExpr>, TokenAt),
+    Add(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Sub(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Mul(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Div(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Mod(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Pow(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    BitAnd(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    BitOr(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    BitXor(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Shl(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Shr(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    AddEq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    SubEq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    MulEq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    DivEq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    ModEq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Eq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Neq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Grt(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Les(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Geq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Leq(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Not(Box<AstExpr>, TokenAt),
+    And(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    Or(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    /// `x = v`, where `at` spans the `x`
+    Assign(Rc<String>, Option<VarSlot>, Box<AstExpr>, TokenAt),
+    AssignField(Box<AstExpr>, Rc<String>, Box<AstExpr>),
+    /// `a[i] = v`, where `at` spans the `[i]`
+    AssignIndex(Box<AstExpr>, Box<AstExpr>, Box<AstExpr>, TokenAt),
     FnCall(Box<AstExpr>, Vec<AstExpr>),
-    Var(Rc<String>),
+    /// a variable, which the resolver finds the slot of, where `at` spans
+    /// its name
+    Var(Rc<String>, Option<VarSlot>, TokenAt),
+    Field(Box<AstExpr>, Rc<String>),
+    /// `a[i]`, the item of a list, the char of a string or the value of a
+    /// map at `i`, where `at` spans the `[i]` for the errors it may raise
+    Index(Box<AstExpr>, Box<AstExpr>, TokenAt),
+    /// `e?`, the payload of `Some(x)` or `Ok(x)`, returning `None` or
+    /// `Err(e)` from the function
+    Try(Box<AstExpr>),
+    Path(Rc<String>, Rc<String>),
+    /// `fn(a, b) => a + b` or `fn(a, b) { ... }`, a function without a name
+    /// that captures the variables around it
+    Lambda(FnDef, Rc<AstBlock>),
 
     Block(AstBlock),
 
     IfElse(Box<AstExpr>, AstBlock, AstBlock),
-    While(Box<AstExpr>, AstBlock),
-    // For(Rc<String>, Box<AstExpr>, AstBlock)
+    /// a loop, with the label `break` and `continue` may name it by
+    While(Option<Rc<String>>, Box<AstExpr>, AstBlock),
+    Return(Box<AstExpr>),
+    /// leaves the innermost loop, or the one with the label, with a value
+    Break(Option<Rc<String>>, Box<AstExpr>),
+    Continue(Option<Rc<String>>),
+    /// `match e { p => v, ... }`, where `at` spans the `match` for the
+    /// errors about its patterns
+    Match(Box<AstExpr>, Vec<(AstPattern, AstExpr)>, TokenAt),
+    /// `for x in e { ... }`, running the block with `x` bound to each value
+    /// of `e`
+    For(Option<Rc<String>>, Rc<String>, Box<AstExpr>, AstBlock),
+    /// `a..b`, or `a..=b` when inclusive, counting by the value after `step`,
+    /// 1 when there is none
+    Range(Box<AstExpr>, Box<AstExpr>, Option<Box<AstExpr>>, bool),
     LiteralNum(f64),
     LiteralStr(String),
+    LiteralBool(bool),
     LiteralUnit(Vec<AstExpr>),
     LiteralArray(Vec<AstExpr>),
+    /// `{k: v, ...}`, the entries of a map in order with the span of each
+    /// key
+    LiteralMap(Vec<(AstExpr, AstExpr, TokenAt)>),
+    LiteralStruct(Rc<String>, Vec<(Rc<String>, AstExpr)>),
+}
+
+impl AstExpr {
+    /// Whether this names a location that can be assigned to: a variable,
+    /// or a field or an item of one.
+    pub fn is_place(&self) -> bool {
+        match self {
+            AstExpr::Var(..) => true,
+            AstExpr::Field(object, _) | AstExpr::Index(object, _, _) => object.is_place(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AstPattern {
+    Wildcard,
+    Binding(Rc<String>),
+    LiteralNum(f64),
+    LiteralStr(String),
+    LiteralBool(bool),
+    Variant(Rc<String>, Rc<String>, Vec<AstPattern>),
 }
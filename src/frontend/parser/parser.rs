@@ -2,13 +2,97 @@ use std::rc::Rc;
 
 use crate::{
     build_ti_error,
-    frontend::lexer::token::{TokenStream, TokenType},
+    error::error::{TiError, TiErrorKind},
+    frontend::lexer::token::{TokenAt, TokenStream, TokenType},
 };
 
 use super::ast::*;
 
+/// Builds the expr of a binary operator from its operands.
+type BinaryExpr = fn(Box<AstExpr>, Box<AstExpr>, TokenAt) -> AstExpr;
+
+/// How an infix operator groups with another one of the same precedence.
+#[derive(Debug, Clone, Copy)]
+enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a = b = c` is `a = (b = c)`
+    Right,
+    /// `a < b < c` is an error
+    Neither,
+}
+
+/// What an infix operator builds from the exprs on either side.
+#[derive(Clone, Copy)]
+enum Infix {
+    Binary(BinaryExpr),
+    /// `=`, whose left side has to be a place
+    Assign,
+    /// `+=` and the like, whose left side has to be a place
+    Compound(BinaryExpr),
+    /// `..` when not inclusive, `..=` when inclusive, with an optional `step`
+    Range(bool),
+}
+
+// The precedence of the operators, higher binding tighter. Calls, fields,
+// indexes and `?` after an operand bind tighter than all of them.
+const ASSIGN: u8 = 1;
+const RANGE: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const COMPARE: u8 = 5;
+const BIT_OR: u8 = 6;
+const BIT_XOR: u8 = 7;
+const BIT_AND: u8 = 8;
+const SHIFT: u8 = 9;
+const SUM: u8 = 10;
+const PRODUCT: u8 = 11;
+/// `-` and `!` in front of an operand, so `-a * b` is `(-a) * b` but
+/// `-a ** b` is `-(a ** b)`
+const PREFIX: u8 = 12;
+const POW: u8 = 13;
+
+/// The precedence, associativity and meaning of the infix operator
+/// `t_type`. `>>` is two `>` tokens and is looked up apart.
+fn infix(t_type: &TokenType) -> Option<(u8, Assoc, Infix)> {
+    use Assoc::*;
+    use Infix::*;
+    use TokenType::*;
+    let op = match t_type {
+        OperatorAssign => (ASSIGN, Right, Assign),
+        OperatorAddAssign => (ASSIGN, Right, Compound(AstExpr::AddEq)),
+        OperatorSubAssign => (ASSIGN, Right, Compound(AstExpr::SubEq)),
+        OperatorMulAssign => (ASSIGN, Right, Compound(AstExpr::MulEq)),
+        OperatorDivAssign => (ASSIGN, Right, Compound(AstExpr::DivEq)),
+        OperatorModAssign => (ASSIGN, Right, Compound(AstExpr::ModEq)),
+        OperatorRange => (RANGE, Neither, Range(false)),
+        OperatorRangeInclusive => (RANGE, Neither, Range(true)),
+        OperatorOr => (OR, Left, Binary(AstExpr::Or)),
+        OperatorAnd => (AND, Left, Binary(AstExpr::And)),
+        OperatorEq => (COMPARE, Neither, Binary(AstExpr::Eq)),
+        OperatorNeq => (COMPARE, Neither, Binary(AstExpr::Neq)),
+        OperatorLes => (COMPARE, Neither, Binary(AstExpr::Les)),
+        OperatorGrt => (COMPARE, Neither, Binary(AstExpr::Grt)),
+        OperatorLeq => (COMPARE, Neither, Binary(AstExpr::Leq)),
+        OperatorGeq => (COMPARE, Neither, Binary(AstExpr::Geq)),
+        OperatorBitOr => (BIT_OR, Left, Binary(AstExpr::BitOr)),
+        OperatorBitXor => (BIT_XOR, Left, Binary(AstExpr::BitXor)),
+        OperatorRef => (BIT_AND, Left, Binary(AstExpr::BitAnd)),
+        OperatorShl => (SHIFT, Left, Binary(AstExpr::Shl)),
+        OperatorAdd => (SUM, Left, Binary(AstExpr::Add)),
+        OperatorSub => (SUM, Left, Binary(AstExpr::Sub)),
+        OperatorMul => (PRODUCT, Left, Binary(AstExpr::Mul)),
+        OperatorDiv => (PRODUCT, Left, Binary(AstExpr::Div)),
+        OperatorMod => (PRODUCT, Left, Binary(AstExpr::Mod)),
+        OperatorPow => (POW, Right, Binary(AstExpr::Pow)),
+        _ => return None,
+    };
+    Some(op)
+}
+
 #[derive(Debug)]
 pub struct Parser {
+    #[allow(dead_code)]
     sym_id: usize,
     pub fn_def: Vec<WithScope<FnDef>>,
     pub struct_def: Vec<WithScope<StructDef>>,
@@ -17,6 +101,14 @@ pub struct Parser {
     pub var_def: Vec<WithScope<VarDef>>,
     pub tokens: TokenStream,
     pub ast: AstNode,
+    pub diagnostics: Vec<TiError>,
+    /// set while parsing the condition of `if` and `while`, where `Name {`
+    /// starts the body rather than a struct literal
+    no_struct_lit: bool,
+    /// the labels of the loops around the expr being parsed, innermost last
+    loops: Vec<Option<Rc<String>>>,
+    /// set while parsing the body of a function, where `return` is allowed
+    in_fn: bool,
 }
 
 impl Parser {
@@ -30,27 +122,105 @@ impl Parser {
             var_def: Vec::new(),
             tokens,
             ast: AstNode::Empty,
+            diagnostics: Vec::new(),
+            no_struct_lit: false,
+            loops: Vec::new(),
+            in_fn: false,
         }
     }
 }
 
 impl Parser {
-    pub fn parse(&mut self) {
+    /// Parses the whole token stream into `self.ast`.
+    ///
+    /// Syntax errors don't stop the parser: each one is pushed to
+    /// `self.diagnostics`, the parser synchronizes on the next item, and the
+    /// nodes parsed successfully are still kept as a partial `AstProgram`.
+    pub fn parse(&mut self) -> Result<(), TiError> {
         let mut program = AstProgram::new();
         while !self.tokens.is_eof() {
+            let at = self.tokens.peek().t_at;
             // if !self.tokens.assert_next_tier(0) { build_ti_error!(@at self.tokens.peek(), @err "Unexpected Token `{:?}`", self.tokens.peek()) }
             // if self.tokens.is_eof() { break }
-            if let Some(ast_node) = self.parse_definion(Scope::Global) {
-                if let Some(ast_node) = ast_node {
-                    program.add(ast_node);
+            match self.parse_item() {
+                Ok(Some(ast_node)) => program.add(ast_node, at),
+                Ok(None) => {}
+                Err(err) => {
+                    self.recover(err);
+                    // a stray `}` can't close anything at the top level
+                    self.tokens.assert_next(TokenType::CloseBracket);
+                }
+            }
+        }
+        self.ast = AstNode::Program(program);
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            build_ti_error!(@err Syntax, "aborting due to {} previous error(s)", self.diagnostics.len())
+        }
+    }
+
+    /// Parses the token stream as a sequence of statements or definitions,
+    /// the way the REPL reads its input, with the same recovery as `parse`.
+    pub fn parse_stmts(&mut self) -> Result<(), TiError> {
+        let mut program = AstProgram::new();
+        while !self.tokens.is_eof() {
+            let at = self.tokens.peek().t_at;
+            match self.parse_stmt(&Scope::Global) {
+                Ok(ast_node) => program.add(ast_node, at),
+                Err(err) => {
+                    self.recover(err);
+                    self.tokens.assert_next(TokenType::CloseBracket);
                 }
-            } else {
-                self.tokens.forward();
-                // println!("{:#?}", self);
-                // panic!("Unexpeced Token `{:?}`.", self.tokens.next())
             }
         }
         self.ast = AstNode::Program(program);
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            build_ti_error!(@err Syntax, "aborting due to {} previous error(s)", self.diagnostics.len())
+        }
+    }
+
+    fn parse_item(&mut self) -> Result<Option<AstNode>, TiError> {
+        if let Some(ast_node) = self.parse_definion(Scope::Global)? {
+            Ok(ast_node)
+        } else {
+            let token = self.tokens.peek();
+            build_ti_error!(@at token, @err Syntax, "Expect an Item (`fn`, `let`, `struct`, `enum`, `trait` or `impl`), found {:?}", token.t_type)
+        }
+    }
+
+    /// Records `err` and skips tokens until a point where parsing can resume:
+    /// the next `fn` or `let` (or other definition), a `}` or a new line.
+    fn recover(&mut self, err: TiError) {
+        let line = err.at.map_or(self.tokens.last().t_at.0, |at| at.0);
+        if self.tokens.last().t_type == TokenType::CloseBracket
+            && Some(self.tokens.last().t_at) == err.at
+        {
+            // the offending `}` still has to close its block
+            self.tokens.backward();
+        }
+        self.diagnostics.push(err);
+        while !self.tokens.is_eof() {
+            let token = self.tokens.peek();
+            let next = self
+                .tokens
+                .at(self.tokens.curr + 1)
+                .map(|token| &token.t_type);
+            match token.t_type {
+                // not `fn(`, an anonymous function within the bad statement
+                TokenType::KeywordFn if next != Some(&TokenType::OpenParen) => break,
+                TokenType::KeywordLet
+                | TokenType::KeywordStruct
+                | TokenType::KeywordEnum
+                | TokenType::KeywordTrait
+                | TokenType::KeywordImpl
+                | TokenType::CloseBracket => break,
+                _ if token.t_at.0 > line => break,
+                _ => self.tokens.forward(),
+            }
+        }
     }
 
     /* fn sym_name(&mut self) -> Rc<String> {
@@ -58,354 +228,1113 @@ impl Parser {
         Rc::new(format!("${}", self.sym_id))
     } */
 
-    fn parse_definion(&mut self, scope: Scope) -> Option<Option<AstNode>> {
-        let token = self.tokens.next();
-        match token.t_type {
-            TokenType::KeywordFn => Some(Some(self.parse_fn_definion(scope))),
-            TokenType::KeywordEnum => {
-                self.parse_enum_definion(scope);
-                Some(None)
-            }
-            TokenType::KeywordStruct => {
-                self.parse_struct_definion(scope);
-                Some(None)
+    fn parse_definion(&mut self, scope: Scope) -> Result<Option<Option<AstNode>>, TiError> {
+        let token = self.tokens.next().t_type.clone();
+        match token {
+            // `fn(` starts an anonymous function, which is an expr
+            TokenType::KeywordFn if self.tokens.peek().t_type != TokenType::OpenParen => {
+                Ok(Some(Some(self.parse_fn_definion(scope)?)))
             }
-            TokenType::KeywordTrait => {
-                self.parse_trait_definion(scope);
-                Some(None)
-            }
-            TokenType::KeywordImpl => Some(self.parse_impl_definion(scope)),
+            TokenType::KeywordEnum => Ok(Some(Some(self.parse_enum_definion(scope)?))),
+            TokenType::KeywordStruct => Ok(Some(Some(self.parse_struct_definion(scope)?))),
+            TokenType::KeywordTrait => Ok(Some(Some(self.parse_trait_definion(scope)?))),
+            TokenType::KeywordImpl => Ok(Some(Some(self.parse_impl_definion(scope)?))),
             TokenType::KeywordLet => {
                 if let TokenType::Identifier(x) = &self.tokens.next().t_type {
                     let name = x.clone();
                     let expr = if self.tokens.assert_next(TokenType::OperatorAssign) {
-                        let expr = self.parse_expr();
+                        let expr = self.parse_expr()?;
                         Some(expr)
                     } else {
                         None
                     };
-                    Some(Some(AstNode::Let(name, expr)))
+                    Ok(Some(Some(AstNode::Let(name, expr))))
                 } else {
-                    build_ti_error!(@at self.tokens.last(), @err "Expect Identifier, found {:?}", self.tokens.last())
+                    build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
                 }
             }
             _ => {
                 self.tokens.backward();
-                self.tokens.backward();
-                None
+                Ok(None)
             }
         }
     }
 
-    fn parse_fn_definion(&mut self, _scope: Scope) -> AstNode {
+    fn parse_fn_definion(&mut self, _scope: Scope) -> Result<AstNode, TiError> {
+        let fdef = self.parse_fn_signature()?;
+        let fbody = self.parse_fn_body()?;
+        Ok(AstNode::Fn(fdef, fbody))
+    }
+
+    fn parse_fn_signature(&mut self) -> Result<FnDef, TiError> {
         let fname;
-        let mut fargs: Vec<FnArg> = Vec::new();
         match self.tokens.next().t_type.clone() {
             TokenType::OperatorLes => {
                 // fn<TN: TT[, ...]> FN(FA: FT[, ...])
-                todo!()
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Generic Functions are not Supported Yet")
             }
             TokenType::Identifier(n) => {
                 // fn FN(FA: FT[, ...])
                 fname = n;
             }
             _ => {
-                build_ti_error!(@at self.tokens.peek(), @err "Unexpect Token `{:?}`", self.tokens.peek())
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Unexpect Token `{:?}`", self.tokens.last().t_type)
             }
         }
+        let fdef = self.parse_fn_params(fname)?;
+        self.fn_def.push(WithScope::global(fdef.clone()));
+        Ok(fdef)
+    }
+
+    fn parse_fn_params(&mut self, fname: Rc<String>) -> Result<FnDef, TiError> {
+        let mut fargs: Vec<FnArg> = Vec::new();
+        let mut fargs_at: Vec<TokenAt> = Vec::new();
         if !self.tokens.assert_next(TokenType::OpenParen) {
-            build_ti_error!(@at self.tokens.peek(), @err "Expect Token `(`, found `{:?}`", self.tokens.peek())
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `(`, found `{:?}`", self.tokens.peek().t_type)
         }
+        let open_at = self.tokens.last().t_at;
         loop {
             if self.tokens.assert_next(TokenType::CloseParen) {
                 break;
             }
-            if let TokenType::Identifier(argn) = self.tokens.next().t_type.clone() {
-                let arg = FnArg::new(argn.clone());
-                fargs.push(arg);
+            let token = self.tokens.next();
+            if let TokenType::Identifier(argn) = token.t_type.clone() {
+                let arg_at = token.t_at;
+                if argn.as_str() == "self" && !fargs.is_empty() {
+                    build_ti_error!(@span arg_at, @err Syntax, "`self` Must be the First Parameter")
+                }
+                if let Some(idx) = fargs.iter().position(|farg| farg.name == argn) {
+                    return Err(TiError::new(
+                        TiErrorKind::Syntax,
+                        format!(
+                            "Identifier `{}` is Bound More Than Once in This Parameter List",
+                            argn
+                        ),
+                    )
+                    .with_at(arg_at)
+                    .with_label(fargs_at[idx], "first bound here".to_string()));
+                }
+                fargs.push(FnArg::new(argn.clone()));
+                fargs_at.push(arg_at);
             } else {
-                build_ti_error!(@at self.tokens.last(), @err "Expect Identifier or Token `)`, found {:?}", self.tokens.last())
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier or Token `)`, found {:?}", self.tokens.last().t_type)
             }
             if self.tokens.assert_next(TokenType::CloseParen) {
                 break;
             }
-            self.tokens.forward();
-            // self.tokens.forward();
+            if !self.tokens.assert_next(TokenType::OperatorComma) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `)`, found {:?}", self.tokens.peek().t_type)
+            }
             if self.tokens.is_eof() {
-                build_ti_error!(@err "Expect Token `)`, found `Eof`")
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `)`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
             }
         }
-        self.fn_def.push(WithScope::global(FnDef {
-            name: fname.clone(),
-            arguments: fargs.clone(),
-        }));
+        Ok(FnDef {
+            name: fname,
+            arguments: fargs,
+        })
+    }
 
-        let mut fbody;
+    /// Parses the body of a function, which the loops around it can't be
+    /// left from.
+    fn parse_fn_body(&mut self) -> Result<AstBlock, TiError> {
+        let loops = std::mem::take(&mut self.loops);
+        let in_fn = std::mem::replace(&mut self.in_fn, true);
+        let fbody = self.parse_fn_body_inner();
+        self.loops = loops;
+        self.in_fn = in_fn;
+        fbody
+    }
+
+    fn parse_fn_body_inner(&mut self) -> Result<AstBlock, TiError> {
         if self.tokens.assert_next(TokenType::OperatorFatArrow) {
-            let expr = self.parse_expr();
-            fbody = AstBlock::new();
-            fbody.add(AstNode::Expr(expr));
+            let at = self.tokens.peek().t_at;
+            let expr = self.parse_expr()?;
+            let mut fbody = AstBlock::new();
+            fbody.add(AstNode::Expr(expr), at);
+            Ok(fbody)
         } else {
-            fbody = self.parse_block();
+            self.parse_block()
         }
+    }
 
-        AstNode::Fn(
-            FnDef {
-                name: fname.clone(),
-                arguments: fargs,
-            },
-            fbody,
-        )
+    fn parse_enum_definion(&mut self, scope: Scope) -> Result<AstNode, TiError> {
+        // enum EN { VN[(VT[, ...])][, ...] }
+        let ename = match self.tokens.next().t_type.clone() {
+            TokenType::Identifier(n) => n,
+            _ => {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+            }
+        };
+        if !self.tokens.assert_next(TokenType::OpenBracket) {
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `{{`, found {:?}", self.tokens.peek().t_type)
+        }
+        let open_at = self.tokens.last().t_at;
+        let mut fields: Vec<EnumField> = Vec::new();
+        let mut fields_at: Vec<TokenAt> = Vec::new();
+        loop {
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            let token = self.tokens.next();
+            let (vname, variant_at) = if let TokenType::Identifier(vname) = token.t_type.clone() {
+                (vname, token.t_at)
+            } else {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier or Token `}}`, found {:?}", self.tokens.last().t_type)
+            };
+            if let Some(idx) = fields.iter().position(|field| field.name == vname) {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    format!(
+                        "Variant `{}` is Already Declared in Enum `{}`",
+                        vname, ename
+                    ),
+                )
+                .with_at(variant_at)
+                .with_label(fields_at[idx], "first declared here".to_string()));
+            }
+            let mut values = Vec::new();
+            if self.tokens.assert_next(TokenType::OpenParen) {
+                loop {
+                    if self.tokens.assert_next(TokenType::CloseParen) {
+                        break;
+                    }
+                    values.push(self.parse_type()?);
+                    if self.tokens.assert_next(TokenType::CloseParen) {
+                        break;
+                    }
+                    if !self.tokens.assert_next(TokenType::OperatorComma) {
+                        build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `)`, found {:?}", self.tokens.peek().t_type)
+                    }
+                }
+            }
+            fields.push(EnumField {
+                name: vname,
+                values,
+            });
+            fields_at.push(variant_at);
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if !self.tokens.assert_next(TokenType::OperatorComma) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+        }
+        let edef = EnumDef {
+            name: ename,
+            fields,
+        };
+        self.enum_def.push(WithScope {
+            scope,
+            body: edef.clone(),
+        });
+        Ok(AstNode::Enum(edef))
     }
 
-    fn parse_enum_definion(&mut self, scope: Scope) {
-        todo!()
+    fn parse_struct_definion(&mut self, scope: Scope) -> Result<AstNode, TiError> {
+        // struct SN { FN[: FT][, ...] }
+        let sname = match self.tokens.next().t_type.clone() {
+            TokenType::Identifier(n) => n,
+            _ => {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+            }
+        };
+        if !self.tokens.assert_next(TokenType::OpenBracket) {
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `{{`, found {:?}", self.tokens.peek().t_type)
+        }
+        let open_at = self.tokens.last().t_at;
+        let mut fields: Vec<StructField> = Vec::new();
+        let mut fields_at: Vec<TokenAt> = Vec::new();
+        loop {
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            let token = self.tokens.next();
+            let (fname, field_at) = if let TokenType::Identifier(fname) = token.t_type.clone() {
+                (fname, token.t_at)
+            } else {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier or Token `}}`, found {:?}", self.tokens.last().t_type)
+            };
+            if let Some(idx) = fields.iter().position(|field| field.name == fname) {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    format!(
+                        "Field `{}` is Already Declared in Struct `{}`",
+                        fname, sname
+                    ),
+                )
+                .with_at(field_at)
+                .with_label(fields_at[idx], "first declared here".to_string()));
+            }
+            let ty = if self.tokens.assert_next(TokenType::OperatorColon) {
+                self.parse_type()?
+            } else {
+                Type::Unknown
+            };
+            fields.push(StructField { name: fname, ty });
+            fields_at.push(field_at);
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if !self.tokens.assert_next(TokenType::OperatorComma) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+        }
+        let sdef = StructDef {
+            name: sname,
+            fields,
+        };
+        self.struct_def.push(WithScope {
+            scope,
+            body: sdef.clone(),
+        });
+        Ok(AstNode::Struct(sdef))
     }
 
-    fn parse_struct_definion(&mut self, scope: Scope) {
-        todo!()
+    fn parse_trait_definion(&mut self, scope: Scope) -> Result<AstNode, TiError> {
+        // trait TN { fn FN(FA[, ...])[ FB] ... }
+        let tname = match self.tokens.next().t_type.clone() {
+            TokenType::Identifier(n) => n,
+            _ => {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+            }
+        };
+        let fields = self.parse_fn_items(|parser, fdef| {
+            let default = match parser.tokens.peek().t_type {
+                TokenType::OperatorFatArrow | TokenType::OpenBracket => {
+                    Some(parser.parse_fn_body()?)
+                }
+                _ => {
+                    parser.tokens.assert_next(TokenType::Semi);
+                    None
+                }
+            };
+            Ok(TraitField {
+                body: fdef,
+                default,
+            })
+        })?;
+        let tdef = TraitDef {
+            name: tname,
+            fields,
+        };
+        self.trait_def.push(WithScope {
+            scope,
+            body: tdef.clone(),
+        });
+        Ok(AstNode::Trait(tdef))
     }
 
-    fn parse_trait_definion(&mut self, scope: Scope) {
-        todo!()
+    fn parse_impl_definion(&mut self, _scope: Scope) -> Result<AstNode, TiError> {
+        // impl [TN for ]SN { fn FN(FA[, ...]) FB ... }
+        let mut sname = match self.tokens.next().t_type.clone() {
+            TokenType::Identifier(n) => n,
+            _ => {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+            }
+        };
+        let mut tname = None;
+        if self.tokens.assert_next(TokenType::KeywordFor) {
+            tname = Some(sname);
+            sname = match self.tokens.next().t_type.clone() {
+                TokenType::Identifier(n) => n,
+                _ => {
+                    build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+                }
+            };
+        }
+        let items = self.parse_fn_items(|parser, fdef| {
+            let fbody = parser.parse_fn_body()?;
+            Ok(AstNode::Fn(fdef, fbody))
+        })?;
+        Ok(AstNode::Impl(sname, tname, items))
     }
 
-    fn parse_impl_definion(&mut self, scope: Scope) -> Option<AstNode> {
-        todo!()
+    /// Parses the `{ fn ... }` body of a `trait` or an `impl`: each item
+    /// starts with a signature, and `item` parses what follows it.
+    fn parse_fn_items<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self, FnDef) -> Result<T, TiError>,
+    ) -> Result<Vec<T>, TiError> {
+        if !self.tokens.assert_next(TokenType::OpenBracket) {
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `{{`, found {:?}", self.tokens.peek().t_type)
+        }
+        let open_at = self.tokens.last().t_at;
+        let mut items = Vec::new();
+        let mut names: Vec<(Rc<String>, TokenAt)> = Vec::new();
+        while !self.tokens.assert_next(TokenType::CloseBracket) {
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            if !self.tokens.assert_next(TokenType::KeywordFn) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `fn` or `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+            let name_at = self.tokens.peek().t_at;
+            let fdef = self.parse_fn_signature()?;
+            if let Some((_, first_at)) = names.iter().find(|(name, _)| *name == fdef.name) {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    format!("Method `{}` is Already Defined", fdef.name),
+                )
+                .with_at(name_at)
+                .with_label(*first_at, "first defined here".to_string()));
+            }
+            names.push((fdef.name.clone(), name_at));
+            items.push(item(self, fdef)?);
+        }
+        Ok(items)
     }
 
-    fn parse_expr(&mut self) -> AstExpr {
-        self.parse_expr_logic()
+    fn parse_expr(&mut self) -> Result<AstExpr, TiError> {
+        self.parse_expr_bp(0)
     }
 
-    fn parse_expr_primary(&mut self) -> AstExpr {
+    fn parse_expr_primary(&mut self) -> Result<AstExpr, TiError> {
         let curr = self.tokens.next();
         match &curr.t_type {
             TokenType::Identifier(x) => {
                 let name = x.clone();
-                if self.tokens.assert_next(TokenType::OperatorAssign) {
-                    let expr = self.parse_expr();
-                    AstExpr::Assign(name, Box::new(expr))
+                let at = curr.t_at;
+                if self.tokens.assert_next(TokenType::OperatorPath) {
+                    match self.tokens.next().t_type.clone() {
+                        TokenType::Identifier(item) => Ok(AstExpr::Path(name, item)),
+                        _ => {
+                            build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+                        }
+                    }
+                } else if self.at_struct_lit() {
+                    self.parse_struct_lit(name)
                 } else {
-                    AstExpr::Var(name)
+                    Ok(AstExpr::Var(name, None, at))
                 }
             }
-            TokenType::LiteralNum(x) => AstExpr::LiteralNum(*x),
-            TokenType::LiteralStr(x) => AstExpr::LiteralStr(x.clone()),
-            TokenType::OperatorSub => {
-                let expr = self.parse_expr();
-                AstExpr::Neg(Box::new(expr))
-            }
+            TokenType::LiteralNum(x) => Ok(AstExpr::LiteralNum(*x)),
+            TokenType::LiteralStr(x) => Ok(AstExpr::LiteralStr(x.clone())),
+            TokenType::LiteralBool(x) => Ok(AstExpr::LiteralBool(*x)),
             TokenType::KeywordIf => {
-                let expr = self.parse_expr();
-                let true_case = self.parse_block();
+                let expr = self.parse_cond()?;
+                let true_case = self.parse_block()?;
                 let false_case = if self.tokens.assert_next(TokenType::KeywordElse) {
-                    self.parse_block()
+                    self.parse_block()?
                 } else {
                     AstBlock::new()
                 };
-                AstExpr::IfElse(Box::new(expr), true_case, false_case)
+                Ok(AstExpr::IfElse(Box::new(expr), true_case, false_case))
             }
-            TokenType::KeywordWhile => {
-                let expr = self.parse_expr();
-                let body = self.parse_block();
-                /* let else = if self.tokens.assert_next(TokenType::KeywordElse) {
-                    self.parse_block()
+            TokenType::KeywordWhile => self.parse_while(None),
+            TokenType::KeywordFor => self.parse_for(None),
+            TokenType::Label(label) => {
+                // 'L: while C { ... } | 'L: for X in E { ... }
+                let label = label.clone();
+                if !self.tokens.assert_next(TokenType::OperatorColon) {
+                    build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `:`, found {:?}", self.tokens.peek().t_type)
+                }
+                if self.tokens.assert_next(TokenType::KeywordWhile) {
+                    self.parse_while(Some(label))
+                } else if self.tokens.assert_next(TokenType::KeywordFor) {
+                    self.parse_for(Some(label))
                 } else {
-                    AstBlock::new()
-                }; */
-                AstExpr::While(Box::new(expr), body)
+                    build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect a Loop After the Label `'{}`, found {:?}", label, self.tokens.peek().t_type)
+                }
+            }
+            TokenType::KeywordReturn => {
+                let at = curr.t_at;
+                if !self.in_fn {
+                    build_ti_error!(@span at, @err Syntax, "`return` Outside of a Function")
+                }
+                let value = self.parse_value(at)?;
+                Ok(AstExpr::Return(Box::new(value)))
+            }
+            TokenType::KeywordBreak => {
+                let at = curr.t_at;
+                let label = self.parse_loop_label(at, "break")?;
+                let value = self.parse_value(at)?;
+                Ok(AstExpr::Break(label, Box::new(value)))
+            }
+            TokenType::KeywordContinue => {
+                let at = curr.t_at;
+                let label = self.parse_loop_label(at, "continue")?;
+                Ok(AstExpr::Continue(label))
+            }
+            TokenType::KeywordMatch => {
+                let at = curr.t_at;
+                let expr = self.parse_cond()?;
+                if !self.tokens.assert_next(TokenType::OpenBracket) {
+                    build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `{{`, found {:?}.", self.tokens.peek().t_type)
+                }
+                let open_at = self.tokens.last().t_at;
+                let mut arms = Vec::new();
+                while !self.tokens.assert_next(TokenType::CloseBracket) {
+                    if self.tokens.is_eof() {
+                        return Err(TiError::new(
+                            TiErrorKind::Syntax,
+                            "Expect Token `}`, found `Eof`".to_string(),
+                        )
+                        .with_at(self.tokens.peek().t_at)
+                        .with_label(open_at, "unclosed delimiter".to_string()));
+                    }
+                    let pattern = self.parse_pattern()?;
+                    if !self.tokens.assert_next(TokenType::OperatorFatArrow) {
+                        build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `=>`, found {:?}", self.tokens.peek().t_type)
+                    }
+                    let body = self.parse_expr_unrestricted()?;
+                    arms.push((pattern, body));
+                    self.tokens.assert_next(TokenType::OperatorComma);
+                }
+                Ok(AstExpr::Match(Box::new(expr), arms, at))
             }
             TokenType::OpenBracket => {
-                self.tokens.backward();
-                AstExpr::Block(self.parse_block())
+                let open_at = curr.t_at;
+                if self.at_map_lit() {
+                    self.parse_map_lit(open_at)
+                } else {
+                    self.tokens.backward();
+                    Ok(AstExpr::Block(self.parse_block()?))
+                }
+            }
+            TokenType::OpenBrace => {
+                // [E[, ...][,]]
+                let open_at = curr.t_at;
+                let mut items = Vec::new();
+                loop {
+                    if self.tokens.assert_next(TokenType::CloseBrace) {
+                        break;
+                    }
+                    if self.tokens.is_eof() {
+                        return Err(TiError::new(
+                            TiErrorKind::Syntax,
+                            "Expect Token `]`, found `Eof`".to_string(),
+                        )
+                        .with_at(self.tokens.peek().t_at)
+                        .with_label(open_at, "unclosed delimiter".to_string()));
+                    }
+                    items.push(self.parse_expr_unrestricted()?);
+                    if self.tokens.assert_next(TokenType::CloseBrace) {
+                        break;
+                    }
+                    if !self.tokens.assert_next(TokenType::OperatorComma) {
+                        build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `]`, found {:?}", self.tokens.peek().t_type)
+                    }
+                }
+                Ok(AstExpr::LiteralArray(items))
+            }
+            TokenType::KeywordFn => {
+                // fn(FA[, ...]) => E | fn(FA[, ...]) { ... }
+                let fdef = self.parse_fn_params(Rc::new("<anonymous>".to_string()))?;
+                let fbody = self.parse_fn_body()?;
+                Ok(AstExpr::Lambda(fdef, Rc::new(fbody)))
             }
             TokenType::OpenParen => {
-                let expr = self.parse_expr();
+                let open_at = curr.t_at;
+                let expr = self.parse_expr_unrestricted()?;
                 if !self.tokens.assert_next(TokenType::CloseParen) {
-                    build_ti_error!(@at self.tokens.peek(), @err "Expect Token `)`, found {:?}.", self.tokens.peek())
+                    return Err(TiError::new(
+                        TiErrorKind::Syntax,
+                        format!("Expect Token `)`, found {:?}.", self.tokens.peek().t_type),
+                    )
+                    .with_at(self.tokens.peek().t_at)
+                    .with_label(open_at, "unclosed delimiter".to_string()));
                 }
-                expr
+                Ok(expr)
             }
             _ => {
-                build_ti_error!(@at curr, @err "Unexpected Token `{:?}` While Parsing an Expr.", curr)
+                let err = TiError::new(
+                    TiErrorKind::Syntax,
+                    format!(
+                        "Unexpected Token `{:?}` While Parsing an Expr.",
+                        curr.t_type
+                    ),
+                )
+                .with_at(curr.t_at);
+                // leave the token for error recovery, it may start the next statement
+                self.tokens.backward();
+                Err(err)
             }
         }
     }
 
-    fn parse_expr_logic(&mut self) -> AstExpr {
-        let mut lhs = self.parse_expr_comp();
-        while !self.tokens.is_eof() {
-            let curr = self.tokens.peek();
-            match curr.t_type {
-                TokenType::OperatorAnd => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_comp();
-                    lhs = AstExpr::And(Box::new(lhs), Box::new(rhs));
-                }
-                TokenType::OperatorOr => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_comp();
-                    lhs = AstExpr::Or(Box::new(lhs), Box::new(rhs));
-                }
-                _ => break,
+    fn parse_while(&mut self, label: Option<Rc<String>>) -> Result<AstExpr, TiError> {
+        let expr = self.parse_cond()?;
+        self.loops.push(label.clone());
+        let body = self.parse_block();
+        self.loops.pop();
+        /* let else = if self.tokens.assert_next(TokenType::KeywordElse) {
+            self.parse_block()
+        } else {
+            AstBlock::new()
+        }; */
+        Ok(AstExpr::While(label, Box::new(expr), body?))
+    }
+
+    fn parse_for(&mut self, label: Option<Rc<String>>) -> Result<AstExpr, TiError> {
+        // for X in E { ... }
+        let name = match self.tokens.next().t_type.clone() {
+            TokenType::Identifier(name) => name,
+            _ => {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
             }
+        };
+        if !self.tokens.assert_next(TokenType::KeywordIn) {
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Keyword `in`, found {:?}", self.tokens.peek().t_type)
         }
-        lhs
+        let expr = self.parse_cond()?;
+        self.loops.push(label.clone());
+        let body = self.parse_block();
+        self.loops.pop();
+        Ok(AstExpr::For(label, name, Box::new(expr), body?))
     }
-    fn parse_expr_comp(&mut self) -> AstExpr {
-        let mut lhs = self.parse_expr_add();
-        while !self.tokens.is_eof() {
-            let curr = self.tokens.peek();
-            match curr.t_type {
-                TokenType::OperatorLes => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_add();
-                    lhs = AstExpr::Les(Box::new(lhs), Box::new(rhs));
-                }
-                TokenType::OperatorGrt => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_add();
-                    lhs = AstExpr::Grt(Box::new(lhs), Box::new(rhs));
+
+    /// Parses the label after `break` or `continue` at `at`, checking that
+    /// it names one of the loops around them.
+    fn parse_loop_label(
+        &mut self,
+        at: TokenAt,
+        keyword: &str,
+    ) -> Result<Option<Rc<String>>, TiError> {
+        let label = match self.tokens.peek().t_type.clone() {
+            TokenType::Label(label) => {
+                self.tokens.forward();
+                Some(label)
+            }
+            _ => None,
+        };
+        if self.loops.is_empty() {
+            build_ti_error!(@span at, @err Syntax, "`{}` Outside of a Loop", keyword)
+        }
+        if let Some(label) = &label {
+            if !self.loops.iter().any(|outer| outer.as_ref() == Some(label)) {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Use of Undeclared Label `'{}`", label)
+            }
+        }
+        Ok(label)
+    }
+
+    /// Parses the value of `return` or `break` at `at`, the unit when nothing
+    /// follows on the same line.
+    fn parse_value(&mut self, at: TokenAt) -> Result<AstExpr, TiError> {
+        let next = self.tokens.peek();
+        let ends = self.tokens.is_eof()
+            || next.t_at.0 != at.0
+            || matches!(
+                next.t_type,
+                TokenType::CloseBracket
+                    | TokenType::CloseParen
+                    | TokenType::CloseBrace
+                    | TokenType::OperatorComma
+                    | TokenType::Semi
+            );
+        if ends {
+            Ok(AstExpr::LiteralUnit(Vec::new()))
+        } else {
+            self.parse_expr()
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Result<AstPattern, TiError> {
+        let curr = self.tokens.next();
+        match curr.t_type.clone() {
+            TokenType::Identifier(name) if name.as_str() == "_" => Ok(AstPattern::Wildcard),
+            TokenType::Identifier(name) => {
+                if !self.tokens.assert_next(TokenType::OperatorPath) {
+                    return Ok(AstPattern::Binding(name));
                 }
-                TokenType::OperatorLeq => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_add();
-                    lhs = AstExpr::Leq(Box::new(lhs), Box::new(rhs));
+                // EN::VN[(P[, ...])]
+                let variant = match self.tokens.next().t_type.clone() {
+                    TokenType::Identifier(variant) => variant,
+                    _ => {
+                        build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+                    }
+                };
+                let mut values = Vec::new();
+                if self.tokens.assert_next(TokenType::OpenParen) {
+                    loop {
+                        if self.tokens.assert_next(TokenType::CloseParen) {
+                            break;
+                        }
+                        values.push(self.parse_pattern()?);
+                        if self.tokens.assert_next(TokenType::CloseParen) {
+                            break;
+                        }
+                        if !self.tokens.assert_next(TokenType::OperatorComma) {
+                            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `)`, found {:?}", self.tokens.peek().t_type)
+                        }
+                    }
                 }
-                TokenType::OperatorGeq => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_add();
-                    lhs = AstExpr::Geq(Box::new(lhs), Box::new(rhs));
+                Ok(AstPattern::Variant(name, variant, values))
+            }
+            TokenType::LiteralNum(x) => Ok(AstPattern::LiteralNum(x)),
+            TokenType::OperatorSub => match self.tokens.next().t_type {
+                TokenType::LiteralNum(x) => Ok(AstPattern::LiteralNum(-x)),
+                _ => {
+                    build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect an Literal Number, found {:?}", self.tokens.last().t_type)
                 }
-                _ => break,
+            },
+            TokenType::LiteralStr(x) => Ok(AstPattern::LiteralStr(x)),
+            TokenType::LiteralBool(x) => Ok(AstPattern::LiteralBool(x)),
+            _ => {
+                build_ti_error!(@at curr, @err Syntax, "Expect a Pattern, found {:?}", curr.t_type)
             }
         }
-        lhs
     }
-    fn parse_expr_add(&mut self) -> AstExpr {
-        let mut lhs = self.parse_expr_mul();
-        while !self.tokens.is_eof() {
-            let curr = self.tokens.peek();
-            match curr.t_type {
-                TokenType::OperatorAdd => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_mul();
-                    lhs = AstExpr::Add(Box::new(lhs), Box::new(rhs));
-                }
-                TokenType::OperatorSub => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_mul();
-                    lhs = AstExpr::Sub(Box::new(lhs), Box::new(rhs));
+
+    /// Parses the condition of `if`, `while` or `match`, which can't be a struct
+    /// literal unless it's wrapped in parentheses.
+    fn parse_cond(&mut self) -> Result<AstExpr, TiError> {
+        let no_struct_lit = std::mem::replace(&mut self.no_struct_lit, true);
+        let expr = self.parse_expr();
+        self.no_struct_lit = no_struct_lit;
+        expr
+    }
+
+    /// Parses an expr nested in delimiters, where struct literals are
+    /// allowed again even inside a condition.
+    fn parse_expr_unrestricted(&mut self) -> Result<AstExpr, TiError> {
+        let no_struct_lit = std::mem::replace(&mut self.no_struct_lit, false);
+        let expr = self.parse_expr();
+        self.no_struct_lit = no_struct_lit;
+        expr
+    }
+
+    /// `Name {` starts a struct literal when followed by `}` or `field:`.
+    fn at_struct_lit(&self) -> bool {
+        if self.no_struct_lit || self.tokens.peek().t_type != TokenType::OpenBracket {
+            return false;
+        }
+        let idx = self.tokens.curr + 1;
+        match self.tokens.at(idx).map(|token| &token.t_type) {
+            Some(TokenType::CloseBracket) => true,
+            Some(TokenType::Identifier(_)) => matches!(
+                self.tokens.at(idx + 1).map(|token| &token.t_type),
+                Some(TokenType::OperatorColon)
+            ),
+            _ => false,
+        }
+    }
+
+    fn parse_struct_lit(&mut self, sname: Rc<String>) -> Result<AstExpr, TiError> {
+        // SN { FN: FV[, ...] }
+        self.tokens.forward();
+        let open_at = self.tokens.last().t_at;
+        let mut fields: Vec<(Rc<String>, AstExpr)> = Vec::new();
+        let mut fields_at: Vec<TokenAt> = Vec::new();
+        loop {
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            let token = self.tokens.next();
+            let (fname, field_at) = if let TokenType::Identifier(fname) = token.t_type.clone() {
+                (fname, token.t_at)
+            } else {
+                build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier or Token `}}`, found {:?}", self.tokens.last().t_type)
+            };
+            if let Some(idx) = fields.iter().position(|(name, _)| *name == fname) {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    format!("Field `{}` is Specified More Than Once", fname),
+                )
+                .with_at(field_at)
+                .with_label(fields_at[idx], "first specified here".to_string()));
+            }
+            if !self.tokens.assert_next(TokenType::OperatorColon) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `:`, found {:?}", self.tokens.peek().t_type)
+            }
+            let expr = self.parse_expr_unrestricted()?;
+            fields.push((fname, expr));
+            fields_at.push(field_at);
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if !self.tokens.assert_next(TokenType::OperatorComma) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+        }
+        Ok(AstExpr::LiteralStruct(sname, fields))
+    }
+
+    /// `{` after the current one starts a map literal rather than a block
+    /// when it's `{:}` or when the first line inside has a `:` outside any
+    /// delimiters and doesn't start with `let` or a label.
+    fn at_map_lit(&self) -> bool {
+        let first = match self.tokens.at(self.tokens.curr) {
+            Some(token) => token,
+            None => return false,
+        };
+        match first.t_type {
+            TokenType::OperatorColon => return true,
+            TokenType::KeywordLet | TokenType::Label(_) | TokenType::CloseBracket => return false,
+            _ => {}
+        }
+        let mut idx = self.tokens.curr;
+        let mut depth = 0usize;
+        while let Some(token) = self.tokens.at(idx) {
+            if token.t_at.0 != first.t_at.0 {
+                break;
+            }
+            match token.t_type {
+                TokenType::OpenParen | TokenType::OpenBrace | TokenType::OpenBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseBrace | TokenType::CloseBracket => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
                 }
+                TokenType::OperatorColon if depth == 0 => return true,
+                _ => {}
+            }
+            idx += 1;
+        }
+        false
+    }
+
+    fn parse_map_lit(&mut self, open_at: TokenAt) -> Result<AstExpr, TiError> {
+        // { K: V[, ...][,] } | {:}
+        if self.tokens.assert_next(TokenType::OperatorColon) {
+            if !self.tokens.assert_next(TokenType::CloseBracket) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+            return Ok(AstExpr::LiteralMap(Vec::new()));
+        }
+        let mut entries = Vec::new();
+        loop {
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            let key_at = self.tokens.peek().t_at;
+            let key = self.parse_expr_unrestricted()?;
+            let key_at = (key_at.0, (key_at.1 .0, self.tokens.last().t_at.1 .1));
+            if !self.tokens.assert_next(TokenType::OperatorColon) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `:`, found {:?}", self.tokens.peek().t_type)
+            }
+            let value = self.parse_expr_unrestricted()?;
+            entries.push((key, value, key_at));
+            if self.tokens.assert_next(TokenType::CloseBracket) {
+                break;
+            }
+            if !self.tokens.assert_next(TokenType::OperatorComma) {
+                build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `}}`, found {:?}", self.tokens.peek().t_type)
+            }
+        }
+        Ok(AstExpr::LiteralMap(entries))
+    }
+
+    /// Parses an expr whose operators all bind at least as tightly as
+    /// `min`, a binding power of twice the precedence: an operand with its
+    /// prefix operators first, then each postfix and infix operator after
+    /// it in turn, with the expr so far as their left operand.
+    fn parse_expr_bp(&mut self, min: u8) -> Result<AstExpr, TiError> {
+        let mut lhs = self.parse_expr_prefix()?;
+        loop {
+            if self.at_postfix() {
+                lhs = self.parse_postfix(lhs)?;
+                continue;
+            }
+            let (prec, assoc, infix, len) = match self.peek_infix() {
+                Some(op) if op.0 * 2 >= min => op,
                 _ => break,
+            };
+            let at = self.tokens.peek().t_at;
+            for _ in 0..len {
+                self.tokens.forward();
+            }
+            // `>>` spans both of its tokens
+            let at = (at.0, (at.1 .0, self.tokens.last().t_at.1 .1));
+            let rhs_min = match assoc {
+                Assoc::Right => prec * 2,
+                Assoc::Left | Assoc::Neither => prec * 2 + 1,
+            };
+            lhs = self.parse_infix(lhs, infix, at, rhs_min)?;
+            if let (Assoc::Neither, Some((next, _, _, _))) = (assoc, self.peek_infix()) {
+                if next == prec {
+                    build_ti_error!(@at self.tokens.peek(), @err Syntax, "Comparison and Range Operators Can't Be Chained, Use Parentheses to Group Them")
+                }
             }
         }
-        lhs
+        Ok(lhs)
     }
 
-    fn parse_expr_mul(&mut self) -> AstExpr {
-        let mut lhs = self.parse_expr_call();
-        while !self.tokens.is_eof() {
-            let curr = self.tokens.peek();
-            match curr.t_type {
-                TokenType::OperatorMul => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_call();
-                    lhs = AstExpr::Mul(Box::new(lhs), Box::new(rhs));
+    fn parse_expr_prefix(&mut self) -> Result<AstExpr, TiError> {
+        // -X | !X
+        let at = self.tokens.peek().t_at;
+        if self.tokens.assert_next(TokenType::OperatorSub) {
+            let expr = self.parse_expr_bp(PREFIX * 2)?;
+            Ok(AstExpr::Neg(Box::new(expr), at))
+        } else if self.tokens.assert_next(TokenType::OperatorNot) {
+            let expr = self.parse_expr_bp(PREFIX * 2)?;
+            Ok(AstExpr::Not(Box::new(expr), at))
+        } else {
+            self.parse_expr_primary()
+        }
+    }
+
+    /// Whether the current token is a postfix operator: a call, a field, an
+    /// index or `?`.
+    fn at_postfix(&self) -> bool {
+        let curr = self.tokens.peek();
+        match curr.t_type {
+            TokenType::OperatorDot | TokenType::OperatorTry => true,
+            // `(` or `[` on a line of its own starts a new expr rather than
+            // calling or indexing the expr before it
+            TokenType::OpenParen | TokenType::OpenBrace => curr.t_at.0 == self.tokens.last().t_at.0,
+            _ => false,
+        }
+    }
+
+    /// Applies the postfix operator at the current token to `lhs`.
+    fn parse_postfix(&mut self, lhs: AstExpr) -> Result<AstExpr, TiError> {
+        let curr = self.tokens.next();
+        let at = curr.t_at;
+        match curr.t_type {
+            TokenType::OpenParen => {
+                // F(A[, ...])
+                let mut args = Vec::new();
+                if !self.tokens.assert_next(TokenType::CloseParen) {
+                    loop {
+                        let expr = self.parse_expr_unrestricted()?;
+                        args.push(expr);
+                        if self.tokens.assert_next(TokenType::CloseParen) {
+                            break;
+                        }
+                        if !self.tokens.assert_next(TokenType::OperatorComma) {
+                            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `,` or `)`, found {:?}", self.tokens.peek().t_type)
+                        }
+                    }
                 }
-                TokenType::OperatorDiv => {
-                    self.tokens.forward();
-                    let rhs = self.parse_expr_call();
-                    lhs = AstExpr::Div(Box::new(lhs), Box::new(rhs));
+                Ok(AstExpr::FnCall(Box::new(lhs), args))
+            }
+            TokenType::OperatorDot => {
+                // O.F
+                let field = match self.tokens.next().t_type.clone() {
+                    TokenType::Identifier(field) => field,
+                    _ => {
+                        build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect Identifier, found {:?}", self.tokens.last().t_type)
+                    }
+                };
+                Ok(AstExpr::Field(Box::new(lhs), field))
+            }
+            TokenType::OpenBrace => {
+                // O[I]
+                let index = self.parse_expr_unrestricted()?;
+                if !self.tokens.assert_next(TokenType::CloseBrace) {
+                    return Err(TiError::new(
+                        TiErrorKind::Syntax,
+                        format!("Expect Token `]`, found {:?}.", self.tokens.peek().t_type),
+                    )
+                    .with_at(self.tokens.peek().t_at)
+                    .with_label(at, "unclosed delimiter".to_string()));
                 }
-                _ => break,
+                let at = (at.0, (at.1 .0, self.tokens.last().t_at.1 .1));
+                Ok(AstExpr::Index(Box::new(lhs), Box::new(index), at))
+            }
+            _ => {
+                // E?
+                if !self.in_fn {
+                    build_ti_error!(@span at, @err Syntax, "`?` Outside of a Function")
+                }
+                Ok(AstExpr::Try(Box::new(lhs)))
             }
         }
-        lhs
     }
 
-    fn parse_expr_call(&mut self) -> AstExpr {
-        let member = self.parse_expr_primary();
-        if self.tokens.assert_next(TokenType::OpenParen) {
-            let mut args = Vec::new();
-            if !self.tokens.assert_next(TokenType::CloseParen) {
-                loop {
-                    let expr = self.parse_expr();
-                    args.push(expr);
-                    if self.tokens.assert_next(TokenType::CloseParen) {
-                        break;
+    /// Builds the infix operator `infix` at `at` with `lhs` and the operand
+    /// on its right, which takes the operators binding at least `min`.
+    fn parse_infix(
+        &mut self,
+        lhs: AstExpr,
+        infix: Infix,
+        at: TokenAt,
+        min: u8,
+    ) -> Result<AstExpr, TiError> {
+        match infix {
+            Infix::Binary(build) => {
+                let rhs = self.parse_expr_bp(min)?;
+                Ok(build(Box::new(lhs), Box::new(rhs), at))
+            }
+            Infix::Assign => {
+                let rhs = self.parse_expr_bp(min)?;
+                match lhs {
+                    AstExpr::Var(name, slot, at) => {
+                        Ok(AstExpr::Assign(name, slot, Box::new(rhs), at))
+                    }
+                    AstExpr::Field(object, field) if object.is_place() => {
+                        Ok(AstExpr::AssignField(object, field, Box::new(rhs)))
+                    }
+                    AstExpr::Index(object, index, index_at) if object.is_place() => {
+                        Ok(AstExpr::AssignIndex(object, index, Box::new(rhs), index_at))
+                    }
+                    _ => {
+                        build_ti_error!(@span at, @err Syntax, "Invalid Left-Hand Side of Assignment, Expect a Variable, a Field or an Item")
                     }
-                    self.tokens.forward();
                 }
             }
-            AstExpr::FnCall(Box::new(member), args)
-        } else {
-            member
+            Infix::Compound(build) => {
+                if !lhs.is_place() {
+                    build_ti_error!(@span at, @err Syntax, "Invalid Left-Hand Side of Assignment, Expect a Variable, a Field or an Item")
+                }
+                let rhs = self.parse_expr_bp(min)?;
+                Ok(build(Box::new(lhs), Box::new(rhs), at))
+            }
+            Infix::Range(inclusive) => {
+                // S..E[ step T] | S..=E[ step T]
+                let end = self.parse_expr_bp(min)?;
+                // `step` only counts on the line of the range, it's a plain
+                // name anywhere else
+                let next = self.tokens.peek();
+                let step = match &next.t_type {
+                    TokenType::Identifier(name)
+                        if name.as_str() == "step" && next.t_at.0 == at.0 =>
+                    {
+                        self.tokens.forward();
+                        Some(Box::new(self.parse_expr_bp(min)?))
+                    }
+                    _ => None,
+                };
+                Ok(AstExpr::Range(
+                    Box::new(lhs),
+                    Box::new(end),
+                    step,
+                    inclusive,
+                ))
+            }
+        }
+    }
+
+    /// The infix operator at the current token, with the number of tokens
+    /// it takes.
+    fn peek_infix(&self) -> Option<(u8, Assoc, Infix, usize)> {
+        if self.at_shr() {
+            return Some((SHIFT, Assoc::Left, Infix::Binary(AstExpr::Shr), 2));
+        }
+        let (prec, assoc, infix) = infix(&self.tokens.peek().t_type)?;
+        Some((prec, assoc, infix, 1))
+    }
+
+    /// `>>` is lexed as two `>`, it's a shift only when they touch.
+    fn at_shr(&self) -> bool {
+        let idx = self.tokens.curr;
+        match (self.tokens.at(idx), self.tokens.at(idx + 1)) {
+            (Some(first), Some(second)) => {
+                first.t_type == TokenType::OperatorGrt
+                    && second.t_type == TokenType::OperatorGrt
+                    && first.t_at.0 == second.t_at.0
+                    && first.t_at.1 .1 == second.t_at.1 .0
+            }
+            _ => false,
         }
     }
 
     fn find_scope(&self) -> Scope {
         let mut idx = self.tokens.curr;
-        while !self.tokens.is_eof() {
-            match self.tokens.at(idx).unwrap().t_type {
-                TokenType::CloseBracket => break,
-                _ => {}
+        while let Some(token) = self.tokens.at(idx) {
+            if let TokenType::CloseBracket = token.t_type {
+                break;
             }
             idx += 1;
         }
         Scope::Block(self.tokens.curr, idx)
     }
 
-    fn parse_block(&mut self) -> AstBlock {
+    fn parse_block(&mut self) -> Result<AstBlock, TiError> {
         if !self.tokens.assert_next(TokenType::OpenBracket) {
-            build_ti_error!(@at self.tokens.peek(), @err "Expect Token `{{`, found {:?}.", self.tokens.peek())
+            build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `{{`, found {:?}.", self.tokens.peek().t_type)
         }
+        let open_at = self.tokens.last().t_at;
         let mut block = AstBlock::new();
         let scope = self.find_scope();
-        while !self.tokens.is_eof() && !self.tokens.assert_next(TokenType::CloseBracket) {
-            let ast_node = self.parse_stmt(&scope);
-            block.add(ast_node);
+        while !self.tokens.assert_next(TokenType::CloseBracket) {
+            if self.tokens.is_eof() {
+                return Err(TiError::new(
+                    TiErrorKind::Syntax,
+                    "Expect Token `}`, found `Eof`".to_string(),
+                )
+                .with_at(self.tokens.peek().t_at)
+                .with_label(open_at, "unclosed delimiter".to_string()));
+            }
+            let at = self.tokens.peek().t_at;
+            match self.parse_stmt(&scope) {
+                Ok(ast_node) => block.add(ast_node, at),
+                Err(err) => self.recover(err),
+            }
         }
-        block
+        Ok(block)
     }
 
-    fn parse_stmt(&mut self, scope: &Scope) -> AstNode {
-        if let Some(ast_node) = self.parse_definion(scope.clone()) {
-            ast_node.unwrap_or(AstNode::Empty)
+    fn parse_stmt(&mut self, scope: &Scope) -> Result<AstNode, TiError> {
+        if let Some(ast_node) = self.parse_definion(scope.clone())? {
+            Ok(ast_node.unwrap_or(AstNode::Empty))
         } else {
-            self.tokens.forward();
-            let expr = self.parse_expr();
-            AstNode::Expr(expr)
+            let expr = self.parse_expr()?;
+            Ok(AstNode::Expr(expr))
         }
     }
 
-    fn parse_type(&mut self) -> Type {
+    fn parse_type(&mut self) -> Result<Type, TiError> {
         self.parse_type_anna()
     }
 
-    fn parse_type_anna(&mut self) -> Type {
-        let t = self.parse_type_primary();
+    fn parse_type_anna(&mut self) -> Result<Type, TiError> {
+        let t = self.parse_type_primary()?;
         if self.tokens.assert_next(TokenType::OperatorLes) {
             let mut anna = Vec::new();
             loop {
-                let expr = self.parse_type();
+                let expr = self.parse_type()?;
                 anna.push(expr);
                 if self.tokens.assert_next(TokenType::OperatorGrt) {
                     break;
                 }
                 self.tokens.forward();
             }
-            Type::Anna(Box::new(t), anna)
+            Ok(Type::Anna(Box::new(t), anna))
         } else {
-            t
+            Ok(t)
         }
     }
 
-    fn parse_type_primary(&mut self) -> Type {
+    fn parse_type_primary(&mut self) -> Result<Type, TiError> {
         let curr = self.tokens.next();
         match &curr.t_type {
-            TokenType::Identifier(t) => match t.as_str() {
+            TokenType::Identifier(t) => Ok(match t.as_str() {
                 "i8" => Type::I8,
                 "i16" => Type::I16,
                 "i32" => Type::I32,
@@ -420,42 +1349,234 @@ impl Parser {
                 "f32" => Type::F32,
                 "f64" => Type::F64,
                 _ => Type::Costume(t.clone()),
-            },
+            }),
             TokenType::OpenParen => {
                 let mut unit = Vec::new();
                 loop {
-                    let expr = self.parse_type();
+                    let expr = self.parse_type()?;
                     unit.push(expr);
                     if self.tokens.assert_next(TokenType::CloseParen) {
                         break;
                     }
                     self.tokens.forward();
                 }
-                Type::Unit(unit)
+                Ok(Type::Unit(unit))
             }
             TokenType::OpenBrace => {
-                let t = self.parse_type();
+                let t = self.parse_type()?;
                 let l = if self.tokens.assert_next(TokenType::Semi) {
                     if let TokenType::LiteralNum(l) = self.tokens.next().t_type {
                         l as usize
                     } else {
-                        build_ti_error!(@at self.tokens.peek(), @err "Expect an Literal Number, found {:?}", self.tokens.peek())
+                        build_ti_error!(@at self.tokens.last(), @err Syntax, "Expect an Literal Number, found {:?}", self.tokens.last().t_type)
                     }
                 } else {
                     0
                 };
                 if !self.tokens.assert_next(TokenType::CloseBrace) {
-                    build_ti_error!(@at self.tokens.peek(), @err "Expect Token `]`, found {:?}", self.tokens.peek())
+                    build_ti_error!(@at self.tokens.peek(), @err Syntax, "Expect Token `]`, found {:?}", self.tokens.peek().t_type)
                 }
-                Type::Array(Box::new(t), l)
+                Ok(Type::Array(Box::new(t), l))
             }
             TokenType::OperatorRef => {
-                let t = self.parse_type();
-                Type::Ref(Box::new(t))
+                let t = self.parse_type()?;
+                Ok(Type::Ref(Box::new(t)))
             }
             _ => {
-                build_ti_error!(@at curr, @err "Expect Identifier, found {:?}", curr)
+                build_ti_error!(@at curr, @err Syntax, "Expect Identifier, found {:?}", curr.t_type)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::lexer::Lexer;
+
+    /// The statements of `src`, each shown as an s-expr.
+    fn parse_all(src: &str) -> Result<Vec<String>, TiError> {
+        let mut parser = Parser::new(Lexer::new(src).tokenize()?);
+        if let Err(err) = parser.parse_stmts() {
+            return Err(parser.diagnostics.into_iter().next().unwrap_or(err));
+        }
+        match parser.ast {
+            AstNode::Program(program) => Ok(program
+                .program
+                .iter()
+                .map(|stmt| match stmt {
+                    AstNode::Expr(expr) => show(expr),
+                    AstNode::Let(name, Some(value)) => format!("let {} = {}", name, show(value)),
+                    other => panic!("unexpected statement {:?}", other),
+                })
+                .collect()),
+            other => panic!("expected a program, found {:?}", other),
+        }
+    }
+
+    /// Parses `src` as a single statement and prints its expr with every
+    /// operator and its operands in parens, or returns the first syntax error.
+    fn parse(src: &str) -> Result<String, TiError> {
+        let mut stmts = parse_all(src)?;
+        assert_eq!(stmts.len(), 1, "expected one statement in {:?}", src);
+        Ok(stmts.remove(0))
+    }
+
+    fn show(expr: &AstExpr) -> String {
+        let (op, a, b) = match expr {
+            AstExpr::LiteralNum(x) => return x.to_string(),
+            AstExpr::LiteralBool(x) => return x.to_string(),
+            AstExpr::Var(name, ..) => return name.to_string(),
+            AstExpr::Neg(a, ..) => return format!("(-{})", show(a)),
+            AstExpr::Not(a, ..) => return format!("(!{})", show(a)),
+            AstExpr::FnCall(f, args) => return format!("{}({})", show(f), show_all(args)),
+            AstExpr::Index(a, i, _) => return format!("{}[{}]", show(a), show(i)),
+            AstExpr::LiteralArray(items) => return format!("[{}]", show_all(items)),
+            AstExpr::Add(a, b, ..) => ("+", a, b),
+            AstExpr::Sub(a, b, ..) => ("-", a, b),
+            AstExpr::Mul(a, b, ..) => ("*", a, b),
+            AstExpr::Div(a, b, ..) => ("/", a, b),
+            AstExpr::Mod(a, b, ..) => ("%", a, b),
+            AstExpr::Pow(a, b, ..) => ("**", a, b),
+            AstExpr::BitAnd(a, b, ..) => ("&", a, b),
+            AstExpr::BitOr(a, b, ..) => ("|", a, b),
+            AstExpr::BitXor(a, b, ..) => ("^", a, b),
+            AstExpr::Shl(a, b, ..) => ("<<", a, b),
+            AstExpr::Shr(a, b, ..) => (">>", a, b),
+            AstExpr::Eq(a, b, ..) => ("==", a, b),
+            AstExpr::Les(a, b, ..) => ("<", a, b),
+            AstExpr::And(a, b, ..) => ("&&", a, b),
+            AstExpr::Or(a, b, ..) => ("||", a, b),
+            other => panic!("unexpected expr {:?}", other),
+        };
+        format!("({} {} {})", show(a), op, show(b))
+    }
+
+    fn show_all(exprs: &[AstExpr]) -> String {
+        exprs.iter().map(show).collect::<Vec<_>>().join(", ")
+    }
+
+    #[test]
+    fn prefix_binds_tighter_than_sum_and_product() {
+        assert_eq!(parse("-a + b").unwrap(), "((-a) + b)");
+        assert_eq!(parse("-a * b").unwrap(), "((-a) * b)");
+        assert_eq!(parse("!a == b").unwrap(), "((!a) == b)");
+        assert_eq!(parse("- -a").unwrap(), "(-(-a))");
+    }
+
+    #[test]
+    fn pow_is_right_assoc_and_binds_tighter_than_prefix() {
+        assert_eq!(parse("2 ** 3 ** 2").unwrap(), "(2 ** (3 ** 2))");
+        assert_eq!(parse("-2 ** 2").unwrap(), "(-(2 ** 2))");
+        assert_eq!(parse("2 ** -1").unwrap(), "(2 ** (-1))");
+    }
+
+    #[test]
+    fn arithmetic_is_left_assoc() {
+        assert_eq!(parse("a - b - c").unwrap(), "((a - b) - c)");
+        assert_eq!(parse("a / b % c * d").unwrap(), "(((a / b) % c) * d)");
+        assert_eq!(parse("a + b * c").unwrap(), "(a + (b * c))");
+    }
+
+    #[test]
+    fn shift_and_bitwise_precedence() {
+        assert_eq!(parse("1 << 2 + 3").unwrap(), "(1 << (2 + 3))");
+        assert_eq!(parse("a >> b >> c").unwrap(), "((a >> b) >> c)");
+        assert_eq!(parse("1 | 2 ^ 6 & 4").unwrap(), "(1 | (2 ^ (6 & 4)))");
+        assert_eq!(parse("a & b == c").unwrap(), "((a & b) == c)");
+    }
+
+    #[test]
+    fn logic_precedence() {
+        assert_eq!(parse("a || b && c").unwrap(), "(a || (b && c))");
+        assert_eq!(parse("a < b && b < c").unwrap(), "((a < b) && (b < c))");
+        assert_eq!(parse("a || b || c").unwrap(), "((a || b) || c)");
+    }
+
+    #[test]
+    fn comparisons_cannot_be_chained() {
+        let err = parse("a < b < c").unwrap_err();
+        assert_eq!(err.kind, TiErrorKind::Syntax);
+        assert!(err.message.contains("Can't Be Chained"), "{}", err.message);
+        assert!(parse("a == b != c").is_err());
+        assert!(parse("a..b..c").is_err());
+        assert_eq!(parse("(a < b) == c").unwrap(), "((a < b) == c)");
+    }
+
+    #[test]
+    fn paren_or_bracket_on_a_new_line_starts_a_new_expr() {
+        assert_eq!(
+            parse_all("let a = 1\n(a + 1) * 2").unwrap(),
+            ["let a = 1", "((a + 1) * 2)"]
+        );
+        assert_eq!(parse_all("f\n[1]").unwrap(), ["f", "[1]"]);
+        assert_eq!(parse("f(a)[1]").unwrap(), "f(a)[1]");
+    }
+
+    /// Parses the program `src`, returning the line of each syntax error and
+    /// the names of the functions in the partial program.
+    fn recovered(src: &str) -> (Vec<usize>, Vec<String>) {
+        let mut parser = Parser::new(Lexer::new(src).tokenize().unwrap());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(
+            err.message,
+            format!(
+                "aborting due to {} previous error(s)",
+                parser.diagnostics.len()
+            )
+        );
+        let lines = parser
+            .diagnostics
+            .iter()
+            .map(|err| err.at.expect("a syntax error without a span").0)
+            .collect();
+        let fns = match parser.ast {
+            AstNode::Program(program) => program
+                .program
+                .iter()
+                .filter_map(|item| match item {
+                    AstNode::Fn(fdef, _) => Some(fdef.name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            other => panic!("expected a program, found {:?}", other),
+        };
+        (lines, fns)
+    }
+
+    #[test]
+    fn every_syntax_error_of_a_file_is_reported() {
+        let src = "fn a() {\n    let x = 1 +\n    let y = 2\n}\nfn b() {\n    let z = )\n}\nfn c() {\n    1 + 2\n}\n";
+        let (lines, fns) = recovered(src);
+        assert_eq!(lines, [3, 6]);
+        assert_eq!(fns, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn parsing_resumes_at_the_next_item() {
+        let src = "fn a() {\n    let x = (1 + 2\n}\n42\nfn main() { }\n}\nfn d( { }\nfn e() { }\n";
+        let (lines, fns) = recovered(src);
+        assert_eq!(lines, [3, 4, 6, 7]);
+        assert_eq!(fns, ["a", "main", "e"]);
+    }
+
+    #[test]
+    fn statements_recover_on_a_new_line() {
+        let mut parser = Parser::new(
+            Lexer::new("let a = *\nlet b = 2\nb +\n")
+                .tokenize()
+                .unwrap(),
+        );
+        assert!(parser.parse_stmts().is_err());
+        let lines: Vec<_> = parser
+            .diagnostics
+            .iter()
+            .map(|err| err.at.unwrap().0)
+            .collect();
+        assert_eq!(lines, [1, 3]);
+        match parser.ast {
+            AstNode::Program(program) => assert_eq!(program.program.len(), 1),
+            other => panic!("expected a program, found {:?}", other),
+        }
+    }
+}
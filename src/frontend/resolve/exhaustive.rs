@@ -0,0 +1,110 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    build_ti_error,
+    error::error::{TiError, TiErrorKind},
+    frontend::parser::ast::{AstPattern, EnumDef},
+};
+
+fn is_irrefutable(pattern: &AstPattern) -> bool {
+    matches!(pattern, AstPattern::Wildcard | AstPattern::Binding(_))
+}
+
+/// Checks that every variant pattern names a declared variant with the right
+/// number of values.
+fn check_pattern(
+    enums: &HashMap<Rc<String>, EnumDef>,
+    pattern: &AstPattern,
+) -> Result<(), TiError> {
+    if let AstPattern::Variant(name, variant, patterns) = pattern {
+        let edef = match enums.get(name) {
+            Some(edef) => edef,
+            None => build_ti_error!(@err Reference, "enum `{}` is not defined.", name),
+        };
+        let field = match edef.fields.iter().find(|field| field.name == *variant) {
+            Some(field) => field,
+            None => {
+                build_ti_error!(@err Reference, "no variant `{}` in enum `{}`.", variant, name)
+            }
+        };
+        if field.values.len() != patterns.len() {
+            build_ti_error!(@err Type, "pattern `{}::{}` has {} fields but the variant has {}.", name, variant, patterns.len(), field.values.len())
+        }
+        for pattern in patterns {
+            check_pattern(enums, pattern)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the patterns of a `match` and that they cover every possible
+/// value: an arm of each variant whose values are all wildcards or bindings,
+/// both `true` and `false`, or a catch-all arm.
+pub fn check_exhaustive(
+    enums: &HashMap<Rc<String>, EnumDef>,
+    patterns: &[&AstPattern],
+) -> Result<(), TiError> {
+    for pattern in patterns {
+        check_pattern(enums, pattern)?;
+    }
+    if patterns.iter().any(|pattern| is_irrefutable(pattern)) {
+        return Ok(());
+    }
+    let mut ename = None;
+    for pattern in patterns {
+        if let AstPattern::Variant(name, _, _) = pattern {
+            match ename {
+                None => ename = Some(name.clone()),
+                Some(ref ename) if ename != name => {
+                    build_ti_error!(@err Type, "mismatched types: patterns of enum `{}` and `{}` in the same match.", ename, name)
+                }
+                _ => {}
+            }
+        }
+    }
+    let missing: Vec<String> = if let Some(edef) = ename.and_then(|ename| enums.get(&ename)) {
+        edef.fields
+            .iter()
+            .filter(|field| {
+                !patterns.iter().any(|pattern| match pattern {
+                    AstPattern::Variant(_, variant, patterns) => {
+                        *variant == field.name && patterns.iter().all(is_irrefutable)
+                    }
+                    _ => false,
+                })
+            })
+            .map(|field| match field.values.len() {
+                0 => format!("`{}::{}`", edef.name, field.name),
+                _ => format!("`{}::{}(..)`", edef.name, field.name),
+            })
+            .collect()
+    } else if !patterns.is_empty()
+        && patterns
+            .iter()
+            .all(|pattern| matches!(pattern, AstPattern::LiteralBool(_)))
+    {
+        [true, false]
+            .into_iter()
+            .filter(|x| {
+                !patterns
+                    .iter()
+                    .any(|pattern| matches!(pattern, AstPattern::LiteralBool(y) if x == y))
+            })
+            .map(|x| format!("`{}`", x))
+            .collect()
+    } else {
+        // numbers and strings can't all be listed
+        vec!["`_`".to_string()]
+    };
+    if !missing.is_empty() {
+        return Err(TiError::new(
+            TiErrorKind::Type,
+            format!(
+                "non-exhaustive patterns: {} not covered.",
+                missing.join(", ")
+            ),
+        )
+        .with_help("add an arm for each of them, or a `_ => ...` arm".to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,2 @@
+pub mod resolve;
+pub mod exhaustive;
\ No newline at end of file
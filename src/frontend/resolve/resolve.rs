@@ -0,0 +1,462 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    error::error::{TiError, TiErrorKind},
+    frontend::{
+        lexer::token::TokenAt,
+        parser::ast::{
+            AstBlock, AstExpr, AstNode, AstPattern, AstProgram, EnumDef, FnDef, VarSlot,
+        },
+    },
+    vm::builtins,
+};
+
+use super::exhaustive::check_exhaustive;
+
+/// What a program may use without defining it, which in the REPL includes
+/// what the earlier inputs defined.
+#[derive(Debug, Clone, Default)]
+pub struct TiPrelude {
+    /// the enums the patterns of a `match` may name
+    pub enums: Vec<EnumDef>,
+    /// the globals besides the builtins a variable may name
+    pub names: Vec<Rc<String>>,
+}
+
+/// The variables of one frame, in the order of their slots.
+struct Scope {
+    names: Vec<Rc<String>>,
+    /// the statement declaring each of `names`, if there is one
+    at: Vec<Option<TokenAt>>,
+    /// how many of `names` are declared at the point being resolved
+    declared: usize,
+    /// the number of functions around the scope
+    level: usize,
+}
+
+impl Scope {
+    /// The scope of a block, which declares its `let`s and `fn`s in the
+    /// order of `stmts`.
+    fn block<'a>(
+        stmts: impl IntoIterator<Item = (&'a AstNode, Option<TokenAt>)>,
+        level: usize,
+    ) -> Self {
+        let mut scope = Self::new(Vec::new(), level);
+        for (stmt, at) in stmts {
+            let name = match stmt {
+                AstNode::Let(name, _) => name,
+                AstNode::Fn(fdef, _) => &fdef.name,
+                _ => continue,
+            };
+            scope.names.push(name.clone());
+            scope.at.push(at);
+        }
+        scope
+    }
+
+    /// A scope whose `names` are all declared from the start.
+    fn new(names: Vec<Rc<String>>, level: usize) -> Self {
+        Self {
+            at: vec![None; names.len()],
+            declared: names.len(),
+            names,
+            level,
+        }
+    }
+}
+
+/// Binds every variable to the slot of the frame `vm_ast` keeps it in. The
+/// scopes mirror the frames the vm creates: one per block, one for the
+/// parameters of a call, one for the bindings of a match arm and one for
+/// the variable of each round of a `for` loop. The outermost scope holds
+/// the globals, which stay looked up by name.
+///
+/// Every function also gets the variables of the scopes around it that it
+/// uses, the ones a closure keeps.
+struct Resolver {
+    scopes: Vec<Scope>,
+    /// the number of functions around the point being resolved
+    level: usize,
+    /// for each function around the point being resolved, the index in
+    /// `scopes` of its parameters and the variables outside it it uses so
+    /// far, counted from the scope it is defined in
+    captures: Vec<(usize, Vec<VarSlot>)>,
+    /// the statement being resolved, where the warnings about a `let` point
+    at: Option<TokenAt>,
+    /// the enums declared so far
+    enums: HashMap<Rc<String>, EnumDef>,
+    /// the globals of the prelude
+    names: HashSet<Rc<String>>,
+    diagnostics: Vec<TiError>,
+}
+
+/// Resolves the variables of `ast_node` in place and checks that the
+/// patterns of each `match` cover every value. Returns the errors about
+/// patterns and names declared nowhere, and the warnings about a variable
+/// used before its declaration or declared twice by `let` in the same block.
+pub fn resolve(ast_node: &mut AstNode, prelude: &TiPrelude) -> Vec<TiError> {
+    let globals = match &*ast_node {
+        AstNode::Program(program) => Scope::block(
+            hoisted(program)
+                .into_iter()
+                .map(|(stmt, at)| (stmt, Some(*at))),
+            0,
+        ),
+        ast_node => Scope::block([(ast_node, None)], 0),
+    };
+    let mut resolver = Resolver {
+        scopes: vec![globals],
+        level: 0,
+        captures: Vec::new(),
+        at: None,
+        enums: builtins::enums()
+            .into_iter()
+            .chain(prelude.enums.iter().cloned())
+            .map(|edef| (edef.name.clone(), edef))
+            .collect(),
+        names: prelude.names.iter().cloned().collect(),
+        diagnostics: Vec::new(),
+    };
+    resolver.resolve_node(ast_node);
+    resolver.diagnostics
+}
+
+impl Resolver {
+    fn resolve_node(&mut self, ast_node: &mut AstNode) {
+        match ast_node {
+            AstNode::Program(program) => {
+                // in the order the vm runs the items
+                let mut stmts: Vec<_> = program.program.iter_mut().zip(&program.at).collect();
+                stmts.sort_by_key(|(stmt, _)| stmt.hoisting());
+                for (stmt, at) in stmts {
+                    self.at = Some(*at);
+                    self.resolve_node(stmt);
+                }
+            }
+            AstNode::Fn(fdef, fbody) => {
+                // declared first, so the function can call itself
+                self.declare(&fdef.name, false);
+                self.resolve_fn(fdef, fbody);
+            }
+            AstNode::Impl(_, _, items) => {
+                for item in items {
+                    if let AstNode::Fn(fdef, fbody) = item {
+                        self.resolve_fn(fdef, fbody);
+                    }
+                }
+            }
+            AstNode::Trait(tdef) => {
+                for field in tdef.fields.iter_mut() {
+                    if let Some(fbody) = &mut field.default {
+                        self.resolve_fn(&field.body, fbody);
+                    }
+                }
+            }
+            AstNode::Let(name, value) => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+                self.declare(name, true);
+            }
+            AstNode::Expr(expr) => self.resolve_expr(expr),
+            AstNode::Enum(edef) if builtins::is_builtin_enum(&edef.name) => {
+                let mut err = TiError::new(
+                    TiErrorKind::Type,
+                    format!(
+                        "enum `{}` is builtin, it cannot be defined again.",
+                        edef.name
+                    ),
+                );
+                if let Some(at) = self.at {
+                    err = err.with_at(at);
+                }
+                self.diagnostics.push(err);
+            }
+            AstNode::Enum(edef) => {
+                self.enums.insert(edef.name.clone(), edef.clone());
+            }
+            AstNode::Struct(_) | AstNode::Empty => {}
+        }
+    }
+
+    fn resolve_fn(&mut self, fdef: &FnDef, fbody: &mut AstBlock) {
+        let params = fdef
+            .arguments
+            .iter()
+            .map(|farg| farg.name.clone())
+            .collect();
+        self.level += 1;
+        self.captures.push((self.scopes.len(), Vec::new()));
+        self.scopes.push(Scope::new(params, self.level));
+        self.resolve_block(fbody);
+        self.scopes.pop();
+        if let Some((_, captures)) = self.captures.pop() {
+            fbody.captures = captures;
+        }
+        self.level -= 1;
+    }
+
+    fn resolve_block(&mut self, ast_block: &mut AstBlock) {
+        let at = self.at;
+        let stmts = ast_block
+            .block
+            .iter()
+            .zip(ast_block.at.iter().copied().map(Some));
+        self.scopes.push(Scope::block(stmts, self.level));
+        for (stmt, at) in ast_block.block.iter_mut().zip(&ast_block.at) {
+            self.at = Some(*at);
+            self.resolve_node(stmt);
+        }
+        self.scopes.pop();
+        self.at = at;
+    }
+
+    fn resolve_expr(&mut self, ast_expr: &mut AstExpr) {
+        match ast_expr {
+            AstExpr::Var(name, slot, at) => *slot = self.lookup(name, *at),
+            AstExpr::Assign(name, slot, value, at) => {
+                self.resolve_expr(value);
+                *slot = self.lookup(name, *at);
+            }
+            AstExpr::Add(x, y, _)
+            | AstExpr::Sub(x, y, _)
+            | AstExpr::Mul(x, y, _)
+            | AstExpr::Div(x, y, _)
+            | AstExpr::Mod(x, y, _)
+            | AstExpr::Pow(x, y, _)
+            | AstExpr::BitAnd(x, y, _)
+            | AstExpr::BitOr(x, y, _)
+            | AstExpr::BitXor(x, y, _)
+            | AstExpr::Shl(x, y, _)
+            | AstExpr::Shr(x, y, _)
+            | AstExpr::AddEq(x, y, _)
+            | AstExpr::SubEq(x, y, _)
+            | AstExpr::MulEq(x, y, _)
+            | AstExpr::DivEq(x, y, _)
+            | AstExpr::ModEq(x, y, _)
+            | AstExpr::Eq(x, y, _)
+            | AstExpr::Neq(x, y, _)
+            | AstExpr::Grt(x, y, _)
+            | AstExpr::Les(x, y, _)
+            | AstExpr::Geq(x, y, _)
+            | AstExpr::Leq(x, y, _)
+            | AstExpr::Index(x, y, _)
+            | AstExpr::And(x, y, _)
+            | AstExpr::Or(x, y, _) => {
+                self.resolve_expr(x);
+                self.resolve_expr(y);
+            }
+            AstExpr::Not(x, _)
+            | AstExpr::Neg(x, _)
+            | AstExpr::Field(x, _)
+            | AstExpr::Try(x)
+            | AstExpr::Return(x)
+            | AstExpr::Break(_, x) => self.resolve_expr(x),
+            AstExpr::AssignField(object, _, value) => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            AstExpr::AssignIndex(object, index, value, _) => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            AstExpr::FnCall(caller, args) => {
+                self.resolve_expr(caller);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            AstExpr::Block(block) => self.resolve_block(block),
+            AstExpr::Lambda(fdef, fbody) => self.resolve_fn(fdef, Rc::make_mut(fbody)),
+            AstExpr::IfElse(cond, true_case, false_case) => {
+                self.resolve_expr(cond);
+                self.resolve_block(true_case);
+                self.resolve_block(false_case);
+            }
+            AstExpr::While(_, cond, body) => {
+                self.resolve_expr(cond);
+                self.resolve_block(body);
+            }
+            AstExpr::For(_, name, expr, body) => {
+                self.resolve_expr(expr);
+                self.scopes.push(Scope::new(vec![name.clone()], self.level));
+                self.resolve_block(body);
+                self.scopes.pop();
+            }
+            AstExpr::Range(start, end, step, _) => {
+                self.resolve_expr(start);
+                self.resolve_expr(end);
+                if let Some(step) = step {
+                    self.resolve_expr(step);
+                }
+            }
+            AstExpr::Match(expr, arms, at) => {
+                self.resolve_expr(expr);
+                let patterns: Vec<_> = arms.iter().map(|(pattern, _)| pattern).collect();
+                if let Err(err) = check_exhaustive(&self.enums, &patterns) {
+                    self.diagnostics.push(err.with_at(*at));
+                }
+                for (pattern, body) in arms {
+                    let mut names = Vec::new();
+                    bindings(pattern, &mut names);
+                    self.scopes.push(Scope::new(names, self.level));
+                    self.resolve_expr(body);
+                    self.scopes.pop();
+                }
+            }
+            AstExpr::LiteralUnit(items) | AstExpr::LiteralArray(items) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            AstExpr::LiteralMap(entries) => {
+                for (key, value, _) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            AstExpr::LiteralStruct(_, fields) => {
+                for (_, expr) in fields {
+                    self.resolve_expr(expr);
+                }
+            }
+            AstExpr::Path(_, _)
+            | AstExpr::Continue(_)
+            | AstExpr::LiteralNum(_)
+            | AstExpr::LiteralStr(_)
+            | AstExpr::LiteralBool(_) => {}
+        }
+    }
+
+    /// Declares the next name of the innermost scope, which is `name`.
+    fn declare(&mut self, name: &Rc<String>, is_let: bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        let previous = scope.names[..scope.declared]
+            .iter()
+            .rposition(|declared| declared == name)
+            .map(|idx| scope.at[idx]);
+        scope.declared += 1;
+        if let (true, Some(previous)) = (is_let, previous) {
+            self.warn(
+                self.at,
+                format!("`{}` is already declared in this block.", name),
+                previous,
+                "first declared here",
+            );
+        }
+    }
+
+    /// Finds the slot of `name`, used at `at`, from the innermost scope out.
+    /// A name declared later in a scope is only found from the functions
+    /// defined before it, which run once it is.
+    fn lookup(&mut self, name: &Rc<String>, at: TokenAt) -> Option<VarSlot> {
+        let globals = self.scopes.len() - 1;
+        let mut later = None;
+        let mut res = None;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let found = match scope.names[..scope.declared]
+                .iter()
+                .rposition(|declared| declared == name)
+            {
+                Some(slot) => Some(slot),
+                None => match scope.names[scope.declared..]
+                    .iter()
+                    .position(|declared| declared == name)
+                {
+                    Some(idx) if scope.level == self.level => {
+                        later = later.or(Some(scope.at[scope.declared + idx]));
+                        None
+                    }
+                    idx => idx.map(|idx| scope.declared + idx),
+                },
+            };
+            if let Some(slot) = found {
+                res = Some(VarSlot { depth, slot });
+                break;
+            }
+        }
+        match res {
+            Some(var) if var.depth == globals => return None,
+            Some(var) => {
+                self.capture(globals - var.depth, var.slot);
+                return Some(var);
+            }
+            None => {}
+        }
+        match later {
+            Some(declared) => self.warn(
+                Some(at),
+                format!("`{}` is used before its declaration.", name),
+                declared,
+                "declared here",
+            ),
+            None if !self.names.contains(name) && !builtins::is_builtin(name) => {
+                self.diagnostics.push(
+                    TiError::new(
+                        TiErrorKind::Reference,
+                        format!("name `{}` is not declared.", name),
+                    )
+                    .with_at(at),
+                );
+            }
+            None => {}
+        }
+        None
+    }
+
+    /// Adds the variable at `slot` of the scope at `idx` of `scopes` to the
+    /// captures of the functions around the point being resolved that it
+    /// is outside of.
+    fn capture(&mut self, idx: usize, slot: usize) {
+        for (params, captures) in self.captures.iter_mut().rev() {
+            if *params <= idx {
+                break;
+            }
+            let var = VarSlot {
+                depth: *params - 1 - idx,
+                slot,
+            };
+            if !captures.contains(&var) {
+                captures.push(var);
+            }
+        }
+    }
+
+    fn warn(&mut self, at: Option<TokenAt>, message: String, label: Option<TokenAt>, text: &str) {
+        let mut warning = TiError::new(TiErrorKind::Warning, message);
+        if let Some(at) = at {
+            warning = warning.with_at(at);
+        }
+        if let Some(at) = label {
+            warning = warning.with_label(at, text.to_string());
+        }
+        self.diagnostics.push(warning);
+    }
+}
+
+/// The items of `program` in the order the vm runs them.
+fn hoisted(program: &AstProgram) -> Vec<(&AstNode, &TokenAt)> {
+    let mut stmts: Vec<_> = program.program.iter().zip(&program.at).collect();
+    stmts.sort_by_key(|(stmt, _)| stmt.hoisting());
+    stmts
+}
+
+/// The names bound by `pattern`, in the order the vm binds them.
+fn bindings(pattern: &AstPattern, names: &mut Vec<Rc<String>>) {
+    match pattern {
+        AstPattern::Binding(name) => names.push(name.clone()),
+        AstPattern::Variant(_, _, patterns) => {
+            for pattern in patterns {
+                bindings(pattern, names);
+            }
+        }
+        AstPattern::Wildcard
+        | AstPattern::LiteralNum(_)
+        | AstPattern::LiteralStr(_)
+        | AstPattern::LiteralBool(_) => {}
+    }
+}
@@ -1,4 +1,7 @@
+#![allow(clippy::module_inception)]
+
 pub mod error;
 pub mod frontend;
 pub mod backend;
-pub mod vm;
\ No newline at end of file
+pub mod vm;
+pub mod driver;
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::rc::Rc;
+
+use crate::{
+    error::error::TiError,
+    frontend::parser::ast::{EnumDef, EnumField, Type},
+    vm::environment::environment::TiEnvironment,
+};
+
+use super::types::object_type::TiObj;
+
+pub type TiBuiltinFn = fn(Vec<TiObj>) -> Result<TiObj, TiError>;
+
+static BUILTINS: &[(&str, TiBuiltinFn)] = &[("print", print), ("println", println)];
+
+/// The builtin enum `Option { Some(x), None }`, which the `next` method of
+/// an iterator returns.
+pub const OPTION: &str = "Option";
+/// The builtin enum `Result { Ok(x), Err(e) }`.
+pub const RESULT: &str = "Result";
+
+/// Whether `name` is the name of a builtin function.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+/// Whether `name` is the name of a builtin enum.
+pub fn is_builtin_enum(name: &str) -> bool {
+    matches!(name, OPTION | RESULT)
+}
+
+/// The builtin enums, `Option` and `Result`, which every program has and
+/// none may define again, as `for` and `?` rely on them.
+pub fn enums() -> Vec<EnumDef> {
+    let variant = |name: &str, len: usize| EnumField {
+        name: Rc::new(name.to_string()),
+        values: vec![Type::Unknown; len],
+    };
+    vec![
+        EnumDef {
+            name: Rc::new(OPTION.to_string()),
+            fields: vec![variant("Some", 1), variant("None", 0)],
+        },
+        EnumDef {
+            name: Rc::new(RESULT.to_string()),
+            fields: vec![variant("Ok", 1), variant("Err", 1)],
+        },
+    ]
+}
+
+/// Binds every builtin function in the current frame of `environment`.
+pub fn register(environment: &mut TiEnvironment) {
+    for (name, f) in BUILTINS {
+        environment.set(name.to_string().into(), TiObj::Builtin(name, *f));
+    }
+}
+
+/// Formats `args` the way `print` does: when the first argument is a string,
+/// each `{}` in it is replaced by the next argument, and the remaining
+/// arguments are appended separated by spaces.
+pub fn format_args(args: Vec<TiObj>) -> String {
+    let mut args = args.into_iter();
+    let mut out = match args.next() {
+        Some(TiObj::Str(fmt)) => {
+            let mut out = String::new();
+            let mut parts = fmt.split("{}").peekable();
+            while let Some(part) = parts.next() {
+                out.push_str(part);
+                if parts.peek().is_some() {
+                    match args.next() {
+                        Some(arg) => out.push_str(&arg.to_string()),
+                        None => out.push_str("{}"),
+                    }
+                }
+            }
+            out
+        }
+        Some(arg) => arg.to_string(),
+        None => String::new(),
+    };
+    for arg in args {
+        out.push(' ');
+        out.push_str(&arg.to_string());
+    }
+    out
+}
+
+fn print(args: Vec<TiObj>) -> Result<TiObj, TiError> {
+    print!("{}", format_args(args));
+    Ok(TiObj::build_unit())
+}
+
+fn println(args: Vec<TiObj>) -> Result<TiObj, TiError> {
+    println!("{}", format_args(args));
+    Ok(TiObj::build_unit())
+}
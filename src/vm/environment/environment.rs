@@ -1,61 +1,199 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
-use crate::vm::types::object_type::TiObj;
+use crate::{frontend::parser::ast::VarSlot, vm::types::object_type::TiObj};
 
-#[derive(Debug, Clone)]
+/// A variable of a frame, shared with the closures that capture it. It is
+/// `None` until its declaration runs.
+type TiCell = Rc<RefCell<Option<TiObj>>>;
+
+/// The names of the outermost frame are globals, looked up by name; the
+/// resolver gives the variables of every other frame a slot.
+#[derive(Default)]
+struct TiFrame {
+    vars: HashMap<Rc<String>, TiObj>,
+    slots: Vec<TiCell>,
+    /// how many of `slots` are declared; the ones after them were made by
+    /// a closure capturing a variable declared later
+    declared: usize,
+    parent: Option<TiEnvironment>,
+}
+
+impl TiFrame {
+    /// The variable at `slot`, made undeclared if there is none yet.
+    fn cell(&mut self, slot: usize) -> TiCell {
+        if self.slots.len() <= slot {
+            self.slots.resize_with(slot + 1, TiCell::default);
+        }
+        self.slots[slot].clone()
+    }
+}
+
+/// A chain of frames, innermost first. Cloning shares the frames; a
+/// function keeps the globals and the variables it captured from the
+/// environment it was defined in, and sees them change afterwards.
+#[derive(Clone)]
 pub struct TiEnvironment {
-    vars: Vec<HashMap<Rc<String>, TiObj>>,
+    frame: Rc<RefCell<TiFrame>>,
+}
+
+impl Default for TiEnvironment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TiEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // a function is usually stored in the environment it captured
+        f.debug_struct("TiEnvironment").finish_non_exhaustive()
+    }
 }
 
 impl TiEnvironment {
     pub fn new() -> Self {
         Self {
-            vars: vec![HashMap::new()],
+            frame: Rc::new(RefCell::new(TiFrame::default())),
         }
     }
-    pub fn create_frame(&mut self) {
-        self.vars.push(HashMap::new());
+    /// Whether `self` and `other` share their frames, rather than only
+    /// holding the same names.
+    pub fn same(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.frame, &other.frame)
+    }
+    /// A new frame on top of `self`, leaving `self` as it is.
+    pub fn child(&self) -> Self {
+        Self {
+            frame: Rc::new(RefCell::new(TiFrame {
+                parent: Some(self.clone()),
+                ..TiFrame::default()
+            })),
+        }
+    }
+    /// The environment of a closure defined in `self` that uses the
+    /// variables at `captures`: new frames out to the globals, holding only
+    /// those variables at the same depths and slots. Nothing else of the
+    /// frames of `self` is kept, so a closure stored in one of them doesn't
+    /// keep it alive.
+    pub fn capture(&self, captures: &[VarSlot]) -> Self {
+        let mut frames = Vec::new();
+        let mut frame = self.frame.clone();
+        loop {
+            let parent = match &frame.borrow().parent {
+                Some(parent) => parent.frame.clone(),
+                None => break,
+            };
+            frames.push(std::mem::replace(&mut frame, parent));
+        }
+        let len = captures.iter().map(|var| var.depth + 1).max().unwrap_or(0);
+        let mut env = Self { frame };
+        for (depth, frame) in frames.iter().enumerate().take(len).rev() {
+            let mut slots = Vec::new();
+            for var in captures.iter().filter(|var| var.depth == depth) {
+                if slots.len() <= var.slot {
+                    slots.resize_with(var.slot + 1, TiCell::default);
+                }
+                slots[var.slot] = frame.borrow_mut().cell(var.slot);
+            }
+            env = Self {
+                frame: Rc::new(RefCell::new(TiFrame {
+                    vars: HashMap::new(),
+                    declared: slots.len(),
+                    slots,
+                    parent: Some(env),
+                })),
+            };
+        }
+        env
     }
-    pub fn with(&mut self, mut environment: TiEnvironment) -> &Self {
-        self.vars.append(&mut environment.vars);
-        self
+    pub fn create_frame(&mut self) {
+        *self = self.child();
     }
     pub fn remove_frame(&mut self) {
-        self.vars.pop();
+        let parent = self.frame.borrow().parent.clone();
+        if let Some(parent) = parent {
+            *self = parent;
+        }
     }
-    pub fn set(&mut self, name: Rc<String>, value: TiObj) {
-        self.vars.last_mut().unwrap().insert(name, value);
+    /// The first result of `f` on the frames from the innermost one out.
+    fn find<R>(
+        &self,
+        mut f: impl FnMut(&mut HashMap<Rc<String>, TiObj>) -> Option<R>,
+    ) -> Option<R> {
+        let mut frame = self.frame.clone();
+        loop {
+            if let Some(res) = f(&mut frame.borrow_mut().vars) {
+                return Some(res);
+            }
+            let parent = frame.borrow().parent.as_ref()?.frame.clone();
+            frame = parent;
+        }
     }
-    pub fn set_v(&mut self, name: Rc<String>, value: TiObj) {
-        let mut i = self.vars.len();
-        while i > 0 {
-            i -= 1;
-            let v = self.vars.get_mut(i).unwrap();
-            if let Some(_) = v.get(&name) {
-                v.insert(name, value);
-                return
+    /// Binds a new variable: a global in the outermost frame, otherwise the
+    /// next slot of the innermost frame.
+    pub fn declare(&mut self, name: Rc<String>, value: TiObj) {
+        let mut frame = self.frame.borrow_mut();
+        if frame.parent.is_some() {
+            let slot = frame.declared;
+            frame.declared += 1;
+            *frame.cell(slot).borrow_mut() = Some(value);
+        } else {
+            frame.vars.insert(name, value);
+        }
+    }
+    fn frame_at(&self, depth: usize) -> Option<Rc<RefCell<TiFrame>>> {
+        let mut frame = self.frame.clone();
+        for _ in 0..depth {
+            let parent = frame.borrow().parent.as_ref()?.frame.clone();
+            frame = parent;
+        }
+        Some(frame)
+    }
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<TiObj> {
+        self.frame_at(depth)?
+            .borrow()
+            .slots
+            .get(slot)?
+            .borrow()
+            .clone()
+    }
+    /// Assigns to the variable at `slot`, if it has been declared.
+    pub fn set_at(&mut self, depth: usize, slot: usize, value: TiObj) {
+        if let Some(frame) = self.frame_at(depth) {
+            if let Some(cell) = frame.borrow().slots.get(slot) {
+                if let Some(v) = cell.borrow_mut().as_mut() {
+                    *v = value;
+                }
             }
         }
     }
+    pub fn set(&mut self, name: Rc<String>, value: TiObj) {
+        self.frame.borrow_mut().vars.insert(name, value);
+    }
+    pub fn set_v(&mut self, name: Rc<String>, value: TiObj) {
+        let mut value = Some(value);
+        self.find(|vars| {
+            let v = vars.get_mut(&name)?;
+            *v = value.take()?;
+            Some(())
+        });
+    }
     pub fn move_out(&mut self, name: &Rc<String>) -> Option<TiObj> {
-        let mut i = self.vars.len();
-        while i > 0 {
-            i -= 1;
-            if let Some(v) = self.vars.get_mut(i).unwrap().remove(name) {
-                return Some(v);
+        self.find(|vars| vars.remove(name))
+    }
+    /// Every visible binding, shadowed names resolved to the innermost one.
+    pub fn bindings(&self) -> HashMap<Rc<String>, TiObj> {
+        let mut bindings = HashMap::new();
+        self.find(|vars| {
+            for (name, value) in vars.iter() {
+                bindings
+                    .entry(name.clone())
+                    .or_insert_with(|| value.clone());
             }
-        }
-        None
+            None::<()>
+        });
+        bindings
     }
     pub fn get(&self, name: &Rc<String>) -> Option<TiObj> {
-        // self.vars.get(name).and_then(|v| Some(v.clone()))
-        let mut i = self.vars.len();
-        while i > 0 {
-            i -= 1;
-            if let Some(v) = self.vars.get(i).unwrap().get(name) {
-                return Some(v.clone());
-            }
-        }
-        None
+        self.find(|vars| vars.get(name).cloned())
     }
 }
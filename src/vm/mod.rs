@@ -2,4 +2,5 @@ pub mod vm;
 pub mod types;
 pub mod builtins;
 pub mod environment;
+pub mod pattern;
 pub mod vm_ast;
\ No newline at end of file
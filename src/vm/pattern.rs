@@ -0,0 +1,34 @@
+use std::rc::Rc;
+
+use crate::frontend::parser::ast::AstPattern;
+
+use super::types::object_type::TiObj;
+
+/// Whether `value` matches `pattern`; on success the names bound by the
+/// pattern are pushed to `bindings`.
+pub fn match_pattern(
+    pattern: &AstPattern,
+    value: &TiObj,
+    bindings: &mut Vec<(Rc<String>, TiObj)>,
+) -> bool {
+    match (pattern, value) {
+        (AstPattern::Wildcard, _) => true,
+        (AstPattern::Binding(name), _) => {
+            bindings.push((name.clone(), value.clone()));
+            true
+        }
+        (AstPattern::LiteralNum(x), TiObj::Num(y)) => x == y,
+        (AstPattern::LiteralStr(x), TiObj::Str(y)) => x == y,
+        (AstPattern::LiteralBool(x), TiObj::Bool(y)) => x == y,
+        (AstPattern::Variant(name, variant, patterns), TiObj::Enum(ename, evariant, values)) => {
+            name == ename
+                && variant == evariant
+                && patterns.len() == values.len()
+                && patterns
+                    .iter()
+                    .zip(values)
+                    .all(|(pattern, value)| match_pattern(pattern, value, bindings))
+        }
+        _ => false,
+    }
+}
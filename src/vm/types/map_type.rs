@@ -0,0 +1,97 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use super::object_type::TiObj;
+
+/// The map behind `TiObj::Map`. Its entries stay in the order their keys
+/// were first added, so walking or printing a map gives the same order on
+/// every run, and a hash index finds the entry of a key.
+#[derive(Debug, Clone, Default)]
+pub struct TiMap {
+    entries: Vec<(TiObj, TiObj)>,
+    /// where the entry of each key is in `entries`
+    index: HashMap<TiObj, usize>,
+}
+
+impl TiMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &TiObj) -> Option<&TiObj> {
+        self.index.get(key).map(|idx| &self.entries[*idx].1)
+    }
+
+    pub fn get_mut(&mut self, key: &TiObj) -> Option<&mut TiObj> {
+        self.index.get(key).map(|idx| &mut self.entries[*idx].1)
+    }
+
+    /// Sets the value of `key`, which keeps its place when it's already in
+    /// the map and goes last otherwise.
+    pub fn insert(&mut self, key: TiObj, value: TiObj) {
+        match self.index.get(&key) {
+            Some(idx) => self.entries[*idx].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// The entries in order.
+    pub fn iter(&self) -> std::slice::Iter<'_, (TiObj, TiObj)> {
+        self.entries.iter()
+    }
+}
+
+impl IntoIterator for TiMap {
+    type Item = (TiObj, TiObj);
+    type IntoIter = std::vec::IntoIter<(TiObj, TiObj)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// Two maps are equal when they have the same keys with equal values,
+/// whatever order the keys were added in.
+impl PartialEq for TiMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key).is_some_and(|other| value == other))
+    }
+}
+
+/// Adds up the hashes of the entries, which doesn't depend on their order
+/// as equality doesn't.
+impl Hash for TiMap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut sum: u64 = 0;
+        for entry in &self.entries {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            sum = sum.wrapping_add(hasher.finish());
+        }
+        state.write_usize(self.len());
+        state.write_u64(sum);
+    }
+}
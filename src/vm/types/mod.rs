@@ -1,2 +1,4 @@
 pub mod object_type;
+pub mod map_type;
 pub mod builtins;
+pub mod registry;
@@ -1,234 +1,193 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::ControlFlow,
+    rc::Rc,
+};
 
 use crate::{
+    backend::bytecode::bytecode::TiFnProto,
     build_ti_error,
+    error::error::TiError,
     frontend::parser::ast::{AstBlock, FnDef},
-    vm::environment::environment::TiEnvironment,
+    vm::{
+        builtins::{TiBuiltinFn, OPTION, RESULT},
+        environment::environment::TiEnvironment,
+        vm::TiUpvalues,
+    },
 };
 
-macro_rules! impl_binary_operator {
-  (@num $op_num: tt $num_t: ident $($num_b: tt)? @str $op_str: tt $str_t: ident @bool $op_bool: tt $bool_t: ident $($bool_b: tt)?, $a: expr, $b: expr) => {
-    match $a {
-      TiObj::Map(_) => match $b {
-        TiObj::Map(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::List(_) => match $b {
-        TiObj::List(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Num(x) => match $b {
-        TiObj::Num(y) => {
-          TiObj::$num_t(x $op_num $($num_b)? y)
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Str(ref x) => match $b {
-        TiObj::Str(y) => {
-          TiObj::$str_t(x $op_str y)
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Bool(x) => match $b {
-        TiObj::Bool(y) => {
-          TiObj::$bool_t(x $op_bool $($bool_b)? y)
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Fn(_, _, _) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
-      },
-    }
-  };
-
-  (@num $op_num: tt $num_t: ident $($num_b: tt)? @str $op_str: tt $str_t: ident, $a: expr, $b: expr) => {
-    match $a {
-      TiObj::Map(_) => match $b {
-        TiObj::Map(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::List(_) => match $b {
-        TiObj::List(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Num(x) => match $b {
-        TiObj::Num(y) => {
-          TiObj::$num_t(x $op_num $($num_b)? y)
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Str(ref x) => match $b {
-        TiObj::Str(y) => {
-          TiObj::$str_t(x $op_str y)
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
-      },
-      TiObj::Bool(_) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
-      },
-      TiObj::Fn(_, _, _) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
-      },
-    }
-  };
+use super::map_type::TiMap;
 
+macro_rules! impl_binary_operator {
   (@num $op_num: tt $num_t: ident $($num_b: tt)? @str_owned $op_str: tt $str_t: ident, $a: expr, $b: expr) => {
     match $a {
-      TiObj::Map(_) => match $b {
-        TiObj::Map(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
+      TiObj::Map(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), TiObjType::Map)
       },
-      TiObj::List(_) => match $b {
-        TiObj::List(_) => {
-          todo!()
+      TiObj::List(mut x) => match $b {
+        TiObj::List(y) => {
+          x.extend(y.iter().cloned());
+          Ok(TiObj::List(x))
         },
         _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+          build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", TiObjType::List, $b.as_type())
         },
       },
       TiObj::Num(x) => match $b {
         TiObj::Num(y) => {
-          TiObj::$num_t(x $op_num $($num_b)? y)
+          Ok(TiObj::$num_t(x $op_num $($num_b)? y))
         },
         _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+          build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
         },
       },
       TiObj::Str(ref x) => match $b {
         TiObj::Str(y) => {
-          TiObj::$str_t(x.to_owned() $op_str y)
+          Ok(TiObj::$str_t(x.to_owned() $op_str y))
         },
         _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+          build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
         },
       },
       TiObj::Bool(_) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), $a.as_type())
       },
-      TiObj::Fn(_, _, _) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
+      TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _) | TiObj::Struct(_, _) | TiObj::Enum(_, _, _) | TiObj::Range(_) | TiObj::Iter(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), $a.as_type())
       },
     }
   };
 
   (@num $op_num: tt $num_t: ident, $a: expr, $b: expr) => {
     match $a {
-      TiObj::Map(_) => match $b {
-        TiObj::Map(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
+      TiObj::Map(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), TiObjType::Map)
       },
-      TiObj::List(_) => match $b {
-        TiObj::List(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
+      TiObj::List(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), TiObjType::List)
       },
       TiObj::Num(x) => match $b {
         TiObj::Num(y) => {
-          TiObj::$num_t(x $op_num y)
+          Ok(TiObj::$num_t(x $op_num y))
         },
         _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+          build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
         },
       },
       TiObj::Str(_) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Str)
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), TiObjType::Str)
       },
       TiObj::Bool(_) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Bool)
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), TiObjType::Bool)
       },
-      TiObj::Fn(_, _, _) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
+      TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _) | TiObj::Struct(_, _) | TiObj::Enum(_, _, _) | TiObj::Range(_) | TiObj::Iter(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_num), $a.as_type())
       },
     }
   };
 
   (@bool $op_bool: tt $bool_t: ident, $a: expr, $b: expr) => {
     match $a {
-      TiObj::Map(_) => match $b {
-        TiObj::Map(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
+      TiObj::Map(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_bool), TiObjType::Map)
       },
-      TiObj::List(_) => match $b {
-        TiObj::List(_) => {
-          todo!()
-        },
-        _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
-        },
+      TiObj::List(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_bool), TiObjType::List)
       },
       TiObj::Num(_) => {
-        build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+        build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
       },
       TiObj::Str(_) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Str)
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_bool), TiObjType::Str)
       },
       TiObj::Bool(x) => match $b {
         TiObj::Bool(y) => {
-          TiObj::$bool_t(x $op_bool y)
+          Ok(TiObj::$bool_t(x $op_bool y))
         },
         _ => {
-          build_ti_error!(@err "type error: mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
+          build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", $a.as_type(), $b.as_type())
         },
       },
-      TiObj::Fn(_, _, _) => {
-        build_ti_error!(@err "type error: the operator `{}` is not implemtnted for type `{:?}`.", stringify!($op_num), TiObjType::Fn)
+      TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _) | TiObj::Struct(_, _) | TiObj::Enum(_, _, _) | TiObj::Range(_) | TiObj::Iter(_) => {
+        build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", stringify!($op_bool), $a.as_type())
       },
     }
   };
 }
 
+/// The signature shared by the binary operators of `TiObj`.
+pub type TiBinaryOp = fn(TiObj, &TiObj) -> Result<TiObj, TiError>;
+
+/// The signature shared by the unary operators of `TiObj`.
+pub type TiUnaryOp = fn(TiObj) -> Result<TiObj, TiError>;
+
 #[derive(Debug, Clone)]
 pub enum TiObj {
-    Map(HashMap<TiObj, TiObj>),
+    /// boxed, as a map is much larger than the other values
+    Map(Box<TiMap>),
     List(Vec<TiObj>),
     Num(f64),
     Str(String),
     Bool(bool),
-    Fn(FnDef, AstBlock, TiEnvironment),
+    Fn(FnDef, Rc<AstBlock>, TiEnvironment),
+    Builtin(&'static str, TiBuiltinFn),
+    Proto(Rc<TiFnProto>, TiUpvalues),
+    Struct(Rc<String>, Vec<(Rc<String>, TiObj)>),
+    Enum(Rc<String>, Rc<String>, Vec<TiObj>),
+    Range(TiRange),
+    /// the state of a `for` loop, which the bytecode vm keeps in a local
+    Iter(Box<TiIter>),
+}
+
+/// The numbers from `start` up to `end`, or down to it when `step` is
+/// negative, `end` itself included when `inclusive`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TiRange {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    pub inclusive: bool,
 }
 
-#[derive(Debug)]
+impl TiRange {
+    /// The number `n` steps from the start, `None` past the end.
+    pub fn at(&self, n: usize) -> Option<f64> {
+        let x = self.start + self.step * n as f64;
+        let before_end = if self.step > 0.0 { x < self.end } else { x > self.end };
+        (before_end || self.inclusive && x == self.end).then_some(x)
+    }
+}
+
+/// How far a `for` loop got through the value it walks. Lists, strings,
+/// maps and ranges are walked here; a user type is walked by the vm, which
+/// calls its `next` method until it returns `None`.
+#[derive(Debug, Clone)]
+pub enum TiIter {
+    Items(std::vec::IntoIter<TiObj>),
+    Range(TiRange, usize),
+    Method(TiObj),
+}
+
+impl Iterator for TiIter {
+    type Item = TiObj;
+
+    /// The next value of a builtin iterator, a `Method` one gives none here.
+    fn next(&mut self) -> Option<TiObj> {
+        match self {
+            TiIter::Items(items) => items.next(),
+            TiIter::Range(range, n) => {
+                let x = range.at(*n)?;
+                *n += 1;
+                Some(TiObj::Num(x))
+            }
+            TiIter::Method(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum TiObjType {
     Map,
     List,
@@ -236,8 +195,13 @@ pub enum TiObjType {
     Str,
     Bool,
     Fn,
+    Struct,
+    Enum,
+    Range,
+    Iter,
 }
 
+#[allow(clippy::should_implement_trait)]
 impl TiObj {
     pub fn as_type(&self) -> TiObjType {
         match self {
@@ -246,67 +210,171 @@ impl TiObj {
             TiObj::Num(_) => TiObjType::Num,
             TiObj::Str(_) => TiObjType::Str,
             TiObj::Bool(_) => TiObjType::Bool,
-            TiObj::Fn(_, _, _) => TiObjType::Fn,
+            TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _) => TiObjType::Fn,
+            TiObj::Struct(_, _) => TiObjType::Struct,
+            TiObj::Enum(_, _, _) => TiObjType::Enum,
+            TiObj::Range(_) => TiObjType::Range,
+            TiObj::Iter(_) => TiObjType::Iter,
         }
     }
 
-    pub fn add(self, b: &TiObj) -> TiObj {
+    pub fn add(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@num + Num * @str_owned + Str, self, b)
     }
 
-    pub fn sub(self, b: &TiObj) -> TiObj {
+    pub fn sub(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@num - Num, self, b)
     }
 
-    pub fn mul(self, b: &TiObj) -> TiObj {
+    pub fn mul(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@num * Num, self, b)
     }
 
-    pub fn div(self, b: &TiObj) -> TiObj {
+    pub fn div(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@num / Num, self, b)
     }
 
-    pub fn r#mod(self, b: &TiObj) -> TiObj {
+    pub fn r#mod(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@num % Num, self, b)
     }
 
-    pub fn neg(self) -> Self {
-        todo!()
+    pub fn pow(self, b: &TiObj) -> Result<TiObj, TiError> {
+        match (&self, b) {
+            (TiObj::Num(x), TiObj::Num(y)) => Ok(TiObj::Num(x.powf(*y))),
+            (TiObj::Num(_), _) => {
+                build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", self.as_type(), b.as_type())
+            }
+            _ => {
+                build_ti_error!(@err Type, "the operator `**` is not implemented for type `{:?}`.", self.as_type())
+            }
+        }
+    }
+
+    pub fn neg(self) -> Result<TiObj, TiError> {
+        match self {
+            TiObj::Num(x) => Ok(TiObj::Num(-x)),
+            _ => {
+                build_ti_error!(@err Type, "the operator `-` is not implemented for type `{:?}`.", self.as_type())
+            }
+        }
+    }
+
+    /// `!` of a bool, or the bitwise not of an integer.
+    pub fn not(self) -> Result<TiObj, TiError> {
+        match self {
+            TiObj::Bool(x) => Ok(TiObj::Bool(!x)),
+            TiObj::Num(_) => Ok(TiObj::Num(!self.as_int("!")? as f64)),
+            _ => {
+                build_ti_error!(@err Type, "the operator `!` is not implemented for type `{:?}`.", self.as_type())
+            }
+        }
+    }
+
+    pub fn bit_and(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Num((self.as_int("&")? & b.as_int("&")?) as f64))
+    }
+
+    pub fn bit_or(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Num((self.as_int("|")? | b.as_int("|")?) as f64))
+    }
+
+    pub fn bit_xor(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Num((self.as_int("^")? ^ b.as_int("^")?) as f64))
+    }
+
+    pub fn shl(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Num((self.as_int("<<")? << b.as_shift("<<")?) as f64))
+    }
+
+    /// An arithmetic shift, keeping the sign of a negative number.
+    pub fn shr(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Num((self.as_int(">>")? >> b.as_shift(">>")?) as f64))
     }
 
-    pub fn not(self) -> Self {
-        todo!()
+    /// The whole number the bitwise operator `op` works on, which has to
+    /// fit in 64 bits.
+    fn as_int(&self, op: &str) -> Result<i64, TiError> {
+        match self {
+            TiObj::Num(x) if x.fract() == 0.0 && *x >= i64::MIN as f64 && *x < i64::MAX as f64 => {
+                Ok(*x as i64)
+            }
+            TiObj::Num(x) => {
+                build_ti_error!(@err Type, "the operator `{}` expects an integer, found `{}`.", op, x)
+            }
+            _ => {
+                build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", op, self.as_type())
+            }
+        }
+    }
+
+    /// How far the shift `op` moves the bits, less than 64.
+    fn as_shift(&self, op: &str) -> Result<u32, TiError> {
+        let n = self.as_int(op)?;
+        if !(0..64).contains(&n) {
+            build_ti_error!(@err Runtime, "the shift amount of `{}` must be in `0..64`, found `{}`.", op, n)
+        }
+        Ok(n as u32)
+    }
+
+    /// How `<`, `>`, `<=` and `>=` order `self` against `other`, as `op`:
+    /// numbers, strings and bools as usual, and lists by their items in
+    /// turn, then by their length. `None` when a `NaN` decides, which makes
+    /// all four false. Other values, and values of different types, can't
+    /// be ordered.
+    pub fn compare(&self, other: &TiObj, op: &str) -> Result<Option<Ordering>, TiError> {
+        match (self, other) {
+            (TiObj::Num(x), TiObj::Num(y)) => Ok(x.partial_cmp(y)),
+            (TiObj::Str(x), TiObj::Str(y)) => Ok(Some(x.cmp(y))),
+            (TiObj::Bool(x), TiObj::Bool(y)) => Ok(Some(x.cmp(y))),
+            (TiObj::List(x), TiObj::List(y)) => {
+                for (x, y) in x.iter().zip(y) {
+                    match x.compare(y, op)? {
+                        Some(Ordering::Equal) => {}
+                        ord => return Ok(ord),
+                    }
+                }
+                Ok(Some(x.len().cmp(&y.len())))
+            }
+            _ if self.as_type() != other.as_type() => {
+                build_ti_error!(@err Type, "mismatched type `{:?}` and `{:?}`.", self.as_type(), other.as_type())
+            }
+            _ => {
+                build_ti_error!(@err Type, "the operator `{}` is not implemented for type `{:?}`.", op, self.as_type())
+            }
+        }
     }
 
-    pub fn les(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num < Bool * @str < Bool, self, b)
+    pub fn les(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self.compare(b, "<")?.is_some_and(Ordering::is_lt)))
     }
 
-    pub fn grt(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num > Bool * @str > Bool, self, b)
+    pub fn grt(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self.compare(b, ">")?.is_some_and(Ordering::is_gt)))
     }
 
-    pub fn eq(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num == Bool * @str == Bool @bool == Bool *, self, b)
+    /// `==` by the `PartialEq` of `TiObj`, so values of different types are
+    /// unequal rather than an error.
+    pub fn eq(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self == *b))
     }
 
-    pub fn neq(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num != Bool * @str != Bool @bool != Bool *, self, b)
+    pub fn neq(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self != *b))
     }
 
-    pub fn leq(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num <= Bool * @str <= Bool, self, b)
+    pub fn leq(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self.compare(b, "<=")?.is_some_and(Ordering::is_le)))
     }
 
-    pub fn geq(self, b: &TiObj) -> Self {
-        impl_binary_operator!(@num >= Bool * @str >= Bool, self, b)
+    pub fn geq(self, b: &TiObj) -> Result<TiObj, TiError> {
+        Ok(TiObj::Bool(self.compare(b, ">=")?.is_some_and(Ordering::is_ge)))
     }
 
-    pub fn and(self, b: &TiObj) -> Self {
+    pub fn and(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@bool && Bool, self, *b)
     }
 
-    pub fn or(self, b: &TiObj) -> Self {
+    pub fn or(self, b: &TiObj) -> Result<TiObj, TiError> {
         impl_binary_operator!(@bool || Bool, self, *b)
     }
 }
@@ -315,4 +383,493 @@ impl TiObj {
     pub fn build_unit() -> Self {
         TiObj::List(Vec::new())
     }
+
+    /// The range `start..end`, `start..=end` when `inclusive`, counting by
+    /// `step`.
+    pub fn range(start: TiObj, end: TiObj, step: TiObj, inclusive: bool) -> Result<TiObj, TiError> {
+        match (&start, &end, &step) {
+            (TiObj::Num(start), TiObj::Num(end), TiObj::Num(step)) => {
+                if *step == 0.0 || step.is_nan() {
+                    build_ti_error!(@err Runtime, "the step of a range cannot be {}.", step)
+                }
+                Ok(TiObj::Range(TiRange { start: *start, end: *end, step: *step, inclusive }))
+            }
+            _ => build_ti_error!(@err Type, "a range takes numbers, found `{:?}`, `{:?}` and `{:?}`.", start.as_type(), end.as_type(), step.as_type()),
+        }
+    }
+
+    /// Starts walking the values of `self` in a `for` loop: the items of a
+    /// list, the characters of a string, the `[key, value]` entries of a
+    /// map or the numbers of a range. A struct or an enum walks itself with
+    /// its `next` method.
+    pub fn iter(self) -> Result<TiIter, TiError> {
+        match self {
+            TiObj::List(list) => Ok(TiIter::Items(list.into_iter())),
+            TiObj::Str(x) => {
+                let chars: Vec<_> = x.chars().map(|c| TiObj::Str(c.to_string())).collect();
+                Ok(TiIter::Items(chars.into_iter()))
+            }
+            TiObj::Map(map) => {
+                let entries: Vec<_> = (*map).into_iter().map(|(k, v)| TiObj::List(vec![k, v])).collect();
+                Ok(TiIter::Items(entries.into_iter()))
+            }
+            TiObj::Range(range) => Ok(TiIter::Range(range, 0)),
+            TiObj::Iter(iter) => Ok(*iter),
+            TiObj::Struct(_, _) | TiObj::Enum(_, _, _) => Ok(TiIter::Method(self)),
+            _ => build_ti_error!(@err Type, "type `{:?}` cannot be iterated.", self.as_type()),
+        }
+    }
+
+    /// Whether this is a function taking `self` as its first parameter.
+    pub fn is_method(&self) -> bool {
+        let fdef = match self {
+            TiObj::Fn(fdef, _, _) => fdef,
+            TiObj::Proto(proto, _) => &proto.def,
+            _ => return false,
+        };
+        fdef.arguments
+            .first()
+            .is_some_and(|farg| farg.name.as_str() == "self")
+    }
+
+    /// The name of the type for messages, the declared one for structs
+    /// and enums.
+    pub fn type_name(&self) -> String {
+        match self {
+            TiObj::Struct(name, _) | TiObj::Enum(name, _, _) => name.to_string(),
+            _ => format!("{:?}", self.as_type()),
+        }
+    }
+
+    pub fn field(&self, name: &Rc<String>) -> Result<&TiObj, TiError> {
+        match self {
+            TiObj::Struct(sname, fields) => match fields.iter().find(|(fname, _)| fname == name) {
+                Some((_, value)) => Ok(value),
+                None => build_ti_error!(@err Reference, "no field `{}` on type `{}`.", name, sname),
+            },
+            _ => build_ti_error!(@err Type, "type `{:?}` has no fields, cannot access `{}`.", self.as_type(), name),
+        }
+    }
+
+    /// The map of `entries`, where a key given more than once keeps its
+    /// first place and its last value.
+    pub fn map(entries: Vec<(TiObj, TiObj)>) -> Result<TiObj, TiError> {
+        let mut map = TiObj::Map(Box::new(TiMap::with_capacity(entries.len())));
+        for (key, value) in entries {
+            map.set_index(key, value)?;
+        }
+        Ok(map)
+    }
+
+    /// `self[index]`: the item of a list or the character of a string at a
+    /// whole number `index`, counted from the end when it's negative, the
+    /// part of either a range gives, or the value of a map at a key.
+    pub fn index(&self, index: &TiObj) -> Result<TiObj, TiError> {
+        match (self, index) {
+            (TiObj::Map(map), _) => match map.get(index) {
+                Some(value) => Ok(value.clone()),
+                None => build_ti_error!(@err Runtime, "no key `{}` in the map.", index),
+            },
+            (TiObj::List(list), TiObj::Range(range)) => Ok(TiObj::List(list[slice(range, list.len())?].to_vec())),
+            (TiObj::List(list), _) => Ok(list[position(index, list.len())?].clone()),
+            (TiObj::Str(x), _) => {
+                let chars: Vec<char> = x.chars().collect();
+                match index {
+                    TiObj::Range(range) => Ok(TiObj::Str(chars[slice(range, chars.len())?].iter().collect())),
+                    _ => Ok(TiObj::Str(chars[position(index, chars.len())?].to_string())),
+                }
+            }
+            _ => build_ti_error!(@err Type, "type `{:?}` cannot be indexed.", self.as_type()),
+        }
+    }
+
+    /// The item of a list or the value of a map `self[index]` names, to be
+    /// changed in place.
+    pub fn index_mut(&mut self, index: &TiObj) -> Result<&mut TiObj, TiError> {
+        match (self, index) {
+            (TiObj::Map(map), _) => match map.get_mut(index) {
+                Some(value) => Ok(value),
+                None => build_ti_error!(@err Runtime, "no key `{}` in the map.", index),
+            },
+            (TiObj::List(_) | TiObj::Str(_), TiObj::Range(_)) => build_ti_error!(@err Type, "a slice cannot be assigned to."),
+            (TiObj::List(list), _) => {
+                let idx = position(index, list.len())?;
+                Ok(&mut list[idx])
+            }
+            (TiObj::Str(_), _) => build_ti_error!(@err Type, "the characters of a string cannot be assigned to."),
+            (obj, _) => build_ti_error!(@err Type, "type `{:?}` cannot be indexed.", obj.as_type()),
+        }
+    }
+
+    /// `self[index] = value`, which adds the key `index` to a map that
+    /// doesn't have it yet.
+    pub fn set_index(&mut self, index: TiObj, value: TiObj) -> Result<(), TiError> {
+        match self {
+            TiObj::Map(map) => {
+                index.check_key()?;
+                map.insert(index, value);
+            }
+            _ => *self.index_mut(&index)? = value,
+        }
+        Ok(())
+    }
+
+    /// A map key has to be equal to itself, so it can't be or hold `NaN`,
+    /// a function or an iterator.
+    fn check_key(&self) -> Result<(), TiError> {
+        match self {
+            TiObj::Num(x) if x.is_nan() => {
+                build_ti_error!(@err Runtime, "`NaN` cannot be a map key, it is not equal to itself.")
+            }
+            TiObj::Range(range) if range.start.is_nan() || range.end.is_nan() => {
+                build_ti_error!(@err Runtime, "`NaN` cannot be a map key, it is not equal to itself.")
+            }
+            TiObj::Num(_) | TiObj::Str(_) | TiObj::Bool(_) | TiObj::Range(_) => Ok(()),
+            TiObj::List(items) | TiObj::Enum(_, _, items) => items.iter().try_for_each(TiObj::check_key),
+            // the keys of a map were checked as they were added
+            TiObj::Map(map) => map.iter().try_for_each(|(_, value)| value.check_key()),
+            TiObj::Struct(_, fields) => fields.iter().try_for_each(|(_, value)| value.check_key()),
+            TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _) | TiObj::Iter(_) => {
+                build_ti_error!(@err Type, "type `{:?}` cannot be a map key.", self.as_type())
+            }
+        }
+    }
+
+    /// What `?` does with `self`: go on with the payload of `Option::Some(x)`
+    /// or `Result::Ok(x)`, or break out of the function with `Option::None`
+    /// or `Result::Err(e)` itself.
+    pub fn branch(self) -> Result<ControlFlow<TiObj, TiObj>, TiError> {
+        match self {
+            TiObj::Enum(ref name, ref variant, _) if matches!((name.as_str(), variant.as_str()), (OPTION, "None") | (RESULT, "Err")) => {
+                Ok(ControlFlow::Break(self))
+            }
+            TiObj::Enum(name, variant, mut payload) if matches!((name.as_str(), variant.as_str()), (OPTION, "Some") | (RESULT, "Ok")) => {
+                Ok(ControlFlow::Continue(payload.pop().unwrap_or_else(TiObj::build_unit)))
+            }
+            _ => build_ti_error!(@err Type, "the operator `?` takes an `Option` or a `Result`, found `{}`.", self),
+        }
+    }
+
+    /// The value of a condition, which has to be a bool, as nothing is cast
+    /// to one.
+    pub fn into_cond(self) -> Result<bool, TiError> {
+        match self {
+            TiObj::Bool(x) => Ok(x),
+            cond => build_ti_error!(@err Type, "type `{:?}` cannot be hidden cast where there's a condition.", cond.as_type()),
+        }
+    }
+
+    /// What the `next` method of an iterator gave: the payload of
+    /// `Option::Some(x)`, or `None` once it gives `Option::None`.
+    pub fn into_next(self) -> Result<Option<TiObj>, TiError> {
+        match self {
+            TiObj::Enum(name, variant, mut payload) if name.as_str() == OPTION => match variant.as_str() {
+                "Some" => Ok(payload.pop()),
+                _ => Ok(None),
+            },
+            _ => build_ti_error!(@err Type, "the `next` method of an iterator must return an `Option`, found `{}`.", self),
+        }
+    }
+
+    pub fn field_mut(&mut self, name: &Rc<String>) -> Result<&mut TiObj, TiError> {
+        let ty = self.as_type();
+        match self {
+            TiObj::Struct(sname, fields) => match fields.iter_mut().find(|(fname, _)| fname == name) {
+                Some((_, value)) => Ok(value),
+                None => build_ti_error!(@err Reference, "no field `{}` on type `{}`.", name, sname),
+            },
+            _ => build_ti_error!(@err Type, "type `{:?}` has no fields, cannot access `{}`.", ty, name),
+        }
+    }
+}
+
+/// Where `index` points into something with `len` items, counting from
+/// the end when it's negative.
+fn position(index: &TiObj, len: usize) -> Result<usize, TiError> {
+    let x = match index {
+        TiObj::Num(x) if x.fract() != 0.0 => build_ti_error!(@err Runtime, "index `{}` is not a whole number.", x),
+        TiObj::Num(x) => *x,
+        _ => build_ti_error!(@err Type, "type `{:?}` cannot be used as an index.", index.as_type()),
+    };
+    let idx = if x < 0.0 { x + len as f64 } else { x };
+    if idx < 0.0 || idx >= len as f64 {
+        build_ti_error!(@err Runtime, "index `{}` is out of bounds for length {}.", x, len)
+    }
+    Ok(idx as usize)
+}
+
+/// The items of something with `len` items the range `range` takes, where
+/// negative bounds count from the end.
+fn slice(range: &TiRange, len: usize) -> Result<std::ops::Range<usize>, TiError> {
+    if range.step != 1.0 {
+        build_ti_error!(@err Runtime, "a slice counts by 1, found a step of {}.", range.step)
+    }
+    let bound = |x: f64| {
+        let x = if x < 0.0 { x + len as f64 } else { x };
+        (x.fract() == 0.0 && x >= 0.0).then_some(x as usize)
+    };
+    if let (Some(start), Some(end)) = (bound(range.start), bound(range.end)) {
+        let end = end + range.inclusive as usize;
+        if start <= end && end <= len {
+            return Ok(start..end);
+        }
+    }
+    build_ti_error!(@err Runtime, "slice `{}` is out of bounds for length {}.", TiObj::Range(*range), len)
+}
+
+/// Structural equality, which `==` goes by: lists, maps, structs and enums
+/// are equal when what they hold is. Numbers follow IEEE 754, so `NaN` is
+/// not equal to anything, itself included, and `0` is equal to `-0`. A
+/// function is only equal to itself, the same code with the same captured
+/// variables, and an iterator to nothing.
+impl PartialEq for TiObj {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TiObj::Map(x), TiObj::Map(y)) => x == y,
+            (TiObj::List(x), TiObj::List(y)) => x == y,
+            (TiObj::Num(x), TiObj::Num(y)) => x == y,
+            (TiObj::Str(x), TiObj::Str(y)) => x == y,
+            (TiObj::Bool(x), TiObj::Bool(y)) => x == y,
+            (TiObj::Fn(_, x, x_env), TiObj::Fn(_, y, y_env)) => Rc::ptr_eq(x, y) && x_env.same(y_env),
+            (TiObj::Builtin(x, _), TiObj::Builtin(y, _)) => x == y,
+            (TiObj::Proto(x, x_upvalues), TiObj::Proto(y, y_upvalues)) => {
+                Rc::ptr_eq(x, y) && Rc::ptr_eq(x_upvalues, y_upvalues)
+            }
+            (TiObj::Struct(x, x_fields), TiObj::Struct(y, y_fields)) => x == y && x_fields == y_fields,
+            (TiObj::Enum(x, x_variant, x_values), TiObj::Enum(y, y_variant, y_values)) => {
+                x == y && x_variant == y_variant && x_values == y_values
+            }
+            (TiObj::Range(x), TiObj::Range(y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
+/// `==` is an equivalence on the values that can be map keys, which leave
+/// out `NaN`, functions and iterators.
+impl Eq for TiObj {}
+
+/// Equal values hash alike, `0` and `-0` included.
+impl Hash for TiObj {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            TiObj::Map(map) => map.hash(state),
+            TiObj::List(items) => items.hash(state),
+            TiObj::Num(x) => hash_num(*x, state),
+            TiObj::Str(x) => x.hash(state),
+            TiObj::Bool(x) => x.hash(state),
+            TiObj::Fn(_, body, _) => Rc::as_ptr(body).hash(state),
+            TiObj::Builtin(name, _) => name.hash(state),
+            TiObj::Proto(proto, _) => Rc::as_ptr(proto).hash(state),
+            TiObj::Struct(name, fields) => {
+                name.hash(state);
+                fields.hash(state);
+            }
+            TiObj::Enum(name, variant, values) => {
+                name.hash(state);
+                variant.hash(state);
+                values.hash(state);
+            }
+            TiObj::Range(range) => {
+                hash_num(range.start, state);
+                hash_num(range.end, state);
+                hash_num(range.step, state);
+                range.inclusive.hash(state);
+            }
+            TiObj::Iter(_) => {}
+        }
+    }
+}
+
+fn hash_num<H: Hasher>(x: f64, state: &mut H) {
+    // `-0 == 0`, so both hash as `0`
+    let x = if x == 0.0 { 0.0 } else { x };
+    x.to_bits().hash(state);
+}
+
+/// Orders values the way `<`, `>`, `<=` and `>=` do, see `TiObj::compare`,
+/// and the values those can't order only against values equal to them.
+impl PartialOrd for TiObj {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other, "<")
+            .unwrap_or_else(|_| (self == other).then_some(Ordering::Equal))
+    }
+}
+
+impl fmt::Display for TiObj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiObj::Map(map) => {
+                write!(f, "{{")?;
+                for (idx, (k, v)) in map.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                write!(f, "}}")
+            }
+            TiObj::List(list) => {
+                write!(f, "[")?;
+                for (idx, v) in list.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            TiObj::Num(x) => write!(f, "{}", x),
+            TiObj::Str(x) => write!(f, "{}", x),
+            TiObj::Bool(x) => write!(f, "{}", x),
+            TiObj::Fn(fdef, _, _) => write!(f, "<fn {}>", fdef.name),
+            TiObj::Builtin(name, _) => write!(f, "<builtin fn {}>", name),
+            TiObj::Proto(proto, _) => write!(f, "<fn {}>", proto.def.name),
+            TiObj::Struct(name, fields) if fields.is_empty() => write!(f, "{} {{}}", name),
+            TiObj::Struct(name, fields) => {
+                write!(f, "{} {{", name)?;
+                for (idx, (k, v)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: {}", k, v)?;
+                }
+                write!(f, " }}")
+            }
+            TiObj::Range(range) => {
+                let op = if range.inclusive { "..=" } else { ".." };
+                write!(f, "{}{}{}", range.start, op, range.end)?;
+                if range.step != 1.0 {
+                    write!(f, " step {}", range.step)?;
+                }
+                Ok(())
+            }
+            TiObj::Iter(_) => write!(f, "<iterator>"),
+            TiObj::Enum(name, variant, values) => {
+                write!(f, "{}::{}", name, variant)?;
+                if !values.is_empty() {
+                    write!(f, "(")?;
+                    for (idx, v) in values.iter().enumerate() {
+                        if idx > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", v)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error::TiErrorKind;
+
+    fn num(x: f64) -> TiObj {
+        TiObj::Num(x)
+    }
+
+    fn kind(res: Result<TiObj, TiError>) -> TiErrorKind {
+        res.expect_err("expected an error").kind
+    }
+
+    #[test]
+    fn mod_keeps_the_sign_of_the_dividend() {
+        assert_eq!(num(7.0).r#mod(&num(3.0)).unwrap(), num(1.0));
+        assert_eq!(num(-7.0).r#mod(&num(3.0)).unwrap(), num(-1.0));
+        assert_eq!(num(7.0).r#mod(&num(-3.0)).unwrap(), num(1.0));
+        assert_eq!(kind(TiObj::Str("a".to_string()).r#mod(&num(1.0))), TiErrorKind::Type);
+    }
+
+    #[test]
+    fn pow() {
+        assert_eq!(num(2.0).pow(&num(10.0)).unwrap(), num(1024.0));
+        assert_eq!(num(2.0).pow(&num(-1.0)).unwrap(), num(0.5));
+        assert_eq!(kind(num(2.0).pow(&TiObj::Bool(true))), TiErrorKind::Type);
+    }
+
+    #[test]
+    fn bitwise_ops_work_on_integers() {
+        assert_eq!(num(7.0).bit_and(&num(3.0)).unwrap(), num(3.0));
+        assert_eq!(num(7.0).bit_or(&num(8.0)).unwrap(), num(15.0));
+        assert_eq!(num(7.0).bit_xor(&num(3.0)).unwrap(), num(4.0));
+        assert_eq!(num(1.0).shl(&num(5.0)).unwrap(), num(32.0));
+        assert_eq!(num(-7.0).shr(&num(2.0)).unwrap(), num(-2.0));
+        assert_eq!(kind(num(1.5).bit_and(&num(1.0))), TiErrorKind::Type);
+        assert_eq!(kind(TiObj::Bool(true).bit_or(&num(1.0))), TiErrorKind::Type);
+        assert_eq!(kind(num(1.0).shl(&num(64.0))), TiErrorKind::Runtime);
+        assert_eq!(kind(num(1.0).shr(&num(-1.0))), TiErrorKind::Runtime);
+    }
+
+    #[test]
+    fn prefix_ops() {
+        assert_eq!(num(4.0).neg().unwrap(), num(-4.0));
+        assert_eq!(TiObj::Bool(true).not().unwrap(), TiObj::Bool(false));
+        assert_eq!(num(7.0).not().unwrap(), num(-8.0));
+        assert_eq!(kind(TiObj::Bool(true).neg()), TiErrorKind::Type);
+        assert_eq!(kind(num(0.5).not()), TiErrorKind::Type);
+        assert_eq!(kind(TiObj::Str("a".to_string()).not()), TiErrorKind::Type);
+    }
+
+    #[test]
+    fn and_or_take_bools() {
+        assert_eq!(TiObj::Bool(true).and(&TiObj::Bool(false)).unwrap(), TiObj::Bool(false));
+        assert_eq!(TiObj::Bool(false).or(&TiObj::Bool(true)).unwrap(), TiObj::Bool(true));
+        assert_eq!(kind(TiObj::Bool(true).and(&num(1.0))), TiErrorKind::Type);
+        assert_eq!(kind(num(1.0).or(&TiObj::Bool(true))), TiErrorKind::Type);
+    }
+
+    #[test]
+    fn ordering() {
+        let list = |items: Vec<TiObj>| TiObj::List(items);
+        let str = |x: &str| TiObj::Str(x.to_string());
+        assert_eq!(num(1.0).les(&num(2.0)).unwrap(), TiObj::Bool(true));
+        assert_eq!(str("b").geq(&str("a")).unwrap(), TiObj::Bool(true));
+        assert_eq!(TiObj::Bool(false).les(&TiObj::Bool(true)).unwrap(), TiObj::Bool(true));
+        let (a, b) = (list(vec![num(1.0), num(2.0)]), list(vec![num(1.0), num(3.0)]));
+        assert_eq!(a.clone().les(&b).unwrap(), TiObj::Bool(true));
+        assert_eq!(a.clone().leq(&a).unwrap(), TiObj::Bool(true));
+        assert_eq!(list(vec![num(1.0)]).les(&a).unwrap(), TiObj::Bool(true));
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+        // `NaN` orders against nothing
+        assert_eq!(num(f64::NAN).les(&num(1.0)).unwrap(), TiObj::Bool(false));
+        assert_eq!(num(f64::NAN).geq(&num(1.0)).unwrap(), TiObj::Bool(false));
+        assert_eq!(list(vec![num(f64::NAN)]).partial_cmp(&list(vec![num(1.0)])), None);
+    }
+
+    #[test]
+    fn ordering_errors() {
+        let str = TiObj::Str("a".to_string());
+        let err = str.clone().les(&num(2.0)).unwrap_err();
+        assert_eq!(err.message, "mismatched type `Str` and `Num`.");
+        let mixed = TiObj::List(vec![num(1.0), str]);
+        let err = mixed.les(&TiObj::List(vec![num(1.0), num(2.0)])).unwrap_err();
+        assert_eq!(err.message, "mismatched type `Str` and `Num`.");
+        let map = TiObj::map(Vec::new()).unwrap();
+        let err = map.clone().geq(&map).unwrap_err();
+        assert_eq!(err.message, "the operator `>=` is not implemented for type `Map`.");
+        // equal values are still ordered as equal
+        assert_eq!(map.partial_cmp(&map), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn values_of_different_types_are_unequal() {
+        let str = |x: &str| TiObj::Str(x.to_string());
+        assert_eq!(num(1.0).eq(&str("a")).unwrap(), TiObj::Bool(false));
+        assert_eq!(num(1.0).neq(&str("a")).unwrap(), TiObj::Bool(true));
+        let list = TiObj::List(vec![num(1.0)]);
+        assert_eq!(list.clone().eq(&TiObj::List(vec![str("a")])).unwrap(), TiObj::Bool(false));
+        assert_eq!(list.clone().eq(&list).unwrap(), TiObj::Bool(true));
+        assert_eq!(TiObj::Bool(true).eq(&num(1.0)).unwrap(), TiObj::Bool(false));
+    }
+
+    #[test]
+    fn arithmetic_on_bools_names_the_bool_type() {
+        let err = TiObj::Bool(true).add(&num(1.0)).unwrap_err();
+        assert_eq!(err.message, "the operator `+` is not implemented for type `Bool`.");
+    }
 }
@@ -0,0 +1,273 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    build_ti_error,
+    error::error::{TiError, TiErrorKind},
+    frontend::parser::ast::{EnumDef, FnDef, StructDef, StructField, TraitDef, Type},
+    vm::builtins,
+};
+
+use super::object_type::TiObj;
+
+/// A function of a trait or an `impl` block, with its signature.
+pub type TiMethod = (FnDef, TiObj);
+
+/// The user defined types known to a running program, shared by every
+/// `TiVM` spawned for a function call.
+#[derive(Debug, Default)]
+pub struct TiTypeRegistry {
+    structs: HashMap<Rc<String>, Rc<StructDef>>,
+    enums: HashMap<Rc<String>, Rc<EnumDef>>,
+    /// each trait with the functions built from its default bodies
+    traits: HashMap<Rc<String>, (Rc<TraitDef>, Vec<TiMethod>)>,
+    /// functions of the `impl` blocks, by type and then by name
+    methods: HashMap<Rc<String>, HashMap<Rc<String>, TiObj>>,
+}
+
+impl TiTypeRegistry {
+    /// A registry knowing the builtin enums.
+    pub fn new() -> Self {
+        let mut types = Self::default();
+        for edef in builtins::enums() {
+            types.define_enum(edef);
+        }
+        types
+    }
+
+    /// Defining a struct again replaces the previous definition, so the REPL
+    /// can redefine it.
+    pub fn define_struct(&mut self, sdef: StructDef) {
+        self.structs.insert(sdef.name.clone(), Rc::new(sdef));
+    }
+
+    pub fn get_struct(&self, name: &Rc<String>) -> Option<Rc<StructDef>> {
+        self.structs.get(name).cloned()
+    }
+
+    pub fn define_enum(&mut self, edef: EnumDef) {
+        self.enums.insert(edef.name.clone(), Rc::new(edef));
+    }
+
+    pub fn get_enum(&self, name: &Rc<String>) -> Option<Rc<EnumDef>> {
+        self.enums.get(name).cloned()
+    }
+
+    pub fn enums(&self) -> impl Iterator<Item = &EnumDef> {
+        self.enums.values().map(|edef| &**edef)
+    }
+
+    /// `defaults` are the functions built from the default bodies of `tdef`.
+    pub fn define_trait(&mut self, tdef: TraitDef, defaults: Vec<TiMethod>) {
+        self.traits
+            .insert(tdef.name.clone(), (Rc::new(tdef), defaults));
+    }
+
+    /// Registers the functions of `impl [tname for ]sname`. Implementing a
+    /// trait takes its defaults for the methods not given, and fails if a
+    /// method without one is missing.
+    pub fn implement(
+        &mut self,
+        sname: &Rc<String>,
+        tname: Option<&Rc<String>>,
+        mut fns: Vec<TiMethod>,
+    ) -> Result<(), TiError> {
+        if !self.structs.contains_key(sname) && !self.enums.contains_key(sname) {
+            build_ti_error!(@err Reference, "cannot implement for `{}`, no struct or enum of this name is defined.", sname)
+        }
+        if let Some(tname) = tname {
+            let (tdef, defaults) = match self.traits.get(tname) {
+                Some(tdef) => tdef.clone(),
+                None => build_ti_error!(@err Reference, "trait `{}` is not defined.", tname),
+            };
+            if let Some((fdef, _)) = fns
+                .iter()
+                .find(|(fdef, _)| tdef.fields.iter().all(|field| field.body.name != fdef.name))
+            {
+                build_ti_error!(@err Reference, "method `{}` is not a member of trait `{}`.", fdef.name, tname)
+            }
+            let mut missing = Vec::new();
+            for field in &tdef.fields {
+                match fns.iter().find(|(fdef, _)| fdef.name == field.body.name) {
+                    Some((fdef, _)) if fdef.arguments.len() != field.body.arguments.len() => {
+                        build_ti_error!(@err Type, "method `{}` takes {} arguments but the declaration in trait `{}` takes {}.", fdef.name, fdef.arguments.len(), tname, field.body.arguments.len())
+                    }
+                    Some(_) => {}
+                    None => match defaults
+                        .iter()
+                        .find(|(fdef, _)| fdef.name == field.body.name)
+                    {
+                        Some(default) => fns.push(default.clone()),
+                        None => missing.push(format!("`{}`", field.body.name)),
+                    },
+                }
+            }
+            if !missing.is_empty() {
+                return Err(TiError::new(
+                    TiErrorKind::Type,
+                    format!(
+                        "not all trait items implemented, missing: {}.",
+                        missing.join(", ")
+                    ),
+                )
+                .with_help(format!(
+                    "implement the missing methods in `impl {} for {}`",
+                    tname, sname
+                )));
+            }
+        }
+        let methods = self.methods.entry(sname.clone()).or_default();
+        for (fdef, f) in fns {
+            methods.insert(fdef.name, f);
+        }
+        Ok(())
+    }
+
+    /// The function `name` of an `impl` of type `sname`, which is a method
+    /// if its first parameter is `self`.
+    pub fn associated(&self, sname: &Rc<String>, name: &Rc<String>) -> Option<TiObj> {
+        self.methods.get(sname)?.get(name).cloned()
+    }
+
+    /// The method `name` of the type of `receiver`, if there's one.
+    pub fn method(&self, receiver: &TiObj, name: &Rc<String>) -> Option<TiObj> {
+        match receiver {
+            TiObj::Struct(sname, _) | TiObj::Enum(sname, _, _) => self.associated(sname, name),
+            _ => None,
+        }
+    }
+
+    /// The method `name` taking `self` of `receiver`, through which a `for`
+    /// loop walks a user type: `iter` or `next`.
+    pub fn protocol(&self, receiver: &TiObj, name: &str) -> Option<TiObj> {
+        self.method(receiver, &Rc::new(name.to_string()))
+            .filter(TiObj::is_method)
+    }
+
+    /// Builds variant `name::variant` from its payload `values`.
+    pub fn construct(
+        &self,
+        name: &Rc<String>,
+        variant: &Rc<String>,
+        values: Vec<TiObj>,
+    ) -> Result<TiObj, TiError> {
+        let edef = match self.get_enum(name) {
+            Some(edef) => edef,
+            None => build_ti_error!(@err Reference, "enum `{}` is not defined.", name),
+        };
+        let field = match edef.fields.iter().find(|field| field.name == *variant) {
+            Some(field) => field,
+            None => {
+                build_ti_error!(@err Reference, "no variant `{}` in enum `{}`.", variant, name)
+            }
+        };
+        if field.values.len() != values.len() {
+            build_ti_error!(@err Type, "variant `{}::{}` takes {} values but {} were given.", name, variant, field.values.len(), values.len())
+        }
+        for (idx, (ty, value)) in field.values.iter().zip(&values).enumerate() {
+            if !type_accepts(ty, value) {
+                build_ti_error!(@err Type, "mismatched types: value #{} of `{}::{}` expects `{}`, found `{:?}`.", idx + 1, name, variant, ty, value.as_type())
+            }
+        }
+        Ok(TiObj::Enum(name.clone(), variant.clone(), values))
+    }
+
+    /// Builds an instance of struct `name`, checking that `fields` gives
+    /// every declared field exactly once with a value of its declared type.
+    pub fn instantiate(
+        &self,
+        name: &Rc<String>,
+        mut fields: Vec<(Rc<String>, TiObj)>,
+    ) -> Result<TiObj, TiError> {
+        let sdef = match self.get_struct(name) {
+            Some(sdef) => sdef,
+            None => build_ti_error!(@err Reference, "struct `{}` is not defined.", name),
+        };
+        if let Some((fname, _)) = fields
+            .iter()
+            .find(|(fname, _)| sdef.fields.iter().all(|field| field.name != *fname))
+        {
+            build_ti_error!(@err Reference, "struct `{}` has no field named `{}`.", name, fname)
+        }
+        let mut values = Vec::with_capacity(sdef.fields.len());
+        for field in &sdef.fields {
+            let value = match fields.iter().position(|(fname, _)| *fname == field.name) {
+                Some(idx) => fields.swap_remove(idx).1,
+                None => {
+                    build_ti_error!(@err Type, "missing field `{}` in initializer of `{}`.", field.name, name)
+                }
+            };
+            Self::check_field(&sdef, field, &value)?;
+            values.push((field.name.clone(), value));
+        }
+        Ok(TiObj::Struct(name.clone(), values))
+    }
+
+    /// Checks that `value` may be stored in field `fname` of struct `sname`.
+    pub fn check_assign(
+        &self,
+        sname: &Rc<String>,
+        fname: &Rc<String>,
+        value: &TiObj,
+    ) -> Result<(), TiError> {
+        if let Some(sdef) = self.get_struct(sname) {
+            if let Some(field) = sdef.fields.iter().find(|field| field.name == *fname) {
+                Self::check_field(&sdef, field, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_field(sdef: &StructDef, field: &StructField, value: &TiObj) -> Result<(), TiError> {
+        if !type_accepts(&field.ty, value) {
+            build_ti_error!(@err Type, "mismatched types: field `{}` of `{}` expects `{}`, found `{:?}`.", field.name, sdef.name, field.ty, value.as_type())
+        }
+        Ok(())
+    }
+}
+
+/// Whether a value of `value`'s runtime type fits the declared type `ty`;
+/// all number types are `Num` at runtime.
+pub fn type_accepts(ty: &Type, value: &TiObj) -> bool {
+    match (ty, value) {
+        (Type::Unknown, _) => true,
+        (Type::Never, _) => false,
+        (
+            Type::F32
+            | Type::F64
+            | Type::I8
+            | Type::I16
+            | Type::I32
+            | Type::I64
+            | Type::I128
+            | Type::U8
+            | Type::U16
+            | Type::U32
+            | Type::U64
+            | Type::U128,
+            TiObj::Num(_),
+        ) => true,
+        (Type::Bool, TiObj::Bool(_)) => true,
+        (Type::Ref(ty), _) | (Type::Anna(ty, _), _) => type_accepts(ty, value),
+        (Type::Array(ty, len), TiObj::List(list)) => {
+            (*len == 0 || *len == list.len()) && list.iter().all(|value| type_accepts(ty, value))
+        }
+        (Type::Unit(tys), TiObj::List(list)) => {
+            tys.len() == list.len()
+                && tys
+                    .iter()
+                    .zip(list)
+                    .all(|(ty, value)| type_accepts(ty, value))
+        }
+        (Type::Costume(name), _) => match (name.as_str(), value) {
+            ("str" | "String", TiObj::Str(_)) => true,
+            ("list", TiObj::List(_)) => true,
+            ("map", TiObj::Map(_)) => true,
+            ("range", TiObj::Range(_)) => true,
+            ("fn", TiObj::Fn(_, _, _) | TiObj::Builtin(_, _) | TiObj::Proto(_, _)) => true,
+            (_, TiObj::Struct(sname, _)) => sname == name,
+            (_, TiObj::Enum(ename, _, _)) => ename == name,
+            _ => false,
+        },
+        _ => false,
+    }
+}
@@ -1,31 +1,93 @@
-use crate::backend::bytecode::{bytecode::TiByteCode};
+use std::{cell::RefCell, fmt, ops::ControlFlow, rc::Rc};
 
-use super::{types::{object_type::TiObj}, environment::environment::TiEnvironment};
+use crate::{
+  backend::bytecode::{
+    bytecode::{ti_bytecode, TiCapture, TiConst, TiFnProto},
+    verify::verify,
+  },
+  build_ti_error,
+  error::error::TiError,
+};
 
-pub struct TiVM<'a> {
-  bytecode: TiByteCode<'a>,
-  stack: Vec<TiObj>,
-  environment: TiEnvironment,
+use super::{
+  builtins,
+  environment::environment::TiEnvironment,
+  types::{object_type::{TiBinaryOp, TiIter, TiObj}, registry::{TiMethod, TiTypeRegistry}},
+};
+
+/// How deep calls may nest in this vm before a call raises an error instead
+/// of exhausting the memory, and in `vm_ast` when `ti` runs it on a stack
+/// of `STACK_SIZE`.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// A variable captured by a function. It stays open, pointing at its slot
+/// on the stack, while the block declaring it runs, and holds the value
+/// itself once the block is done.
+pub enum TiUpvalue {
+  Open(usize),
+  Closed(TiObj),
 }
 
-impl<'a> TiVM<'a> {
-  pub fn new(bytecode: TiByteCode<'a>) -> Self {
-    Self { 
-      bytecode, 
-      stack: vec! [], 
-      environment: TiEnvironment::new(),
+/// The upvalues of a function, shared with the functions that capture the
+/// same variables.
+pub type TiUpvalues = Rc<[Rc<RefCell<TiUpvalue>>]>;
+
+impl fmt::Debug for TiUpvalue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TiUpvalue::Open(slot) => write!(f, "Open({})", slot),
+      // a function may capture itself
+      TiUpvalue::Closed(_) => write!(f, "Closed(..)"),
     }
   }
-  pub fn with_environment(bytecode: TiByteCode<'a>, environment: TiEnvironment) -> Self {
-    Self { 
-      bytecode, 
-      stack: vec! [], 
+}
+
+/// A running function: its locals start at `base` on the stack, right above
+/// the function itself.
+struct TiFrame {
+  proto: Rc<TiFnProto>,
+  upvalues: TiUpvalues,
+  /// the return address, the offset of the next instruction
+  ip: usize,
+  base: usize,
+  /// set for `CALL_METHOD`, which also gets back the final `self`
+  method: bool,
+}
+
+pub struct TiVM {
+  stack: Vec<TiObj>,
+  frames: Vec<TiFrame>,
+  /// the upvalues still pointing at the stack
+  open_upvalues: Vec<Rc<RefCell<TiUpvalue>>>,
+  /// the session variables, defined by the top level of the main chunk
+  pub environment: TiEnvironment,
+  pub types: TiTypeRegistry,
+}
+
+impl Default for TiVM {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TiVM {
+  pub fn new() -> Self {
+    let mut environment = TiEnvironment::new();
+    builtins::register(&mut environment);
+    Self::with_environment(environment)
+  }
+  pub fn with_environment(environment: TiEnvironment) -> Self {
+    Self {
+      stack: vec! [],
+      frames: vec! [],
+      open_upvalues: vec! [],
       environment,
+      types: TiTypeRegistry::new(),
     }
   }
 }
 
-impl TiVM<'_> {
+impl TiVM {
   pub fn push(&mut self, obj: TiObj) -> &Self {
     self.stack.push(obj);
     self
@@ -33,10 +95,581 @@ impl TiVM<'_> {
   pub fn pop(&mut self) -> TiObj {
     self.stack.pop().unwrap()
   }
+  fn peek(&self) -> &TiObj {
+    self.stack.last().unwrap()
+  }
+  fn frame(&self) -> &TiFrame {
+    self.frames.last().unwrap()
+  }
+  fn fetch(&mut self) -> u16 {
+    let frame = self.frames.last_mut().unwrap();
+    let res = frame.proto.bytecode.code()[frame.ip];
+    frame.ip += 1;
+    res
+  }
+  fn constant(&self, idx: u16) -> &TiConst {
+    &self.frame().proto.bytecode.consts()[idx as usize]
+  }
+  fn name(&self, idx: u16) -> Rc<String> {
+    match self.constant(idx) {
+      TiConst::Str(name) => Rc::new(name.clone()),
+      _ => unreachable!("operand {} is not a name", idx),
+    }
+  }
+  fn pop_n(&mut self, n: usize) -> Vec<TiObj> {
+    self.stack.split_off(self.stack.len() - n)
+  }
+  fn binary(&mut self, op: TiBinaryOp) -> Result<(), TiError> {
+    let rhs = self.pop();
+    let lhs = self.pop();
+    self.push(op(lhs, &rhs)?);
+    Ok(())
+  }
+  fn cond(&mut self) -> Result<bool, TiError> {
+    self.pop().into_cond()
+  }
+  fn jump(&mut self, target: u16) {
+    self.frames.last_mut().unwrap().ip = target as usize;
+  }
+
+  /// The upvalue of the stack slot `slot`, shared by every function
+  /// capturing it while it is open.
+  fn capture(&mut self, slot: usize) -> Rc<RefCell<TiUpvalue>> {
+    let found = self.open_upvalues.iter().find(|upvalue| matches!(*upvalue.borrow(), TiUpvalue::Open(open) if open == slot));
+    if let Some(upvalue) = found {
+      return upvalue.clone();
+    }
+    let upvalue = Rc::new(RefCell::new(TiUpvalue::Open(slot)));
+    self.open_upvalues.push(upvalue.clone());
+    upvalue
+  }
+
+  /// Moves the values of the stack slots from `from` up into the upvalues
+  /// pointing at them, before the slots are reused or dropped.
+  fn close_upvalues(&mut self, from: usize) {
+    let stack = &self.stack;
+    self.open_upvalues.retain(|upvalue| {
+      let mut upvalue = upvalue.borrow_mut();
+      match *upvalue {
+        TiUpvalue::Open(slot) if slot >= from => {
+          *upvalue = TiUpvalue::Closed(stack[slot].clone());
+          false
+        }
+        _ => true,
+      }
+    });
+  }
 
-  pub fn execute(self) -> TiObj {
-    let mut res = TiObj::List(Vec::new());
-    while !self.bytecode.has() {}
+  /// Calls the function below its `argc` arguments on the stack. A function
+  /// compiled to bytecode gets a new frame, a builtin runs right away.
+  fn call(&mut self, argc: usize, method: bool) -> Result<(), TiError> {
+    let base = self.stack.len() - argc;
+    match &self.stack[base - 1] {
+      TiObj::Proto(proto, upvalues) => {
+        if proto.def.arguments.len() != argc {
+          build_ti_error!(@err Type, "function `{}` takes {} arguments but {} were given.", proto.def.name, proto.def.arguments.len(), argc)
+        }
+        // the frame of the entry function or the main chunk isn't a nested call
+        if self.frames.len() > MAX_CALL_DEPTH {
+          build_ti_error!(@err Runtime, "stack overflow, calls are nested more than {} deep.", MAX_CALL_DEPTH)
+        }
+        let proto = proto.clone();
+        let upvalues = upvalues.clone();
+        self.stack.resize(base + proto.locals.max(argc), TiObj::build_unit());
+        self.frames.push(TiFrame { proto, upvalues, ip: 0, base, method });
+      }
+      TiObj::Builtin(_, f) => {
+        let f = *f;
+        let args = self.pop_n(argc);
+        self.pop();
+        self.push(f(args)?);
+      }
+      caller => {
+        build_ti_error!(@err Type, "type `{:?}` is not callable.", caller.as_type())
+      }
+    }
+    Ok(())
+  }
+
+  /// Puts `caller` below the topmost `argc` values and calls it.
+  fn call_with(&mut self, caller: TiObj, argc: usize, method: bool) -> Result<(), TiError> {
+    self.stack.insert(self.stack.len() - argc, caller);
+    self.call(argc, method)
+  }
+
+  fn call_method(&mut self, name: Rc<String>, argc: usize) -> Result<(), TiError> {
+    let receiver = &self.stack[self.stack.len() - argc - 1];
+    match self.types.method(receiver, &name) {
+      Some(method) if method.is_method() => self.call_with(method, argc + 1, true),
+      Some(_) => {
+        build_ti_error!(@err Type, "`{}` is an associated function, not a method, call it as `{}::{}(..)`.", name, receiver.type_name(), name)
+      }
+      None => {
+        let caller = match receiver.field(&name) {
+          Ok(caller) => caller.clone(),
+          Err(_) => {
+            build_ti_error!(@err Reference, "no method named `{}` found for type `{}`.", name, receiver.type_name())
+          }
+        };
+        // the receiver stays below the result as the unchanged `self`
+        self.call_with(caller, argc, false)
+      }
+    }
+  }
+
+  /// Calls the method `method` of `receiver` and runs it to the end,
+  /// returning its result and the final `self`.
+  fn call_now(&mut self, method: TiObj, receiver: TiObj) -> Result<(TiObj, TiObj), TiError> {
+    let depth = self.frames.len();
+    self.push(method);
+    self.push(receiver);
+    self.call(1, true)?;
+    self.run(depth)?;
+    let res = self.pop();
+    let receiver = self.pop();
+    Ok((res, receiver))
+  }
+
+  /// Starts a `for` loop over `value`. A struct or an enum with an `iter`
+  /// method is walked through what that returns.
+  fn iter(&mut self, value: TiObj) -> Result<TiIter, TiError> {
+    match self.types.protocol(&value, "iter") {
+      Some(method) => self.call_now(method, value)?.0.iter(),
+      None => value.iter(),
+    }
+  }
+
+  /// The next value of the `for` loop walking `iter`, `None` once it's
+  /// done; a user type gives `x` while its `next` method returns `Some(x)`,
+  /// and is done once it returns `None`.
+  fn next(&mut self, iter: &mut TiIter) -> Result<Option<TiObj>, TiError> {
+    let obj = match iter {
+      TiIter::Method(obj) => obj,
+      iter => return Ok(iter.next()),
+    };
+    let method = match self.types.protocol(obj, "next") {
+      Some(method) => method,
+      None => build_ti_error!(@err Type, "type `{}` cannot be iterated, it has no `iter` or `next` method.", obj.type_name()),
+    };
+    let (res, receiver) = self.call_now(method, obj.clone())?;
+    *obj = receiver;
+    res.into_next()
+  }
+
+  /// Runs instructions until the frame at `depth` returns or the main chunk
+  /// exits, leaving the result on the stack.
+  fn run(&mut self, depth: usize) -> Result<(), TiError> {
+    loop {
+      let op = self.fetch();
+      match op {
+        ti_bytecode::LOAD_CONST => {
+          let idx = self.fetch();
+          let obj = match self.constant(idx) {
+            TiConst::Num(x) => TiObj::Num(*x),
+            TiConst::Str(x) => TiObj::Str(x.clone()),
+            TiConst::Bool(x) => TiObj::Bool(*x),
+            _ => unreachable!("constant {} is not a value", idx),
+          };
+          self.push(obj);
+        }
+        ti_bytecode::LOAD_SESSION => {
+          let name = self.fetch();
+          let name = self.name(name);
+          match self.environment.get(&name) {
+            Some(obj) => self.push(obj),
+            None => build_ti_error!(@err Reference, "name `{}` is not declared or has already moved.", name),
+          };
+        }
+        ti_bytecode::BUILD_FUNC => {
+          let idx = self.fetch();
+          let proto = match self.constant(idx) {
+            TiConst::Fn(proto) => proto.clone(),
+            _ => unreachable!("constant {} is not a function", idx),
+          };
+          let base = self.frame().base;
+          let upvalues = proto.upvalues.iter().map(|capture| match capture {
+            TiCapture::Local(slot) => self.capture(base + *slot as usize),
+            TiCapture::Upvalue(idx) => self.frame().upvalues[*idx as usize].clone(),
+          }).collect();
+          self.push(TiObj::Proto(proto, upvalues));
+        }
+        ti_bytecode::BIN_PRINT => {
+          let obj = self.pop();
+          println!("{}", obj);
+        }
+        ti_bytecode::ADD => self.binary(TiObj::add)?,
+        ti_bytecode::SUB => self.binary(TiObj::sub)?,
+        ti_bytecode::MUL => self.binary(TiObj::mul)?,
+        ti_bytecode::DIV => self.binary(TiObj::div)?,
+        ti_bytecode::MOD => self.binary(TiObj::r#mod)?,
+        ti_bytecode::POW => self.binary(TiObj::pow)?,
+        ti_bytecode::BIT_AND => self.binary(TiObj::bit_and)?,
+        ti_bytecode::BIT_OR => self.binary(TiObj::bit_or)?,
+        ti_bytecode::BIT_XOR => self.binary(TiObj::bit_xor)?,
+        ti_bytecode::SHL => self.binary(TiObj::shl)?,
+        ti_bytecode::SHR => self.binary(TiObj::shr)?,
+        ti_bytecode::AND => self.binary(TiObj::and)?,
+        ti_bytecode::OR => self.binary(TiObj::or)?,
+        ti_bytecode::LES => self.binary(TiObj::les)?,
+        ti_bytecode::GRT => self.binary(TiObj::grt)?,
+        ti_bytecode::LEQ => self.binary(TiObj::leq)?,
+        ti_bytecode::GEQ => self.binary(TiObj::geq)?,
+        ti_bytecode::EQ => self.binary(TiObj::eq)?,
+        ti_bytecode::NEQ => self.binary(TiObj::neq)?,
+        ti_bytecode::NOT => {
+          let obj = self.pop();
+          self.push(obj.not()?);
+        }
+        ti_bytecode::NEG => {
+          let obj = self.pop();
+          self.push(obj.neg()?);
+        }
+        ti_bytecode::JMP => {
+          let target = self.fetch();
+          self.jump(target);
+        }
+        ti_bytecode::JNZ => {
+          let target = self.fetch();
+          if self.cond()? {
+            self.jump(target);
+          }
+        }
+        ti_bytecode::JZ => {
+          let target = self.fetch();
+          if !self.cond()? {
+            self.jump(target);
+          }
+        }
+        ti_bytecode::POP => {
+          self.pop();
+        }
+        ti_bytecode::LOAD_LOCAL => {
+          let slot = self.fetch() as usize + self.frame().base;
+          let obj = self.stack[slot].clone();
+          self.push(obj);
+        }
+        ti_bytecode::STORE_LOCAL => {
+          let slot = self.fetch() as usize + self.frame().base;
+          self.stack[slot] = self.peek().clone();
+        }
+        ti_bytecode::LOAD_UPVALUE => {
+          let idx = self.fetch() as usize;
+          let upvalue = self.frame().upvalues[idx].clone();
+          let obj = match &*upvalue.borrow() {
+            TiUpvalue::Open(slot) => self.stack[*slot].clone(),
+            TiUpvalue::Closed(obj) => obj.clone(),
+          };
+          self.push(obj);
+        }
+        ti_bytecode::STORE_UPVALUE => {
+          let idx = self.fetch() as usize;
+          let upvalue = self.frame().upvalues[idx].clone();
+          let obj = self.peek().clone();
+          match &mut *upvalue.borrow_mut() {
+            TiUpvalue::Open(slot) => self.stack[*slot] = obj,
+            TiUpvalue::Closed(value) => *value = obj,
+          };
+        }
+        ti_bytecode::CLOSE_UPVALUES => {
+          let slot = self.fetch() as usize + self.frame().base;
+          self.close_upvalues(slot);
+        }
+        ti_bytecode::STORE_SESSION => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let obj = self.peek().clone();
+          self.environment.set_v(name, obj);
+        }
+        ti_bytecode::DEFINE_SESSION => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let obj = self.pop();
+          self.environment.set(name, obj);
+        }
+        ti_bytecode::CALL => {
+          let argc = self.fetch();
+          self.call(argc as usize, false)?;
+        }
+        ti_bytecode::RETURN => {
+          let res = self.pop();
+          let frame = self.frames.pop().unwrap();
+          self.close_upvalues(frame.base);
+          let receiver = match frame.method {
+            true => Some(self.stack[frame.base].clone()),
+            false => None,
+          };
+          self.stack.truncate(frame.base - 1);
+          if let Some(receiver) = receiver {
+            self.push(receiver);
+          }
+          self.push(res);
+          if self.frames.len() == depth {
+            return Ok(());
+          }
+        }
+        ti_bytecode::BUILD_LIST => {
+          let len = self.fetch();
+          let list = self.pop_n(len as usize);
+          self.push(TiObj::List(list));
+        }
+        ti_bytecode::BUILD_MAP => {
+          let len = self.fetch() as usize;
+          let mut values = self.pop_n(len * 2).into_iter();
+          let mut entries = Vec::with_capacity(len);
+          while let (Some(key), Some(value)) = (values.next(), values.next()) {
+            entries.push((key, value));
+          }
+          self.push(TiObj::map(entries)?);
+        }
+        ti_bytecode::BUILD_STRUCT => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let len = self.fetch() as usize;
+          let mut values = self.pop_n(len * 2).into_iter();
+          let mut fields = Vec::with_capacity(len);
+          while let (Some(TiObj::Str(fname)), Some(value)) = (values.next(), values.next()) {
+            fields.push((Rc::new(fname), value));
+          }
+          let obj = self.types.instantiate(&name, fields)?;
+          self.push(obj);
+        }
+        ti_bytecode::GET_FIELD => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let obj = self.pop();
+          self.push(obj.field(&name)?.clone());
+        }
+        ti_bytecode::SET_FIELD => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let value = self.pop();
+          let mut obj = self.pop();
+          if let TiObj::Struct(sname, _) = &obj {
+            self.types.check_assign(sname, &name, &value)?;
+          }
+          *obj.field_mut(&name)? = value;
+          self.push(obj);
+        }
+        ti_bytecode::LOAD_PATH => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let item = self.fetch();
+          let item = self.name(item);
+          let obj = if self.types.get_enum(&name).is_some() {
+            self.types.construct(&name, &item, Vec::new())?
+          } else if let Some(obj) = self.types.associated(&name, &item) {
+            obj
+          } else {
+            build_ti_error!(@err Reference, "no variant or associated item named `{}` found for `{}`.", item, name)
+          };
+          self.push(obj);
+        }
+        ti_bytecode::CALL_PATH => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let item = self.fetch();
+          let item = self.name(item);
+          let argc = self.fetch() as usize;
+          let is_variant = self.types.get_enum(&name)
+            .is_some_and(|edef| edef.fields.iter().any(|field| field.name == item));
+          if is_variant {
+            let args = self.pop_n(argc);
+            let obj = self.types.construct(&name, &item, args)?;
+            self.push(obj);
+          } else if self.types.get_enum(&name).is_some() {
+            // reports the unknown variant
+            self.types.construct(&name, &item, Vec::new())?;
+          } else if let Some(caller) = self.types.associated(&name, &item) {
+            self.call_with(caller, argc, false)?;
+          } else {
+            build_ti_error!(@err Reference, "no variant or associated item named `{}` found for `{}`.", item, name)
+          }
+        }
+        ti_bytecode::CALL_METHOD => {
+          let name = self.fetch();
+          let name = self.name(name);
+          let argc = self.fetch();
+          self.call_method(name, argc as usize)?;
+        }
+        ti_bytecode::IS_VARIANT => {
+          let name = self.fetch();
+          let variant = self.fetch();
+          let res = match self.pop() {
+            TiObj::Enum(ename, evariant, _) => {
+              self.name(name) == ename && self.name(variant) == evariant
+            }
+            _ => false,
+          };
+          self.push(TiObj::Bool(res));
+        }
+        ti_bytecode::GET_PAYLOAD => {
+          let idx = self.fetch() as usize;
+          match self.pop() {
+            TiObj::Enum(_, _, mut values) if idx < values.len() => {
+              self.push(values.swap_remove(idx));
+            }
+            obj => build_ti_error!(@err Runtime, "`{}` has no value #{}.", obj, idx),
+          };
+        }
+        ti_bytecode::IS_EQ => {
+          let rhs = self.pop();
+          let lhs = self.pop();
+          let res = match (&lhs, &rhs) {
+            (TiObj::Num(x), TiObj::Num(y)) => x == y,
+            (TiObj::Str(x), TiObj::Str(y)) => x == y,
+            (TiObj::Bool(x), TiObj::Bool(y)) => x == y,
+            _ => false,
+          };
+          self.push(TiObj::Bool(res));
+        }
+        ti_bytecode::MATCH_FAIL => {
+          let value = self.pop();
+          build_ti_error!(@err Runtime, "no arm of the match accepts the value `{}`.", value)
+        }
+        ti_bytecode::DEFINE_TYPE => {
+          let idx = self.fetch();
+          match self.constant(idx).clone() {
+            TiConst::Struct(sdef) => self.types.define_struct(sdef),
+            TiConst::Enum(edef) => self.types.define_enum(edef),
+            _ => unreachable!("constant {} is not a type", idx),
+          }
+        }
+        ti_bytecode::DEFINE_TRAIT => {
+          let idx = self.fetch();
+          let len = self.fetch();
+          let tdef = match self.constant(idx) {
+            TiConst::Trait(tdef) => tdef.clone(),
+            _ => unreachable!("constant {} is not a trait", idx),
+          };
+          let defaults = self.pop_n(len as usize).into_iter().map(method).collect::<Result<_, _>>()?;
+          self.types.define_trait(tdef, defaults);
+        }
+        ti_bytecode::IMPL => {
+          let sname = self.fetch();
+          let sname = self.name(sname);
+          let tname = match self.fetch() {
+            ti_bytecode::NO_NAME => None,
+            tname => Some(self.name(tname)),
+          };
+          let len = self.fetch();
+          let fns = self.pop_n(len as usize).into_iter().map(method).collect::<Result<_, _>>()?;
+          self.types.implement(&sname, tname.as_ref(), fns)?;
+        }
+        ti_bytecode::BUILD_RANGE => {
+          let inclusive = self.fetch() != 0;
+          let step = self.pop();
+          let end = self.pop();
+          let start = self.pop();
+          self.push(TiObj::range(start, end, step, inclusive)?);
+        }
+        ti_bytecode::ITER => {
+          let value = self.pop();
+          let iter = self.iter(value)?;
+          self.push(TiObj::Iter(Box::new(iter)));
+        }
+        ti_bytecode::ITER_NEXT => {
+          let slot = self.fetch() as usize + self.frame().base;
+          let mut iter = match std::mem::replace(&mut self.stack[slot], TiObj::build_unit()) {
+            TiObj::Iter(iter) => iter,
+            obj => build_ti_error!(@err Runtime, "`{}` is not an iterator.", obj),
+          };
+          let res = self.next(&mut iter);
+          self.stack[slot] = TiObj::Iter(iter);
+          let next = res?;
+          let more = next.is_some();
+          self.push(next.unwrap_or_else(TiObj::build_unit));
+          self.push(TiObj::Bool(more));
+        }
+        ti_bytecode::GET_INDEX => {
+          let index = self.pop();
+          let obj = self.pop();
+          self.push(obj.index(&index)?);
+        }
+        ti_bytecode::SET_INDEX => {
+          let value = self.pop();
+          let index = self.pop();
+          let mut obj = self.pop();
+          obj.set_index(index, value)?;
+          self.push(obj);
+        }
+        ti_bytecode::TRY => {
+          let (value, go_on) = match self.pop().branch()? {
+            ControlFlow::Continue(value) => (value, true),
+            ControlFlow::Break(value) => (value, false),
+          };
+          self.push(value);
+          self.push(TiObj::Bool(go_on));
+        }
+        ti_bytecode::EXIT => return Ok(()),
+        _ => build_ti_error!(@err Runtime, "unknown opcode {}.", op),
+      }
+    }
+  }
+
+  /// Runs until the frame at `depth` is done. On error the stack is unwound
+  /// to below that frame and the error gets the span of the statement that
+  /// failed, unless it has one.
+  fn run_frame(&mut self, depth: usize, height: usize) -> Result<TiObj, TiError> {
+    match self.run(depth) {
+      Ok(()) => Ok(self.pop()),
+      Err(mut err) => {
+        if err.at.is_none() {
+          let frame = self.frame();
+          err.at = frame.proto.bytecode.span_at(frame.ip.saturating_sub(1));
+        }
+        self.frames.truncate(depth);
+        self.close_upvalues(height);
+        self.stack.truncate(height);
+        Err(err)
+      }
+    }
+  }
+
+  /// Verifies and runs a main chunk, whose top level `let`s and `fn`s
+  /// define session variables, and returns the value it leaves on the stack.
+  pub fn execute(&mut self, main: Rc<TiFnProto>) -> Result<TiObj, TiError> {
+    verify(&main)?;
+    let depth = self.frames.len();
+    let height = self.stack.len();
+    self.stack.resize(height + main.locals, TiObj::build_unit());
+    self.frames.push(TiFrame { proto: main, upvalues: Rc::new([]), ip: 0, base: height, method: false });
+    let res = self.run_frame(depth, height);
+    if res.is_ok() {
+      self.frames.truncate(depth);
+      self.close_upvalues(height);
+      self.stack.truncate(height);
+    }
     res
   }
-}
\ No newline at end of file
+
+  pub fn run_fn(&mut self, entry: &str, args: Vec<TiObj>) -> Result<TiObj, TiError> {
+    let caller = match self.environment.get(&Rc::new(entry.to_string())) {
+      Some(caller @ TiObj::Proto(_, _)) => caller,
+      _ => build_ti_error!(@err Runtime, "no entry function `{}`.", entry),
+    };
+    if let TiObj::Proto(proto, _) = &caller {
+      if proto.def.arguments.len() != args.len() {
+        build_ti_error!(@err Runtime, "entry function `{}` takes {} arguments but {} were given.", entry, proto.def.arguments.len(), args.len())
+      }
+    }
+    let depth = self.frames.len();
+    let height = self.stack.len();
+    let argc = args.len();
+    self.push(caller);
+    self.stack.extend(args);
+    if let Err(err) = self.call(argc, false) {
+      self.stack.truncate(height);
+      return Err(err);
+    }
+    self.run_frame(depth, height)
+  }
+}
+
+/// The method `obj` gives `IMPL` or `DEFINE_TRAIT`, which has to be a
+/// compiled function; the verifier doesn't track where values come from.
+fn method(obj: TiObj) -> Result<TiMethod, TiError> {
+  match &obj {
+    TiObj::Proto(proto, _) => Ok((proto.def.clone(), obj)),
+    _ => build_ti_error!(@err Runtime, "`{}` is not a function, only functions can be methods.", obj),
+  }
+}
@@ -1,233 +1,807 @@
-use std::rc::Rc;
+use std::{cell::RefCell, ops::ControlFlow, rc::Rc};
 
 use crate::{
     build_ti_error,
-    frontend::parser::ast::{AstBlock, AstExpr, AstNode},
+    error::error::{TiError, TiErrorKind},
+    frontend::{
+        lexer::token::TokenAt,
+        parser::ast::{AstBlock, AstExpr, AstNode, AstPattern, VarSlot},
+    },
 };
 
-use super::{environment::environment::TiEnvironment, types::object_type::TiObj};
+use super::{
+    builtins,
+    environment::environment::TiEnvironment,
+    pattern,
+    types::{
+        object_type::{TiBinaryOp, TiIter, TiObj, TiUnaryOp},
+        registry::TiTypeRegistry,
+    },
+};
+
+/// How deep calls may nest by default before a call raises an error
+/// instead of exhausting the native stack, which this vm recurses on. A
+/// call takes up to about 35KB of it in a debug build and 6KB in a release
+/// one, so this leaves room to spare on a stack of 8MB, the size of a main
+/// thread's.
+pub const DEFAULT_CALL_DEPTH: usize = if cfg!(debug_assertions) { 100 } else { 1_000 };
+
+/// Why an expr stopped before giving a value: an error, or a `return`,
+/// `break` or `continue` leaving every expr up to the function or the loop
+/// it belongs to.
+enum TiUnwind {
+    /// boxed, which keeps the results passed along every expr small
+    Error(Box<TiError>),
+    Return(TiObj),
+    Break(Option<Rc<String>>, TiObj),
+    Continue(Option<Rc<String>>),
+}
+
+impl From<TiError> for TiUnwind {
+    fn from(err: TiError) -> Self {
+        TiUnwind::Error(Box::new(err))
+    }
+}
+
+impl TiUnwind {
+    /// The error of an unwinding that reached the outside of every function
+    /// and loop, which the parser only lets an error do.
+    fn into_error(self) -> TiError {
+        match self {
+            TiUnwind::Error(err) => *err,
+            TiUnwind::Return(_) => TiError::new(
+                TiErrorKind::Runtime,
+                "`return` outside of a function.".to_string(),
+            ),
+            TiUnwind::Break(_, _) | TiUnwind::Continue(_) => TiError::new(
+                TiErrorKind::Runtime,
+                "`break` or `continue` outside of a loop.".to_string(),
+            ),
+        }
+    }
+
+    /// Points an error without a span at `at`, the statement it came from,
+    /// as the bytecode vm does.
+    fn or_at(self, at: TokenAt) -> Self {
+        match self {
+            TiUnwind::Error(mut err) if err.at.is_none() => {
+                err.at = Some(at);
+                TiUnwind::Error(err)
+            }
+            unwind => unwind,
+        }
+    }
+}
+
+/// Whether a `break` or `continue` naming `target` leaves the loop `label`.
+fn leaves(label: &Option<Rc<String>>, target: &Option<Rc<String>>) -> bool {
+    target.is_none() || target == label
+}
+
+/// What a loop does once its body gave `res`: `None` to go on, or the value
+/// or the unwinding it stops with.
+fn after_body(
+    label: &Option<Rc<String>>,
+    res: Result<TiObj, TiUnwind>,
+) -> Option<Result<TiObj, TiUnwind>> {
+    match res {
+        Ok(_) => None,
+        Err(TiUnwind::Break(target, value)) if leaves(label, &target) => Some(Ok(value)),
+        Err(TiUnwind::Continue(target)) if leaves(label, &target) => None,
+        Err(unwind) => Some(Err(unwind)),
+    }
+}
+
+/// A variable: its name, the slot the resolver found it at and its span.
+type Var<'e> = (&'e Rc<String>, &'e Option<VarSlot>, TokenAt);
+
+/// A step from a variable to the place an assignment changes: one of its
+/// fields, or its item at an index already worked out, with the span of the
+/// `[i]` for the errors it may raise.
+enum Step {
+    Field(Rc<String>),
+    Index(TiObj, TokenAt),
+}
 
 pub struct TiVM {
     pub environment: TiEnvironment,
+    pub types: Rc<RefCell<TiTypeRegistry>>,
+    /// how many calls deep the function this vm runs is nested
+    depth: usize,
+    /// how deep calls may nest, see `with_max_depth`
+    max_depth: usize,
+}
+
+impl Default for TiVM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TiVM {
     pub fn new() -> Self {
+        let mut environment = TiEnvironment::new();
+        builtins::register(&mut environment);
+        Self::with_environment(environment, Rc::new(RefCell::new(TiTypeRegistry::new())))
+    }
+    pub fn with_environment(
+        environment: TiEnvironment,
+        types: Rc<RefCell<TiTypeRegistry>>,
+    ) -> Self {
         Self {
-            environment: TiEnvironment::new(),
+            environment,
+            types,
+            depth: 0,
+            max_depth: DEFAULT_CALL_DEPTH,
         }
     }
-    pub fn with_environment(environment: TiEnvironment) -> Self {
-        Self { environment }
+
+    /// Lets calls nest `max_depth` deep, which the stack of the thread
+    /// running the vm has to have room for.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
     }
 }
 
 impl TiVM {
-    fn execute_block(&mut self, ast_block: AstBlock) -> TiObj {
+    fn execute_block(&mut self, ast_block: &AstBlock) -> Result<TiObj, TiUnwind> {
         self.environment.create_frame();
-        let mut res = TiObj::build_unit();
-        for stmt in ast_block.block {
-            res = self.execute_ast(stmt);
+        let mut res = Ok(TiObj::build_unit());
+        for (stmt, at) in ast_block.block.iter().zip(&ast_block.at) {
+            res = self.execute_ast(stmt).map_err(|unwind| unwind.or_at(*at));
+            if res.is_err() {
+                break;
+            }
         }
         self.environment.remove_frame();
         res
     }
 
-    fn execute_expr(&mut self, ast_expr: AstExpr) -> TiObj {
+    fn execute_expr(&mut self, ast_expr: &AstExpr) -> Result<TiObj, TiUnwind> {
+        // every arm needing more than a few locals runs in a method of its
+        // own, which keeps the frame of this one small, as a call recurses
+        // through it a few times
         match ast_expr {
-            AstExpr::Add(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.add(&rhs)
-            }
-            AstExpr::Sub(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.sub(&rhs)
-            }
-            AstExpr::Mul(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.mul(&rhs)
-            }
-            AstExpr::Div(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.div(&rhs)
-            }
-            AstExpr::AddEq(x, y) => {
-                todo!()
-            }
-            AstExpr::SubEq(x, y) => {
-                todo!()
-            }
-            AstExpr::MulEq(x, y) => {
-                todo!()
-            }
-            AstExpr::DivEq(x, y) => {
-                todo!()
-            }
-            AstExpr::Eq(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.eq(&rhs)
-            }
-            AstExpr::Neq(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.neq(&rhs)
-            }
-            AstExpr::Grt(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.grt(&rhs)
-            }
-            AstExpr::Les(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.les(&rhs)
-            }
-            AstExpr::Geq(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.geq(&rhs)
-            }
-            AstExpr::Leq(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.leq(&rhs)
-            }
-            AstExpr::And(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.and(&rhs)
-            }
-            AstExpr::Or(x, y) => {
-                let lhs = self.execute_expr(*x);
-                let rhs = self.execute_expr(*y);
-                lhs.or(&rhs)
-            }
-            AstExpr::Not(x) => {
-                let x = self.execute_expr(*x);
-                x.not()
-            }
-            AstExpr::Assign(n, v) => {
-                let v = self.execute_expr(*v);
-                self.environment.set_v(n, v.clone());
-                v
-            }
-            AstExpr::FnCall(mem, args) => {
-                let caller = self.execute_expr(*mem);
-                match caller {
-                    TiObj::Fn(fdef, fbody, environment) => {
-                        let mut vm = TiVM::with_environment(self.environment.clone());
-                        vm.environment.with(environment);
-                        let args: Vec<TiObj> = args
-                            .into_iter()
-                            .map(|expr| self.execute_expr(expr))
-                            .collect();
-                        for idx in 0..args.len() {
-                            vm.environment
-                                .set(fdef.arguments[idx].name.clone(), args[idx].clone());
-                        }
-                        vm.execute_block(fbody)
-                    }
-                    _ => {
-                        build_ti_error!(@err "type error: type `{:?}` is not callable.", caller.as_type())
-                    }
-                }
+            AstExpr::Add(x, y, at) => self.binary(x, y, TiObj::add, *at),
+            AstExpr::Sub(x, y, at) => self.binary(x, y, TiObj::sub, *at),
+            AstExpr::Mul(x, y, at) => self.binary(x, y, TiObj::mul, *at),
+            AstExpr::Div(x, y, at) => self.binary(x, y, TiObj::div, *at),
+            AstExpr::Mod(x, y, at) => self.binary(x, y, TiObj::r#mod, *at),
+            AstExpr::Pow(x, y, at) => self.binary(x, y, TiObj::pow, *at),
+            AstExpr::BitAnd(x, y, at) => self.binary(x, y, TiObj::bit_and, *at),
+            AstExpr::BitOr(x, y, at) => self.binary(x, y, TiObj::bit_or, *at),
+            AstExpr::BitXor(x, y, at) => self.binary(x, y, TiObj::bit_xor, *at),
+            AstExpr::Shl(x, y, at) => self.binary(x, y, TiObj::shl, *at),
+            AstExpr::Shr(x, y, at) => self.binary(x, y, TiObj::shr, *at),
+            AstExpr::AddEq(x, y, at) => self.compound(x, y, TiObj::add, *at),
+            AstExpr::SubEq(x, y, at) => self.compound(x, y, TiObj::sub, *at),
+            AstExpr::MulEq(x, y, at) => self.compound(x, y, TiObj::mul, *at),
+            AstExpr::DivEq(x, y, at) => self.compound(x, y, TiObj::div, *at),
+            AstExpr::ModEq(x, y, at) => self.compound(x, y, TiObj::r#mod, *at),
+            AstExpr::Eq(x, y, at) => self.binary(x, y, TiObj::eq, *at),
+            AstExpr::Neq(x, y, at) => self.binary(x, y, TiObj::neq, *at),
+            AstExpr::Grt(x, y, at) => self.binary(x, y, TiObj::grt, *at),
+            AstExpr::Les(x, y, at) => self.binary(x, y, TiObj::les, *at),
+            AstExpr::Geq(x, y, at) => self.binary(x, y, TiObj::geq, *at),
+            AstExpr::Leq(x, y, at) => self.binary(x, y, TiObj::leq, *at),
+            AstExpr::And(x, y, at) => self.logic(x, y, true, *at),
+            AstExpr::Or(x, y, at) => self.logic(x, y, false, *at),
+            AstExpr::Not(x, at) => self.unary(x, TiObj::not, *at),
+            AstExpr::Neg(x, at) => self.unary(x, TiObj::neg, *at),
+            AstExpr::Assign(n, slot, v, at) => self.assign((n, slot, *at), v),
+            AstExpr::AssignField(object, field, v) => self.assign_field(object, field, v),
+            AstExpr::AssignIndex(object, index, v, at) => self.assign_index(object, index, v, *at),
+            AstExpr::Field(object, field) => self.field(object, field),
+            AstExpr::Index(object, index, at) => self.index(object, index, *at),
+            AstExpr::Try(x) => self.try_branch(x),
+            AstExpr::FnCall(mem, args) => self.fn_call(mem, args),
+            AstExpr::Var(x, slot, at) => Ok(self.load((x, slot, *at))?),
+            AstExpr::Path(name, item) => Ok(self.path(name, item)?),
+            AstExpr::LiteralNum(x) => Ok(TiObj::Num(*x)),
+            AstExpr::LiteralStr(x) => Ok(TiObj::Str(x.clone())),
+            AstExpr::LiteralBool(x) => Ok(TiObj::Bool(*x)),
+            AstExpr::LiteralUnit(x) | AstExpr::LiteralArray(x) => self.literal_list(x),
+            AstExpr::LiteralMap(entries) => self.literal_map(entries),
+            AstExpr::LiteralStruct(name, fields) => self.literal_struct(name, fields),
+            AstExpr::IfElse(cond, true_case, false_case) => {
+                self.if_else(cond, true_case, false_case)
+            }
+            AstExpr::Block(block) => self.execute_block(block),
+            AstExpr::Lambda(fdef, fbody) => Ok(TiObj::Fn(
+                fdef.clone(),
+                fbody.clone(),
+                self.environment.capture(&fbody.captures),
+            )),
+            AstExpr::Match(expr, arms, _) => self.execute_match(expr, arms),
+            AstExpr::While(label, cond, body) => self.execute_while(label, cond, body),
+            AstExpr::For(label, name, expr, body) => self.execute_for(label, name, expr, body),
+            AstExpr::Range(start, end, step, inclusive) => {
+                self.range(start, end, step.as_deref(), *inclusive)
+            }
+            AstExpr::Return(value) => Err(TiUnwind::Return(self.execute_expr(value)?)),
+            AstExpr::Break(label, value) => {
+                Err(TiUnwind::Break(label.clone(), self.execute_expr(value)?))
             }
-            AstExpr::Var(x) => {
-                if let Some(res) = self.environment.get(&x) {
-                    res
-                } else {
-                    build_ti_error!(@err "reference error: name `{}` is not declared or has already moved.", x)
+            AstExpr::Continue(label) => Err(TiUnwind::Continue(label.clone())),
+        }
+    }
+
+    /// Runs `x && y` when `and`, `x || y` otherwise; `y` only runs when `x`
+    /// doesn't decide.
+    fn logic(
+        &mut self,
+        x: &AstExpr,
+        y: &AstExpr,
+        and: bool,
+        at: TokenAt,
+    ) -> Result<TiObj, TiUnwind> {
+        let res = if and {
+            self.cond(x, at)? && self.cond(y, at)?
+        } else {
+            self.cond(x, at)? || self.cond(y, at)?
+        };
+        Ok(TiObj::Bool(res))
+    }
+
+    /// Runs `op x`.
+    fn unary(&mut self, x: &AstExpr, op: TiUnaryOp, at: TokenAt) -> Result<TiObj, TiUnwind> {
+        let x = self.execute_expr(x)?;
+        Ok(op(x).map_err(|err| err.with_at(at))?)
+    }
+
+    fn assign(&mut self, var: Var, value: &AstExpr) -> Result<TiObj, TiUnwind> {
+        let value = self.execute_expr(value)?;
+        self.store(var, value.clone());
+        Ok(value)
+    }
+
+    fn assign_field(
+        &mut self,
+        object: &AstExpr,
+        field: &Rc<String>,
+        value: &AstExpr,
+    ) -> Result<TiObj, TiUnwind> {
+        let value = self.execute_expr(value)?;
+        let mut steps = Vec::new();
+        let var = self.place(object, &mut steps)?;
+        steps.push(Step::Field(field.clone()));
+        self.store_place(var, &steps, value.clone())?;
+        Ok(value)
+    }
+
+    fn assign_index(
+        &mut self,
+        object: &AstExpr,
+        index: &AstExpr,
+        value: &AstExpr,
+        at: TokenAt,
+    ) -> Result<TiObj, TiUnwind> {
+        let value = self.execute_expr(value)?;
+        let mut steps = Vec::new();
+        let var = self.place(object, &mut steps)?;
+        let index = self.execute_expr(index)?;
+        steps.push(Step::Index(index, at));
+        self.store_place(var, &steps, value.clone())?;
+        Ok(value)
+    }
+
+    fn field(&mut self, object: &AstExpr, field: &Rc<String>) -> Result<TiObj, TiUnwind> {
+        let object = self.execute_expr(object)?;
+        Ok(object.field(field).cloned()?)
+    }
+
+    fn index(&mut self, object: &AstExpr, index: &AstExpr, at: TokenAt) -> Result<TiObj, TiUnwind> {
+        let object = self.execute_expr(object)?;
+        let index = self.execute_expr(index)?;
+        Ok(object.index(&index).map_err(|err| err.with_at(at))?)
+    }
+
+    /// Runs `x?`.
+    fn try_branch(&mut self, x: &AstExpr) -> Result<TiObj, TiUnwind> {
+        match self.execute_expr(x)?.branch()? {
+            ControlFlow::Continue(value) => Ok(value),
+            ControlFlow::Break(value) => Err(TiUnwind::Return(value)),
+        }
+    }
+
+    /// Runs `mem(args)`, which also constructs an enum variant `Enum::Variant(args)`
+    /// and calls a method `object.name(args)`.
+    fn fn_call(&mut self, mem: &AstExpr, args: &[AstExpr]) -> Result<TiObj, TiUnwind> {
+        match mem {
+            AstExpr::Path(name, item) => {
+                let args = self.execute_args(args)?;
+                let is_variant = self
+                    .types
+                    .borrow()
+                    .get_enum(name)
+                    .is_some_and(|edef| edef.fields.iter().any(|field| field.name == *item));
+                if is_variant {
+                    return Ok(self.types.borrow().construct(name, item, args)?);
                 }
+                let caller = self.execute_expr(mem)?;
+                Ok(self.call(caller, args)?.0)
             }
-            AstExpr::LiteralNum(x) => TiObj::Num(x),
-            AstExpr::LiteralStr(x) => TiObj::Str(x),
-            AstExpr::LiteralUnit(x) => {
-                TiObj::List(x.into_iter().map(|expr| self.execute_expr(expr)).collect())
+            AstExpr::Field(object, name) => self.method_call(object, name, args),
+            mem => {
+                let caller = self.execute_expr(mem)?;
+                let args = self.execute_args(args)?;
+                Ok(self.call(caller, args)?.0)
             }
-            AstExpr::LiteralArray(x) => {
-                TiObj::List(x.into_iter().map(|expr| self.execute_expr(expr)).collect())
+        }
+    }
+
+    /// Runs `object.name(args)`, calling the method `name` of `object`, or
+    /// the function in its field `name`.
+    fn method_call(
+        &mut self,
+        object: &AstExpr,
+        name: &Rc<String>,
+        args: &[AstExpr],
+    ) -> Result<TiObj, TiUnwind> {
+        let mut steps = Vec::new();
+        let place = match object.is_place() {
+            true => Some(self.place(object, &mut steps)?),
+            false => None,
+        };
+        let receiver = match place {
+            Some(var) => self.load_place(var, &steps)?,
+            None => self.execute_expr(object)?,
+        };
+        let method = self.types.borrow().method(&receiver, name);
+        match method {
+            Some(method) if method.is_method() => {
+                let mut args = self.execute_args(args)?;
+                args.insert(0, receiver);
+                let (res, receiver) = self.call(method, args)?;
+                // hand a `self` modified by the method back to the caller
+                if let (Some(var), Some(receiver)) = (place, receiver) {
+                    self.store_place(var, &steps, receiver)?;
+                }
+                Ok(res)
             }
-            AstExpr::Neg(x) => {
-                let res = self.execute_expr(*x);
-                res.neg()
+            Some(_) => {
+                build_ti_error!(@err Type, "`{}` is an associated function, not a method, call it as `{}::{}(..)`.", name, receiver.type_name(), name)
             }
-            AstExpr::IfElse(cond, true_case, false_case) => {
-                let cond = self.execute_expr(*cond);
-                let cond = match &cond {
-                    TiObj::Bool(x) => *x,
-                    _ => {
-                        build_ti_error!(@err "type error: type `{:?}` cannot be hidden cast where there's an if expression.", cond.as_type())
+            None => {
+                let caller = match receiver.field(name) {
+                    Ok(caller) => caller.clone(),
+                    Err(_) => {
+                        build_ti_error!(@err Reference, "no method named `{}` found for type `{}`.", name, receiver.type_name())
                     }
                 };
-                if cond {
-                    self.execute_block(true_case)
-                } else {
-                    self.execute_block(false_case)
+                let args = self.execute_args(args)?;
+                Ok(self.call(caller, args)?.0)
+            }
+        }
+    }
+
+    /// The value of `name::item`, a variant without values or an associated
+    /// item.
+    fn path(&self, name: &Rc<String>, item: &Rc<String>) -> Result<TiObj, TiError> {
+        let types = self.types.borrow();
+        if types.get_enum(name).is_some() {
+            types.construct(name, item, Vec::new())
+        } else if let Some(res) = types.associated(name, item) {
+            Ok(res)
+        } else {
+            build_ti_error!(@err Reference, "no variant or associated item named `{}` found for `{}`.", item, name)
+        }
+    }
+
+    fn literal_list(&mut self, items: &[AstExpr]) -> Result<TiObj, TiUnwind> {
+        Ok(TiObj::List(self.execute_args(items)?))
+    }
+
+    fn literal_map(&mut self, entries: &[(AstExpr, AstExpr, TokenAt)]) -> Result<TiObj, TiUnwind> {
+        let mut map = TiObj::map(Vec::new())?;
+        for (key, value, at) in entries {
+            let key = self.execute_expr(key)?;
+            let value = self.execute_expr(value)?;
+            map.set_index(key, value).map_err(|err| err.with_at(*at))?;
+        }
+        Ok(map)
+    }
+
+    fn literal_struct(
+        &mut self,
+        name: &Rc<String>,
+        fields: &[(Rc<String>, AstExpr)],
+    ) -> Result<TiObj, TiUnwind> {
+        let fields = fields
+            .iter()
+            .map(|(fname, expr)| Ok((fname.clone(), self.execute_expr(expr)?)))
+            .collect::<Result<Vec<_>, TiUnwind>>()?;
+        Ok(self.types.borrow().instantiate(name, fields)?)
+    }
+
+    fn if_else(
+        &mut self,
+        cond: &AstExpr,
+        true_case: &AstBlock,
+        false_case: &AstBlock,
+    ) -> Result<TiObj, TiUnwind> {
+        if self.execute_expr(cond)?.into_cond()? {
+            self.execute_block(true_case)
+        } else {
+            self.execute_block(false_case)
+        }
+    }
+
+    fn execute_match(
+        &mut self,
+        expr: &AstExpr,
+        arms: &[(AstPattern, AstExpr)],
+    ) -> Result<TiObj, TiUnwind> {
+        let value = self.execute_expr(expr)?;
+        for (pattern, body) in arms {
+            let mut bindings = Vec::new();
+            if pattern::match_pattern(pattern, &value, &mut bindings) {
+                self.environment.create_frame();
+                for (name, value) in bindings {
+                    self.environment.declare(name, value);
                 }
+                let res = self.execute_expr(body);
+                self.environment.remove_frame();
+                return res;
             }
-            AstExpr::Block(block) => self.execute_block(block),
-            AstExpr::While(cond, body) => {
-                while {
-                    let cond = self.execute_expr(*cond.clone());
-                    let cond = match &cond {
-                        TiObj::Bool(x) => *x,
-                        _ => {
-                            build_ti_error!(@err "type error: type `{:?}` cannot be hidden cast where there's an if expression.", cond.as_type())
-                        }
-                    };
-                    cond
-                } {
-                    self.execute_block(body.clone());
+        }
+        build_ti_error!(@err Runtime, "no arm of the match accepts the value `{}`.", value)
+    }
+
+    fn execute_while(
+        &mut self,
+        label: &Option<Rc<String>>,
+        cond: &AstExpr,
+        body: &AstBlock,
+    ) -> Result<TiObj, TiUnwind> {
+        loop {
+            if !self.execute_expr(cond)?.into_cond()? {
+                break Ok(TiObj::build_unit());
+            }
+            let res = self.execute_block(body);
+            if let Some(res) = after_body(label, res) {
+                break res;
+            }
+        }
+    }
+
+    fn execute_for(
+        &mut self,
+        label: &Option<Rc<String>>,
+        name: &Rc<String>,
+        expr: &AstExpr,
+        body: &AstBlock,
+    ) -> Result<TiObj, TiUnwind> {
+        let value = self.execute_expr(expr)?;
+        let mut iter = self.iter(value)?;
+        loop {
+            let value = match self.next(&mut iter)? {
+                Some(value) => value,
+                None => break Ok(TiObj::build_unit()),
+            };
+            self.environment.create_frame();
+            self.environment.declare(name.clone(), value);
+            let res = self.execute_block(body);
+            self.environment.remove_frame();
+            if let Some(res) = after_body(label, res) {
+                break res;
+            }
+        }
+    }
+
+    fn range(
+        &mut self,
+        start: &AstExpr,
+        end: &AstExpr,
+        step: Option<&AstExpr>,
+        inclusive: bool,
+    ) -> Result<TiObj, TiUnwind> {
+        let start = self.execute_expr(start)?;
+        let end = self.execute_expr(end)?;
+        let step = match step {
+            Some(step) => self.execute_expr(step)?,
+            None => TiObj::Num(1.0),
+        };
+        Ok(TiObj::range(start, end, step, inclusive)?)
+    }
+
+    fn execute_args(&mut self, args: &[AstExpr]) -> Result<Vec<TiObj>, TiUnwind> {
+        args.iter().map(|expr| self.execute_expr(expr)).collect()
+    }
+
+    /// Calls `caller` with `args`, also returning the final value of its first
+    /// parameter, through which a method hands back the `self` it modified.
+    fn call(&mut self, caller: TiObj, args: Vec<TiObj>) -> Result<(TiObj, Option<TiObj>), TiError> {
+        match caller {
+            TiObj::Fn(fdef, fbody, environment) => {
+                if fdef.arguments.len() != args.len() {
+                    build_ti_error!(@err Type, "function `{}` takes {} arguments but {} were given.", fdef.name, fdef.arguments.len(), args.len())
+                }
+                if self.depth >= self.max_depth {
+                    build_ti_error!(@err Runtime, "stack overflow, calls are nested more than {} deep.", self.max_depth)
                 }
-                TiObj::build_unit()
+                let mut vm = TiVM::with_environment(environment.child(), self.types.clone())
+                    .with_max_depth(self.max_depth);
+                vm.depth = self.depth + 1;
+                for (farg, arg) in fdef.arguments.iter().zip(args) {
+                    vm.environment.declare(farg.name.clone(), arg);
+                }
+                let res = match vm.execute_block(&fbody) {
+                    Ok(res) | Err(TiUnwind::Return(res)) => res,
+                    Err(unwind) => return Err(unwind.into_error()),
+                };
+                let first = vm.environment.get_at(0, 0);
+                Ok((res, first))
+            }
+            TiObj::Builtin(_, f) => Ok((f(args)?, None)),
+            _ => {
+                build_ti_error!(@err Type, "type `{:?}` is not callable.", caller.as_type())
             }
         }
     }
 
-    fn execute_ast(&mut self, ast_node: AstNode) -> TiObj {
+    /// Starts a `for` loop over `value`. A struct or an enum with an `iter`
+    /// method is walked through what that returns.
+    fn iter(&mut self, value: TiObj) -> Result<TiIter, TiError> {
+        let method = self.types.borrow().protocol(&value, "iter");
+        match method {
+            Some(method) => self.call(method, vec![value])?.0.iter(),
+            None => value.iter(),
+        }
+    }
+
+    /// The next value of the `for` loop walking `iter`, `None` once it's
+    /// done; a user type gives `x` while its `next` method returns `Some(x)`,
+    /// and is done once it returns `None`.
+    fn next(&mut self, iter: &mut TiIter) -> Result<Option<TiObj>, TiError> {
+        let obj = match iter {
+            TiIter::Method(obj) => obj,
+            iter => return Ok(iter.next()),
+        };
+        let method = self.types.borrow().protocol(obj, "next");
+        let method = match method {
+            Some(method) => method,
+            None => {
+                build_ti_error!(@err Type, "type `{}` cannot be iterated, it has no `iter` or `next` method.", obj.type_name())
+            }
+        };
+        let (res, receiver) = self.call(method, vec![obj.clone()])?;
+        if let Some(receiver) = receiver {
+            *obj = receiver;
+        }
+        res.into_next()
+    }
+
+    /// Runs `x op y`.
+    fn binary(
+        &mut self,
+        x: &AstExpr,
+        y: &AstExpr,
+        op: TiBinaryOp,
+        at: TokenAt,
+    ) -> Result<TiObj, TiUnwind> {
+        let lhs = self.execute_expr(x)?;
+        let rhs = self.execute_expr(y)?;
+        Ok(op(lhs, &rhs).map_err(|err| err.with_at(at))?)
+    }
+
+    /// Runs `expr` as an operand of the `&&` or `||` at `at`, which has to
+    /// be a bool.
+    fn cond(&mut self, expr: &AstExpr, at: TokenAt) -> Result<bool, TiUnwind> {
+        let cond = self.execute_expr(expr)?;
+        Ok(cond.into_cond().map_err(|err| err.with_at(at))?)
+    }
+
+    /// Runs `target op= value`, storing the result into `target`, a variable
+    /// or a field or an item of one, whose indexes run once.
+    fn compound(
+        &mut self,
+        target: &AstExpr,
+        value: &AstExpr,
+        op: TiBinaryOp,
+        at: TokenAt,
+    ) -> Result<TiObj, TiUnwind> {
+        let mut steps = Vec::new();
+        let var = self.place(target, &mut steps)?;
+        let lhs = self.load_place(var, &steps)?;
+        let rhs = self.execute_expr(value)?;
+        let res = op(lhs, &rhs).map_err(|err| err.with_at(at))?;
+        self.store_place(var, &steps, res.clone())?;
+        Ok(res)
+    }
+
+    /// Works out the place `expr`, a variable or a chain of fields and items
+    /// on one: the variable, with each step from it pushed onto `steps` and
+    /// each index run in turn.
+    fn place<'e>(&mut self, expr: &'e AstExpr, steps: &mut Vec<Step>) -> Result<Var<'e>, TiUnwind> {
+        match expr {
+            AstExpr::Var(name, slot, at) => Ok((name, slot, *at)),
+            AstExpr::Field(object, field) => {
+                let root = self.place(object, steps)?;
+                steps.push(Step::Field(field.clone()));
+                Ok(root)
+            }
+            AstExpr::Index(object, index, at) => {
+                let root = self.place(object, steps)?;
+                let index = self.execute_expr(index)?;
+                steps.push(Step::Index(index, *at));
+                Ok(root)
+            }
+            _ => build_ti_error!(@err Runtime, "invalid left-hand side of assignment."),
+        }
+    }
+
+    /// The value at the end of `steps` from the variable `var`.
+    fn load_place(&self, var: Var, steps: &[Step]) -> Result<TiObj, TiError> {
+        let mut value = self.load(var)?;
+        for step in steps {
+            value = match step {
+                Step::Field(field) => value.field(field).cloned()?,
+                Step::Index(index, at) => value.index(index).map_err(|err| err.with_at(*at))?,
+            };
+        }
+        Ok(value)
+    }
+
+    /// Stores `value` at the end of `steps` from the variable `var`; the
+    /// variable is updated in place.
+    fn store_place(&mut self, var: Var, steps: &[Step], value: TiObj) -> Result<(), TiError> {
+        let (last, path) = match steps.split_last() {
+            Some(split) => split,
+            None => {
+                self.store(var, value);
+                return Ok(());
+            }
+        };
+        let mut root = self.load(var)?;
+        let mut target = &mut root;
+        for step in path {
+            target = match step {
+                Step::Field(field) => target.field_mut(field)?,
+                Step::Index(index, at) => {
+                    target.index_mut(index).map_err(|err| err.with_at(*at))?
+                }
+            };
+        }
+        match last {
+            Step::Field(field) => {
+                if let TiObj::Struct(sname, _) = target {
+                    self.types.borrow().check_assign(sname, field, &value)?;
+                }
+                *target.field_mut(field)? = value;
+            }
+            Step::Index(index, at) => target
+                .set_index(index.clone(), value)
+                .map_err(|err| err.with_at(*at))?,
+        }
+        self.store(var, root);
+        Ok(())
+    }
+
+    /// The value of the variable `name`, which the resolver found at `slot`.
+    fn load(&self, (name, slot, at): Var) -> Result<TiObj, TiError> {
+        let res = match slot {
+            Some(VarSlot { depth, slot }) => self.environment.get_at(*depth, *slot),
+            None => self.environment.get(name),
+        };
+        match res {
+            Some(res) => Ok(res),
+            None => Err(TiError::new(
+                TiErrorKind::Reference,
+                format!("name `{}` is not declared or has already moved.", name),
+            )
+            .with_at(at)),
+        }
+    }
+
+    fn store(&mut self, (name, slot, _): Var, value: TiObj) {
+        match slot {
+            Some(VarSlot { depth, slot }) => self.environment.set_at(*depth, *slot, value),
+            None => self.environment.set_v(name.clone(), value),
+        }
+    }
+
+    fn execute_ast(&mut self, ast_node: &AstNode) -> Result<TiObj, TiUnwind> {
         match ast_node {
             AstNode::Program(program) => {
-                for stmt in program.program {
-                    self.execute_ast(stmt);
+                let mut stmts: Vec<_> = program.program.iter().zip(&program.at).collect();
+                stmts.sort_by_key(|(stmt, _)| stmt.hoisting());
+                for (stmt, at) in stmts {
+                    self.execute_ast(stmt).map_err(|unwind| unwind.or_at(*at))?;
                 }
-                TiObj::build_unit()
+                Ok(TiObj::build_unit())
             }
             AstNode::Fn(fdef, fbody) => {
                 let name = fdef.name.clone();
-                let ti_fn = TiObj::Fn(fdef, fbody, self.environment.clone());
-                self.environment.set(name, ti_fn.clone());
-                ti_fn
+                let ti_fn = TiObj::Fn(
+                    fdef.clone(),
+                    Rc::new(fbody.clone()),
+                    self.environment.capture(&fbody.captures),
+                );
+                self.environment.declare(name, ti_fn.clone());
+                Ok(ti_fn)
+            }
+            AstNode::Impl(sname, tname, items) => {
+                let fns = items
+                    .iter()
+                    .filter_map(|item| match item {
+                        AstNode::Fn(fdef, fbody) => Some((
+                            fdef.clone(),
+                            TiObj::Fn(
+                                fdef.clone(),
+                                Rc::new(fbody.clone()),
+                                self.environment.capture(&fbody.captures),
+                            ),
+                        )),
+                        _ => None,
+                    })
+                    .collect();
+                self.types
+                    .borrow_mut()
+                    .implement(sname, tname.as_ref(), fns)?;
+                Ok(TiObj::build_unit())
+            }
+            AstNode::Trait(tdef) => {
+                let defaults = tdef
+                    .fields
+                    .iter()
+                    .filter_map(|field| {
+                        let fbody = field.default.clone()?;
+                        let environment = self.environment.capture(&fbody.captures);
+                        Some((
+                            field.body.clone(),
+                            TiObj::Fn(field.body.clone(), Rc::new(fbody), environment),
+                        ))
+                    })
+                    .collect();
+                self.types.borrow_mut().define_trait(tdef.clone(), defaults);
+                Ok(TiObj::build_unit())
+            }
+            AstNode::Struct(sdef) => {
+                self.types.borrow_mut().define_struct(sdef.clone());
+                Ok(TiObj::build_unit())
+            }
+            AstNode::Enum(edef) => {
+                self.types.borrow_mut().define_enum(edef.clone());
+                Ok(TiObj::build_unit())
             }
-            AstNode::Impl(_, _) => unreachable!(),
             AstNode::Let(name, value) => {
                 let res = match value {
-                    Some(v) => self.execute_expr(v),
+                    Some(v) => self.execute_expr(v)?,
                     None => TiObj::build_unit(),
                 };
-                self.environment.set(name, res);
-                TiObj::build_unit()
+                self.environment.declare(name.clone(), res);
+                Ok(TiObj::build_unit())
             }
             AstNode::Expr(expr) => self.execute_expr(expr),
-            AstNode::Empty => unreachable!(),
+            AstNode::Empty => Ok(TiObj::build_unit()),
         }
     }
 
-    pub fn execute(&mut self, ast_node: AstNode) -> TiObj {
-        self.execute_ast(ast_node)
+    pub fn execute(&mut self, ast_node: &AstNode) -> Result<TiObj, TiError> {
+        self.execute_ast(ast_node).map_err(TiUnwind::into_error)
     }
 
-    pub fn run_fn(&mut self, entry: &str) -> TiObj {
-        if let Some(TiObj::Fn(_, entry_body, _)) =
-            self.environment.move_out(&Rc::new(entry.to_string()))
+    pub fn run_fn(&mut self, entry: &str, args: Vec<TiObj>) -> Result<TiObj, TiError> {
+        if let Some(TiObj::Fn(fdef, entry_body, _)) =
+            self.environment.get(&Rc::new(entry.to_string()))
         {
-            self.execute_block(entry_body)
+            if fdef.arguments.len() != args.len() {
+                build_ti_error!(@err Runtime, "entry function `{}` takes {} arguments but {} were given.", entry, fdef.arguments.len(), args.len())
+            }
+            self.environment.create_frame();
+            for (farg, arg) in fdef.arguments.iter().zip(args) {
+                self.environment.declare(farg.name.clone(), arg);
+            }
+            let res = match self.execute_block(&entry_body) {
+                Ok(res) | Err(TiUnwind::Return(res)) => Ok(res),
+                Err(unwind) => Err(unwind.into_error()),
+            };
+            self.environment.remove_frame();
+            res
         } else {
-            build_ti_error!(@err "runtime error: no entry.")
+            build_ti_error!(@err Runtime, "no entry function `{}`.", entry)
         }
     }
 }
@@ -0,0 +1,150 @@
+//! Runs every program in `examples/`, and every failing one in
+//! `tests/errors/`, checking what it prints against the `.out` file next to
+//! it, then each other way `ti` can run it, checking they all print the
+//! same, errors included, and exit with the same code.
+//!
+//! Run with `TI_BLESS=1` to write the `.out` files from the output instead.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const TI: &str = env!("CARGO_BIN_EXE_ti");
+
+/// Runs `ti` in the directory of the crate, so the paths in its errors
+/// are the relative ones it was given.
+fn ti(args: &[&str]) -> Output {
+    Command::new(TI)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg(args[0])
+        .arg("--no-color")
+        .args(&args[1..])
+        .output()
+        .expect("failed to run ti")
+}
+
+/// The `.ti` files in `dir`, a directory of the crate, relative to it.
+fn programs(dir: &str) -> Vec<PathBuf> {
+    let mut programs: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(dir))
+        .unwrap()
+        .map(|entry| Path::new(dir).join(entry.unwrap().file_name()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ti"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs found in `{}`", dir);
+    programs
+}
+
+/// What a run printed as a `.out` file holds it: its stdout, then its
+/// stderr and the code it exited with, when there are any.
+fn transcript(output: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.stderr.is_empty() {
+        text += "--- stderr\n";
+        text += &String::from_utf8_lossy(&output.stderr);
+    }
+    match output.status.code() {
+        Some(0) => {}
+        Some(code) => text += &format!("--- exit {}\n", code),
+        None => text += "--- killed\n",
+    }
+    text
+}
+
+fn examples() -> Vec<PathBuf> {
+    let mut examples = programs("examples");
+    examples.extend(programs("tests/errors"));
+    examples
+}
+
+/// Checks `output` of running `example` the way `mode` does against the
+/// run on the ast vm.
+fn assert_same(example: &Path, mode: &str, expected: &Output, output: &Output) {
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&expected.stdout),
+        "stdout of `{}` differs with {}",
+        example.display(),
+        mode,
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        String::from_utf8_lossy(&expected.stderr),
+        "stderr of `{}` differs with {}",
+        example.display(),
+        mode,
+    );
+    assert_eq!(
+        output.status.code(),
+        expected.status.code(),
+        "exit code of `{}` differs with {}",
+        example.display(),
+        mode,
+    );
+}
+
+#[test]
+fn ast_vm_prints_what_the_out_files_hold() {
+    let bless = env::var_os("TI_BLESS").is_some();
+    for example in examples() {
+        let text = transcript(&ti(&["run", example.to_str().unwrap()]));
+        let out = Path::new(env!("CARGO_MANIFEST_DIR")).join(example.with_extension("out"));
+        if bless {
+            fs::write(&out, text).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&out)
+            .unwrap_or_else(|err| panic!("cannot read `{}`: {}", out.display(), err));
+        assert_eq!(
+            text,
+            expected,
+            "`{}` does not print what `{}` holds",
+            example.display(),
+            out.display()
+        );
+    }
+}
+
+#[test]
+fn bytecode_and_optimized_runs_match_the_ast_vm() {
+    for example in examples() {
+        let file = example.to_str().unwrap();
+        let expected = ti(&["run", file]);
+        assert!(
+            expected.status.code().is_some(),
+            "`{}` was killed",
+            example.display()
+        );
+        for mode in ["--bytecode", "-O1", "-O2"] {
+            assert_same(&example, mode, &expected, &ti(&["run", mode, file]));
+        }
+    }
+}
+
+#[test]
+fn compiled_tic_files_match_the_ast_vm() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("examples");
+    fs::create_dir_all(&dir).unwrap();
+    for example in examples() {
+        let file = example.to_str().unwrap();
+        let expected = ti(&["run", file]);
+        for level in ["-O0", "-O2"] {
+            let tic = dir
+                .join(example.file_stem().unwrap())
+                .with_extension(format!("{}.tic", &level[1..]));
+            let tic = tic.to_str().unwrap();
+            let compiled = ti(&["compile", level, file, "-o", tic]);
+            assert!(
+                compiled.status.success(),
+                "`{}` failed to compile with {}:\n{}",
+                example.display(),
+                level,
+                String::from_utf8_lossy(&compiled.stderr),
+            );
+            let mode = format!("`compile {}` and `run {}`", level, tic);
+            assert_same(&example, &mode, &expected, &ti(&["run", tic]));
+        }
+    }
+}
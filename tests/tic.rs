@@ -0,0 +1,64 @@
+//! Runs `.tic` files that got past the loader and the verifier but are
+//! still malformed, which have to fail with an error rather than a panic.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+const TI: &str = env!("CARGO_BIN_EXE_ti");
+
+fn ti(args: &[&str]) -> Output {
+    Command::new(TI)
+        .arg(args[0])
+        .arg("--no-color")
+        .args(&args[1..])
+        .output()
+        .expect("failed to run ti")
+}
+
+/// Compiles the example `name` and hands the bytes of the `.tic` file to
+/// `patch`, returning the path of the patched file.
+fn patched(name: &str, patch: impl FnOnce(&mut Vec<u8>)) -> String {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("tic");
+    fs::create_dir_all(&dir).unwrap();
+    let example = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join(name)
+        .with_extension("ti");
+    let tic = dir.join(name).with_extension("tic");
+    let tic = tic.to_str().unwrap().to_string();
+    let compiled = ti(&["compile", example.to_str().unwrap(), "-o", &tic]);
+    assert!(compiled.status.success(), "`{}` failed to compile", name);
+    let mut bytes = fs::read(&tic).unwrap();
+    patch(&mut bytes);
+    fs::write(&tic, bytes).unwrap();
+    tic
+}
+
+/// Replaces the one place `from` is found in `bytes` with `to`.
+fn replace(bytes: &mut [u8], from: &[u8], to: &[u8]) {
+    let found: Vec<usize> = (0..=bytes.len() - from.len())
+        .filter(|&idx| &bytes[idx..idx + from.len()] == from)
+        .collect();
+    assert_eq!(found.len(), 1, "{:?} is not found exactly once", from);
+    bytes[found[0]..found[0] + to.len()].copy_from_slice(to);
+}
+
+#[test]
+fn methods_that_are_not_functions_fail_at_runtime() {
+    // `BUILD_FUNC 9`, the method `area` of `impl Shape for Rect`, becomes
+    // `BUILD_LIST 0`, which leaves the stack as it was
+    let tic = patched("traits", |bytes| {
+        replace(bytes, &[2, 0, 9, 0], &[29, 0, 0, 0])
+    });
+    let output = ti(&["run", &tic]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.starts_with("runtime error: `[]` is not a function"),
+        "{}",
+        stderr
+    );
+}